@@ -255,17 +255,9 @@ export class Slab {
   // getMinMax(max: boolean) {
   //   let pointer;
   //   if (max) {
-  //     pointer = find_max(
-  //       this.data,
-  //       BigInt(this.callBackInfoLen),
-  //       BigInt(this.slotSize)
-  //     );
+  //     pointer = find_max(this.data, BigInt(this.callBackInfoLen));
   //   } else {
-  //     pointer = find_min(
-  //       this.data,
-  //       BigInt(this.callBackInfoLen),
-  //       BigInt(this.slotSize)
-  //     );
+  //     pointer = find_min(this.data, BigInt(this.callBackInfoLen));
   //   }
   //   let offset = SlabHeader.LEN;
   //   if (!pointer) {
@@ -324,7 +316,6 @@ export class Slab {
   //   let raw = find_l2_depth(
   //     this.data,
   //     BigInt(this.callBackInfoLen),
-  //     BigInt(this.slotSize),
   //     BigInt(depth),
   //     increasing
   //   );
@@ -1,5 +1,4 @@
 use critbit::Slab;
-use std::{cell::RefCell, rc::Rc};
 
 mod critbit;
 
@@ -15,37 +14,45 @@ mod tests {
 }
 
 #[wasm_bindgen]
-pub fn find_max(data: &mut [u8], callback_info_len: u64, slot_size: u64) -> Option<u32> {
-    let slab = Slab::new(
-        Rc::new(RefCell::new(data)),
-        callback_info_len as usize,
-        slot_size as usize,
-    );
-    slab.find_max()
+pub fn find_max(data: &[u8], callback_info_len: u64) -> Option<u32> {
+    Slab::new(data, callback_info_len as usize)?.find_max()
 }
 
 #[wasm_bindgen]
-pub fn find_min(data: &mut [u8], callback_info_len: u64, slot_size: u64) -> Option<u32> {
-    let slab = Slab::new(
-        Rc::new(RefCell::new(data)),
-        callback_info_len as usize,
-        slot_size as usize,
-    );
-    slab.find_min()
+pub fn find_min(data: &[u8], callback_info_len: u64) -> Option<u32> {
+    Slab::new(data, callback_info_len as usize)?.find_min()
 }
 
 #[wasm_bindgen]
 pub fn find_l2_depth(
-    data: &mut [u8],
+    data: &[u8],
     callback_info_len: u64,
-    slot_size: u64,
     depth: u64,
     increasing: bool,
 ) -> Vec<u64> {
-    let slab = Slab::new(
-        Rc::new(RefCell::new(data)),
-        callback_info_len as usize,
-        slot_size as usize,
-    );
-    slab.find_l2_depth(depth as usize, increasing)
+    match Slab::new(data, callback_info_len as usize) {
+        Some(slab) => slab.find_l2_depth(depth as usize, increasing),
+        None => vec![],
+    }
+}
+
+/// Serializes up to `depth` orders as consecutive `(order_id: u128, price: u64, base_quantity: u64, callback_info)` entries,
+/// with little-endian integers and `callback_info_len` bytes of callback info.
+///
+/// Only the displayed quantity of each order is serialized, so that the hidden quantity of iceberg orders isn't revealed.
+#[wasm_bindgen]
+pub fn find_l3_depth(data: &[u8], callback_info_len: u64, depth: u64, increasing: bool) -> Vec<u8> {
+    let slab = match Slab::new(data, callback_info_len as usize) {
+        Some(slab) => slab,
+        None => return vec![],
+    };
+    let leaves = slab.find_l3_depth(depth as usize, increasing);
+    let mut result = Vec::with_capacity(leaves.len() * (32 + callback_info_len as usize));
+    for (leaf, callback_info) in leaves {
+        result.extend_from_slice(&leaf.order_id().to_le_bytes());
+        result.extend_from_slice(&leaf.price().to_le_bytes());
+        result.extend_from_slice(&leaf.displayed_quantity().to_le_bytes());
+        result.extend_from_slice(callback_info);
+    }
+    result
 }
@@ -1,5 +1,4 @@
 use crate::{error::AoError, processor};
-use borsh::BorshSerialize;
 use num_traits::FromPrimitive;
 use solana_program::{
     account_info::AccountInfo, decode_error::DecodeError, entrypoint::ProgramResult, msg,
@@ -25,10 +24,18 @@ pub fn process_instruction(
             error.print::<AoError>();
             return Err(error);
         }
-        Ok(r) => {
-            let mut a: &mut [u8] = &mut register_account.data.borrow_mut();
-            r.serialize(&mut a).unwrap();
+        Ok(Some(r)) => {
+            let mut register = register_account.data.borrow_mut();
+            if register.len() < r.len() {
+                msg!(
+                    "The register account should be at least {} bytes long",
+                    r.len()
+                );
+                return Err(AoError::RegisterTooSmall.into());
+            }
+            register[..r.len()].copy_from_slice(&r);
         }
+        Ok(None) => {}
     }
     Ok(())
 }
@@ -58,6 +58,42 @@ pub enum AoError {
     InvalidCallbackInfo,
     #[error("Account tag mismatch")]
     AccountTagMismatch,
+    #[error("An order with the same order id already exists")]
+    DuplicateOrderId,
+    #[error("Limit price exceeds the market's maximum price")]
+    LimitPriceTooHigh,
+    #[error("The callback info size doesn't match the one the event queue was created with")]
+    CallbackSizeMismatch,
+    #[error("The order would not post at the top of the book")]
+    WouldNotImprove,
+    #[error("The order would post beyond the market's maximum depth")]
+    TooDeep,
+    #[error("The market's accounts are corrupted")]
+    MarketCorrupted,
+    #[error("The register account is too small to hold the instruction's output")]
+    RegisterTooSmall,
+    #[error("The taker's limit price doesn't cross the maker order's price")]
+    OrderDoesNotCross,
+    #[error("The callback info size exceeds the maximum supported size")]
+    CallbackInfoTooLarge,
+    #[error("The order's base quantity doesn't match the expected one")]
+    OrderChanged,
+    #[error("The market's reference price is unset or stale")]
+    StaleReferencePrice,
+    #[error("The fill or kill order can't be entirely filled")]
+    FillOrKillUnfulfilled,
+    #[error("The slab account's layout version isn't supported")]
+    SlabVersionMismatch,
+    #[error("An order's quantity can only be decreased in place")]
+    OrderSizeIncrease,
+    #[error("The order's minimum fill quantity can't be matched")]
+    MinFillNotMet,
+    #[error("An all-or-nothing order can't be partially filled")]
+    PartialAllOrNothingFill,
+    #[error("The event queue account's layout version isn't supported")]
+    EventQueueVersionMismatch,
+    #[error("The market account must be migrated to the current market state layout")]
+    MarketStateVersionMismatch,
 }
 
 impl From<AoError> for ProgramError {
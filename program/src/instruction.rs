@@ -8,7 +8,9 @@ use solana_program::{
 use bonfida_utils::{BorshSize, InstructionsAccount};
 
 pub use crate::processor::{
-    cancel_order, close_market, consume_events, create_market, mass_cancel_orders, new_order,
+    cancel_order, cancel_session_orders, close_market, consume_events, consume_then_new_order,
+    create_market, grow_slab, mass_cancel_orders, migrate_market, modify_order, new_order,
+    new_order_batch, prune_orders, replace_order, set_reference_price, take_order, verify_market,
 };
 #[derive(BorshDeserialize, BorshSerialize, FromPrimitive)]
 /// Describes all possible instructions and their required accounts
@@ -88,6 +90,125 @@ pub enum AgnosticOrderbookInstruction {
     /// | 3     | ✅       | ❌     | The asks account        |
     /// | 4     | ❌       | ✅     | The caller authority    |
     MassCancelOrders,
+    /// Execute a batch of new orders on the orderbook.
+    ///
+    /// The orders are executed sequentially, and their [`OrderSummary`][`crate::state::OrderSummary`] objects
+    /// are written back into the register as a vector.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description             |
+    /// |-------|----------|--------|-------------------------|
+    /// | 0     | ✅       | ❌     | The market account      |
+    /// | 1     | ✅       | ❌     | The event queue account |
+    /// | 2     | ✅       | ❌     | The bids account        |
+    /// | 3     | ✅       | ❌     | The asks account        |
+    NewOrderBatch,
+    /// Start a new trading session and remove the orders which were posted during previous sessions.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description             |
+    /// |-------|----------|--------|-------------------------|
+    /// | 0     | ✅       | ❌     | The market account      |
+    /// | 1     | ✅       | ❌     | The event queue account |
+    /// | 2     | ✅       | ❌     | The bids account        |
+    /// | 3     | ✅       | ❌     | The asks account        |
+    CancelSessionOrders,
+    /// Remove a bounded number of orders from the orderbook, optionally without pushing out events.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description             |
+    /// |-------|----------|--------|-------------------------|
+    /// | 0     | ❌       | ❌     | The market account      |
+    /// | 1     | ✅       | ❌     | The event queue account |
+    /// | 2     | ✅       | ❌     | The bids account        |
+    /// | 3     | ✅       | ❌     | The asks account        |
+    PruneOrders,
+    /// Pop a series of events off the event queue, then execute a new order on the orderbook.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description             |
+    /// |-------|----------|--------|-------------------------|
+    /// | 0     | ❌       | ❌     | The market account      |
+    /// | 1     | ✅       | ❌     | The event queue account |
+    /// | 2     | ✅       | ❌     | The bids account        |
+    /// | 3     | ✅       | ❌     | The asks account        |
+    ConsumeThenNewOrder,
+    /// Check a bounded part of a market's accounts for corruption.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description             |
+    /// |-------|----------|--------|-------------------------|
+    /// | 0     | ❌       | ❌     | The market account      |
+    /// | 1     | ❌       | ❌     | The event queue account |
+    /// | 2     | ❌       | ❌     | The bids account        |
+    /// | 3     | ❌       | ❌     | The asks account        |
+    VerifyMarket,
+    /// Match a taker order against a single maker order, leaving the rest of the orderbook untouched.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description             |
+    /// |-------|----------|--------|-------------------------|
+    /// | 0     | ❌       | ❌     | The market account      |
+    /// | 1     | ✅       | ❌     | The event queue account |
+    /// | 2     | ✅       | ❌     | The bids account        |
+    /// | 3     | ✅       | ❌     | The asks account        |
+    TakeOrder,
+    /// Update the market's cached reference price, which pegged orders are priced against.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description        |
+    /// |-------|----------|--------|--------------------|
+    /// | 0     | ✅       | ❌     | The market account |
+    SetReferencePrice,
+    /// Relocate the contents of a bids or asks account after it was reallocated to a larger size, increasing its capacity.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description        |
+    /// |-------|----------|--------|--------------------|
+    /// | 0     | ❌       | ❌     | The market account |
+    /// | 1     | ✅       | ❌     | The bids account   |
+    /// | 2     | ✅       | ❌     | The asks account   |
+    GrowSlab,
+    /// Cancel an existing order, then execute a new order on the orderbook.
+    ///
+    /// The new order isn't executed if the existing order can't be found.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description             |
+    /// |-------|----------|--------|-------------------------|
+    /// | 0     | ❌       | ❌     | The market account      |
+    /// | 1     | ✅       | ❌     | The event queue account |
+    /// | 2     | ✅       | ❌     | The bids account        |
+    /// | 3     | ✅       | ❌     | The asks account        |
+    ReplaceOrder,
+    /// Decrease the quantity of an existing order in the orderbook, preserving its queue priority.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description             |
+    /// |-------|----------|--------|-------------------------|
+    /// | 0     | ❌       | ❌     | The market account      |
+    /// | 1     | ✅       | ❌     | The event queue account |
+    /// | 2     | ✅       | ❌     | The bids account        |
+    /// | 3     | ✅       | ❌     | The asks account        |
+    ModifyOrder,
+    /// Grow a market account created with the legacy market state layout to the current one.
+    ///
+    /// Required accounts
+    ///
+    /// | index | writable | signer | description        |
+    /// |-------|----------|--------|--------------------|
+    /// | 0     | ✅       | ❌     | The market account |
+    MigrateMarket,
 }
 
 /**
@@ -207,7 +328,7 @@ pub fn close_market(
     i
 }
 
-/// Create and initialize a new orderbook market
+/// Cancel a series of existing orders in the orderbook.
 pub fn mass_cancel_orders(
     accounts: mass_cancel_orders::Accounts<Pubkey>,
     register_account: Pubkey,
@@ -215,7 +336,7 @@ pub fn mass_cancel_orders(
 ) -> Instruction {
     let mut i = accounts.get_instruction(
         crate::id(),
-        AgnosticOrderbookInstruction::CloseMarket as u8,
+        AgnosticOrderbookInstruction::MassCancelOrders as u8,
         params,
     );
     i.accounts.push(AccountMeta {
@@ -225,3 +346,252 @@ pub fn mass_cancel_orders(
     });
     i
 }
+
+/**
+Execute a batch of new orders on the orderbook.
+
+The register account should be at least [`BATCH_SUMMARY_SIZE`][`new_order_batch::BATCH_SUMMARY_SIZE`] bytes long.
+*/
+pub fn new_order_batch<C: BorshSerialize + BorshSize>(
+    accounts: new_order_batch::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: new_order_batch::Params<C>,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::NewOrderBatch as u8,
+        params,
+    );
+
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/// Start a new trading session and remove the orders which were posted during previous sessions.
+pub fn cancel_session_orders(
+    accounts: cancel_session_orders::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: cancel_session_orders::Params,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::CancelSessionOrders as u8,
+        params,
+    );
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/// Remove a bounded number of orders from the orderbook, optionally without pushing out events.
+///
+/// The order summary is written into the register, followed by the cursor from which a subsequent instruction can resume pruning.
+pub fn prune_orders(
+    accounts: prune_orders::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: prune_orders::Params,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::PruneOrders as u8,
+        params,
+    );
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/**
+Pop a series of events off the event queue, then execute a new order on the orderbook.
+
+The register account should be at least [`CONSUME_THEN_NEW_ORDER_SUMMARY_SIZE`][`consume_then_new_order::CONSUME_THEN_NEW_ORDER_SUMMARY_SIZE`] bytes long.
+*/
+pub fn consume_then_new_order<C: BorshSerialize + BorshSize>(
+    accounts: consume_then_new_order::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: consume_then_new_order::Params<C>,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::ConsumeThenNewOrder as u8,
+        params,
+    );
+
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/**
+Check a bounded part of a market's accounts for corruption.
+
+The cursor to resume from is written to the register as an `Option<u64>`.
+*/
+pub fn verify_market(
+    accounts: verify_market::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: verify_market::Params,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::VerifyMarket as u8,
+        params,
+    );
+
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/**
+Match a taker order against a single maker order, leaving the rest of the orderbook untouched.
+*/
+pub fn take_order<C: BorshSerialize + BorshSize>(
+    accounts: take_order::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: take_order::Params<C>,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::TakeOrder as u8,
+        params,
+    );
+
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/**
+Update the market's cached reference price, which pegged orders are priced against.
+*/
+pub fn set_reference_price(
+    accounts: set_reference_price::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: set_reference_price::Params,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::SetReferencePrice as u8,
+        params,
+    );
+
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/**
+Relocate the contents of a bids or asks account after it was reallocated to a larger size, increasing its capacity.
+*/
+pub fn grow_slab(
+    accounts: grow_slab::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: grow_slab::Params,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::GrowSlab as u8,
+        params,
+    );
+
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/**
+Cancel an existing order, then execute a new order on the orderbook.
+
+The new order's summary is written to the register, with the same layout as for a new_order instruction.
+*/
+pub fn replace_order<C: BorshSerialize + BorshSize>(
+    accounts: replace_order::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: replace_order::Params<C>,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::ReplaceOrder as u8,
+        params,
+    );
+
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/**
+Decrease the quantity of an existing order in the orderbook, preserving its queue priority.
+*/
+pub fn modify_order(
+    accounts: modify_order::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: modify_order::Params,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::ModifyOrder as u8,
+        params,
+    );
+
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
+
+/**
+Grow a market account created with the legacy market state layout to the current one.
+
+The market account's lamports should be topped up beforehand so that it stays rent exempt at its new size of
+`8 + MarketState::LEN` bytes.
+*/
+pub fn migrate_market(
+    accounts: migrate_market::Accounts<Pubkey>,
+    register_account: Pubkey,
+    params: migrate_market::Params,
+) -> Instruction {
+    let mut i = accounts.get_instruction(
+        crate::id(),
+        AgnosticOrderbookInstruction::MigrateMarket as u8,
+        params,
+    );
+
+    i.accounts.push(AccountMeta {
+        pubkey: register_account,
+        is_signer: false,
+        is_writable: true,
+    });
+    i
+}
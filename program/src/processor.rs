@@ -2,25 +2,39 @@ use bytemuck::Pod;
 use num_traits::FromPrimitive;
 use solana_program::{account_info::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
 
-use crate::{
-    instruction::AgnosticOrderbookInstruction,
-    state::orderbook::{CallbackInfo, OrderSummary},
-};
+use crate::{instruction::AgnosticOrderbookInstruction, state::orderbook::CallbackInfo};
 
-use borsh::BorshDeserialize;
+use borsh::{BorshDeserialize, BorshSerialize};
 
 pub mod cancel_order;
+pub mod cancel_session_orders;
 pub mod close_market;
 pub mod consume_events;
+pub mod consume_then_new_order;
 pub mod create_market;
+pub mod grow_slab;
 pub mod mass_cancel_orders;
+pub mod migrate_market;
+pub mod modify_order;
 pub mod new_order;
+pub mod new_order_batch;
+pub mod prune_orders;
+pub mod replace_order;
+pub mod set_reference_price;
+pub mod take_order;
+pub mod verify_market;
 
+/// Serializes an instruction's output for it to be written back into the register
+fn to_register<T: BorshSerialize>(output: &T) -> Vec<u8> {
+    output.try_to_vec().unwrap()
+}
+
+/// Processes an instruction and returns the serialized output to be written into the register, if any
 pub fn process_instruction<C: Pod + BorshDeserialize + CallbackInfo + PartialEq>(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     instruction_data: &[u8],
-) -> Result<Option<OrderSummary>, ProgramError>
+) -> Result<Option<Vec<u8>>, ProgramError>
 where
     <C as CallbackInfo>::CallbackId: PartialEq,
 {
@@ -43,21 +57,24 @@ where
             let accounts = new_order::Accounts::parse(accounts)?;
             let params = new_order::Params::<C>::try_from_slice(instruction_data)
                 .map_err(|_| ProgramError::InvalidInstructionData)?;
-            return new_order::process(program_id, accounts, params).map(Some);
+            let order_summary = new_order::process(program_id, accounts, params)?;
+            return Ok(Some(to_register(&Some(order_summary))));
         }
         AgnosticOrderbookInstruction::ConsumeEvents => {
             msg!("Instruction: Consume Events");
             let accounts = consume_events::Accounts::parse(accounts)?;
             let params = consume_events::Params::try_from_slice(instruction_data)
                 .map_err(|_| ProgramError::InvalidInstructionData)?;
-            consume_events::process::<C>(program_id, accounts, params)?;
+            let summary = consume_events::process::<C>(program_id, accounts, params)?;
+            return Ok(Some(to_register(&summary)));
         }
         AgnosticOrderbookInstruction::CancelOrder => {
             msg!("Instruction: Cancel Order");
             let accounts = cancel_order::Accounts::parse(accounts)?;
             let params = cancel_order::Params::try_from_slice(instruction_data)
                 .map_err(|_| ProgramError::InvalidInstructionData)?;
-            return cancel_order::process::<C>(program_id, accounts, params).map(Some);
+            let order_summary = cancel_order::process::<C>(program_id, accounts, params)?;
+            return Ok(Some(to_register(&Some(order_summary))));
         }
         AgnosticOrderbookInstruction::CloseMarket => {
             msg!("Instruction: Close Market");
@@ -69,7 +86,100 @@ where
             let accounts = mass_cancel_orders::Accounts::parse(accounts)?;
             let params = mass_cancel_orders::Params::try_from_slice(instruction_data)
                 .map_err(|_| ProgramError::InvalidInstructionData)?;
-            return mass_cancel_orders::process::<C>(program_id, accounts, params).map(Some);
+            let (order_summary, outcomes) =
+                mass_cancel_orders::process::<C>(program_id, accounts, params)?;
+            // Outcomes are appended after the summary so that the register's layout is unchanged without them
+            return Ok(Some(match outcomes {
+                Some(outcomes) => to_register(&(Some(order_summary), outcomes)),
+                None => to_register(&Some(order_summary)),
+            }));
+        }
+        AgnosticOrderbookInstruction::NewOrderBatch => {
+            msg!("Instruction: New Order Batch");
+            let accounts = new_order_batch::Accounts::parse(accounts)?;
+            let params = new_order_batch::Params::<C>::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            let order_summaries = new_order_batch::process(program_id, accounts, params)?;
+            return Ok(Some(to_register(&order_summaries)));
+        }
+        AgnosticOrderbookInstruction::CancelSessionOrders => {
+            msg!("Instruction: Cancel Session Orders");
+            let accounts = cancel_session_orders::Accounts::parse(accounts)?;
+            let params = cancel_session_orders::Params::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            let order_summary = cancel_session_orders::process::<C>(program_id, accounts, params)?;
+            return Ok(Some(to_register(&Some(order_summary))));
+        }
+        AgnosticOrderbookInstruction::PruneOrders => {
+            msg!("Instruction: Prune Orders");
+            let accounts = prune_orders::Accounts::parse(accounts)?;
+            let params = prune_orders::Params::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            let (order_summary, next_cursor) =
+                prune_orders::process::<C>(program_id, accounts, params)?;
+            // The cursor is appended after the summary so that the register starts with the same layout as before
+            return Ok(Some(to_register(&(Some(order_summary), next_cursor))));
+        }
+        AgnosticOrderbookInstruction::ConsumeThenNewOrder => {
+            msg!("Instruction: Consume Then New Order");
+            let accounts = consume_then_new_order::Accounts::parse(accounts)?;
+            let params = consume_then_new_order::Params::<C>::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            let summary = consume_then_new_order::process(program_id, accounts, params)?;
+            return Ok(Some(to_register(&summary)));
+        }
+        AgnosticOrderbookInstruction::VerifyMarket => {
+            msg!("Instruction: Verify Market");
+            let accounts = verify_market::Accounts::parse(accounts)?;
+            let params = verify_market::Params::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            let next_cursor = verify_market::process::<C>(program_id, accounts, params)?;
+            return Ok(Some(to_register(&next_cursor)));
+        }
+        AgnosticOrderbookInstruction::TakeOrder => {
+            msg!("Instruction: Take Order");
+            let accounts = take_order::Accounts::parse(accounts)?;
+            let params = take_order::Params::<C>::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            let order_summary = take_order::process(program_id, accounts, params)?;
+            return Ok(Some(to_register(&Some(order_summary))));
+        }
+        AgnosticOrderbookInstruction::SetReferencePrice => {
+            msg!("Instruction: Set Reference Price");
+            let accounts = set_reference_price::Accounts::parse(accounts)?;
+            let params = set_reference_price::Params::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            set_reference_price::process(program_id, accounts, params)?;
+        }
+        AgnosticOrderbookInstruction::GrowSlab => {
+            msg!("Instruction: Grow Slab");
+            let accounts = grow_slab::Accounts::parse(accounts)?;
+            let params = grow_slab::Params::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            grow_slab::process::<C>(program_id, accounts, params)?;
+        }
+        AgnosticOrderbookInstruction::ReplaceOrder => {
+            msg!("Instruction: Replace Order");
+            let accounts = replace_order::Accounts::parse(accounts)?;
+            let params = replace_order::Params::<C>::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            let order_summary = replace_order::process(program_id, accounts, params)?;
+            return Ok(Some(to_register(&Some(order_summary))));
+        }
+        AgnosticOrderbookInstruction::ModifyOrder => {
+            msg!("Instruction: Modify Order");
+            let accounts = modify_order::Accounts::parse(accounts)?;
+            let params = modify_order::Params::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            let order_summary = modify_order::process::<C>(program_id, accounts, params)?;
+            return Ok(Some(to_register(&Some(order_summary))));
+        }
+        AgnosticOrderbookInstruction::MigrateMarket => {
+            msg!("Instruction: Migrate Market");
+            let accounts = migrate_market::Accounts::parse(accounts)?;
+            let params = migrate_market::Params::try_from_slice(instruction_data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            migrate_market::process(program_id, accounts, params)?;
         }
     }
     Ok(None)
@@ -6,7 +6,7 @@ use borsh::{BorshDeserialize, BorshSerialize};
 use bytemuck::Pod;
 use solana_program::account_info::next_account_info;
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
     pubkey::Pubkey,
 };
 
@@ -24,6 +24,9 @@ The required arguments for a cancel_order instruction.
 pub struct Params {
     /// The order id is a unique identifier for a particular order
     pub order_id: u128,
+    /// When set, the order is only cancelled if its remaining base quantity still matches this value.
+    /// This guards against cancelling an order which was partially filled since it was last read.
+    pub expected_base_qty: Option<u64>,
 }
 
 /// The required accounts for a cancel_order instruction.
@@ -93,6 +96,15 @@ where
     let mut order_book = OrderBookState::<C>::new_safe(&mut bids_guard, &mut asks_guard)?;
 
     let slab = order_book.get_tree(get_side_from_order_id(params.order_id));
+    if let Some(expected_base_qty) = params.expected_base_qty {
+        let handle = slab
+            .find_by_key(params.order_id)
+            .ok_or(AoError::OrderNotFound)?;
+        if slab.leaf_nodes[handle as usize].base_quantity != expected_base_qty {
+            msg!("The order's base quantity has changed");
+            return Err(AoError::OrderChanged.into());
+        }
+    }
     let (leaf_node, _) = slab
         .remove_by_key(params.order_id)
         .ok_or(AoError::OrderNotFound)?;
@@ -105,6 +117,10 @@ where
         total_base_qty,
         total_quote_qty,
         total_base_qty_posted: 0,
+        posted_price: None,
+        post_skipped_below_min: false,
+        post_skipped_book_full: false,
+        matched_order_count: 0,
     };
 
     Ok(order_summary)
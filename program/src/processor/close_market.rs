@@ -2,6 +2,7 @@
 use crate::{
     error::AoError,
     state::{
+        critbit::{legacy_slab_leaf_count, Slab},
         event_queue::EventQueue,
         market_state::MarketState,
         orderbook::{CallbackInfo, OrderBookState},
@@ -87,8 +88,25 @@ where
     let mut bids_data = accounts.bids.data.borrow_mut();
     let mut asks_data = accounts.asks.data.borrow_mut();
 
+    // Empty slabs laid out by a legacy version are laid out again, see `SLAB_VERSION`
+    for (data, tag) in [
+        (&mut *bids_data, AccountTag::Bids),
+        (&mut *asks_data, AccountTag::Asks),
+    ] {
+        match legacy_slab_leaf_count(data) {
+            Some(0) => {
+                Slab::<C>::prune_legacy(data, tag, 0, true)?;
+            }
+            Some(_) => {
+                msg!("The orderbook must be empty");
+                return Err(ProgramError::from(AoError::MarketStillActive));
+            }
+            None => {}
+        }
+    }
+
     // Check if there are still orders in the book
-    let orderbook_state = OrderBookState::<C>::new_safe(&mut bids_data, &mut asks_data).unwrap();
+    let orderbook_state = OrderBookState::<C>::new_safe(&mut bids_data, &mut asks_data)?;
     if !orderbook_state.is_empty() {
         msg!("The orderbook must be empty");
         return Err(ProgramError::from(AoError::MarketStillActive));
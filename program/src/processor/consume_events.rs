@@ -13,7 +13,10 @@ use solana_program::{
 use crate::{
     error::AoError,
     state::{
-        event_queue::EventQueue, market_state::MarketState, orderbook::CallbackInfo, AccountTag,
+        event_queue::{legacy_event_queue_version, EventQueue, EventRef, EventTag},
+        market_state::{LegacyMarketState, MarketState},
+        orderbook::CallbackInfo,
+        AccountTag,
     },
     utils::{check_account_key, check_account_owner},
 };
@@ -25,6 +28,25 @@ The required arguments for a consume_events instruction.
 pub struct Params {
     /// Depending on applications, it might be optimal to process several events at a time
     pub number_of_entries_to_consume: u64,
+    /// When set, only the leading events which involve a callback info starting with these bytes are popped.
+    ///
+    /// The queue stays in order: popping stops at the first event which doesn't match, even if later events would.
+    pub callback_id_filter: Option<Vec<u8>>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Default, PartialEq)]
+/// This struct is written back into the register after a consume_events instruction.
+///
+/// It describes the mix of events which were popped off the event queue.
+pub struct ConsumeEventsSummary {
+    /// The number of fill events which were consumed
+    pub number_of_fills_consumed: u64,
+    /// The number of out events which were consumed
+    pub number_of_outs_consumed: u64,
+    /// The total base quantity settled across the consumed fill events
+    pub total_base_qty: u64,
+    /// The total quote quantity settled across the consumed fill events
+    pub total_quote_qty: u64,
 }
 
 /// The required accounts for a consume_events instruction.
@@ -68,31 +90,131 @@ pub fn process<'a, 'b: 'a, C: CallbackInfo + Pod + PartialEq>(
     program_id: &Pubkey,
     accounts: Accounts<'a, AccountInfo<'b>>,
     params: Params,
-) -> ProgramResult {
+) -> Result<ConsumeEventsSummary, ProgramError> {
     accounts.perform_checks(program_id)?;
+    let mut event_queue_guard = accounts.event_queue.data.borrow_mut();
+    // Legacy event queues are drained without requiring the market account to be migrated
+    if legacy_event_queue_version(&event_queue_guard).is_some() {
+        let market_state = LegacyMarketState::read(&accounts.market.data.borrow())?;
+        check_account_key(
+            accounts.event_queue,
+            &market_state.event_queue,
+            AoError::WrongEventQueueAccount,
+        )?;
+        if params.callback_id_filter.is_some() {
+            msg!("Legacy event queues can't be consumed with a callback id filter");
+            return Err(AoError::EventQueueVersionMismatch.into());
+        }
+        return consume_legacy::<C>(&mut event_queue_guard, params.number_of_entries_to_consume);
+    }
     let mut market_data = accounts.market.data.borrow_mut();
     let market_state = MarketState::from_buffer(&mut market_data, AccountTag::Market)?;
 
     check_accounts(&accounts, market_state)?;
-    let mut event_queue_guard = accounts.event_queue.data.borrow_mut();
     let mut event_queue =
         EventQueue::<C>::from_buffer(&mut event_queue_guard, AccountTag::EventQueue)?;
 
-    // Reward payout
-    let capped_number_of_entries_consumed = std::cmp::min(
-        event_queue.header.count,
+    let summary = consume(
+        &mut event_queue,
         params.number_of_entries_to_consume,
-    );
+        params.callback_id_filter.as_deref(),
+    )?;
+
+    Ok(summary)
+}
+
+/// Pops up to `number_of_entries_to_consume` events off the event queue and summarizes them
+///
+/// When a `callback_id_filter` is given, only the leading events matching it are popped.
+pub(crate) fn consume<C: Pod>(
+    event_queue: &mut EventQueue<C>,
+    number_of_entries_to_consume: u64,
+    callback_id_filter: Option<&[u8]>,
+) -> Result<ConsumeEventsSummary, AoError> {
+    let number_of_entries_to_consume = match callback_id_filter {
+        Some(filter) => event_queue
+            .iter()
+            .take(number_of_entries_to_consume as usize)
+            .take_while(|event| event_matches_filter(event, filter))
+            .count() as u64,
+        None => number_of_entries_to_consume,
+    };
+    // Reward payout
+    let capped_number_of_entries_consumed =
+        std::cmp::min(event_queue.header.count, number_of_entries_to_consume);
+
+    let mut summary = ConsumeEventsSummary::default();
+    for event in event_queue
+        .iter()
+        .take(capped_number_of_entries_consumed as usize)
+    {
+        match event {
+            EventRef::Fill(f) => {
+                summary.number_of_fills_consumed += 1;
+                summary.total_base_qty = summary
+                    .total_base_qty
+                    .checked_add(f.event.base_size)
+                    .ok_or(AoError::NumericalOverflow)?;
+                summary.total_quote_qty = summary
+                    .total_quote_qty
+                    .checked_add(f.event.quote_size)
+                    .ok_or(AoError::NumericalOverflow)?;
+            }
+            EventRef::Out(_) => summary.number_of_outs_consumed += 1,
+            EventRef::LevelCleared(_) => {}
+        }
+    }
 
     // Pop Events
-    event_queue.pop_n(params.number_of_entries_to_consume);
+    event_queue.pop_n(number_of_entries_to_consume);
 
     msg!(
         "Number of events consumed: {:?}",
         capped_number_of_entries_consumed
     );
+    msg!("Consume events summary : {:?}", summary);
 
-    Ok(())
+    Ok(summary)
+}
+
+/// Pops up to `number_of_entries_to_consume` events off a legacy event queue and summarizes them
+fn consume_legacy<C: Pod>(
+    buf: &mut [u8],
+    number_of_entries_to_consume: u64,
+) -> Result<ConsumeEventsSummary, ProgramError> {
+    let events = EventQueue::<C>::pop_legacy(buf, number_of_entries_to_consume)?;
+    let mut summary = ConsumeEventsSummary::default();
+    for event in &events {
+        if event.tag == EventTag::Fill as u8 {
+            summary.number_of_fills_consumed += 1;
+            summary.total_base_qty = summary
+                .total_base_qty
+                .checked_add(event.base_size)
+                .ok_or(AoError::NumericalOverflow)?;
+            summary.total_quote_qty = summary
+                .total_quote_qty
+                .checked_add(event.quote_size)
+                .ok_or(AoError::NumericalOverflow)?;
+        } else {
+            summary.number_of_outs_consumed += 1;
+        }
+    }
+    msg!("Number of legacy events consumed: {:?}", events.len());
+    msg!("Consume events summary : {:?}", summary);
+
+    Ok(summary)
+}
+
+/// Checks whether any of an event's callback infos starts with the given bytes
+fn event_matches_filter<C: Pod>(event: &EventRef<C>, filter: &[u8]) -> bool {
+    let is_match = |callback_info: &C| bytemuck::bytes_of(callback_info).starts_with(filter);
+    match event {
+        EventRef::Fill(f) => is_match(f.maker_callback_info) || is_match(f.taker_callback_info),
+        EventRef::Out(o) => {
+            is_match(o.callback_info) || matches!(o.taker_callback_info, Some(c) if is_match(c))
+        }
+        EventRef::LevelCleared(_) => false,
+    }
 }
 
 fn check_accounts<'a, 'b: 'a>(
@@ -111,3 +233,61 @@ fn check_accounts<'a, 'b: 'a>(
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::event_queue::{FillEvent, OutEvent};
+    use bytemuck::Zeroable;
+
+    #[test]
+    fn test_consume_callback_id_filter() {
+        let allocation_size = EventQueue::<[u8; 32]>::compute_allocation_size(10);
+        let mut buffer = vec![0; allocation_size];
+        let mut event_queue =
+            EventQueue::<[u8; 32]>::from_buffer(&mut buffer, AccountTag::Uninitialized).unwrap();
+        let user = [1; 32];
+        let other_user = [2; 32];
+        event_queue
+            .push_back(
+                FillEvent {
+                    base_size: 10,
+                    ..Zeroable::zeroed()
+                },
+                Some(&other_user),
+                Some(&user),
+            )
+            .unwrap();
+        event_queue
+            .push_back(OutEvent::zeroed(), Some(&user), None)
+            .unwrap();
+        event_queue
+            .push_back(OutEvent::zeroed(), Some(&other_user), None)
+            .unwrap();
+        event_queue
+            .push_back(OutEvent::zeroed(), Some(&user), None)
+            .unwrap();
+
+        // Popping stops at the first event which doesn't involve the user
+        let summary = consume(&mut event_queue, 10, Some(&user[..4])).unwrap();
+        assert_eq!(summary.number_of_fills_consumed, 1);
+        assert_eq!(summary.number_of_outs_consumed, 1);
+        assert_eq!(summary.total_base_qty, 10);
+        assert_eq!(event_queue.len(), 2);
+
+        let summary = consume(&mut event_queue, 10, Some(&user)).unwrap();
+        assert_eq!(summary, ConsumeEventsSummary::default());
+        let remaining = event_queue
+            .iter()
+            .map(|e| match e {
+                EventRef::Out(o) => o.callback_info[0],
+                _ => panic!("Expected an out event"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(remaining, vec![2, 1]);
+
+        // Without a filter, events are popped regardless of who they involve
+        consume(&mut event_queue, 10, None).unwrap();
+        assert!(event_queue.is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+//! Pop a series of events off the event queue, then execute a new order on the orderbook
+
+use bonfida_utils::{BorshSize, InstructionsAccount};
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::Pod;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+
+use crate::{
+    error::AoError,
+    processor::{
+        consume_events::{self, ConsumeEventsSummary},
+        new_order,
+    },
+    state::{
+        event_queue::EventQueue,
+        market_state::MarketState,
+        orderbook::{CallbackInfo, OrderBookState},
+        AccountTag, OrderSummary, ORDER_SUMMARY_SIZE,
+    },
+    utils::check_account_owner,
+};
+
+/// The maximum serialized size of a [`ConsumeThenNewOrderSummary`] object.
+pub const CONSUME_THEN_NEW_ORDER_SUMMARY_SIZE: usize = 32 + ORDER_SUMMARY_SIZE as usize;
+
+#[derive(Clone, BorshSerialize, BorshDeserialize)]
+/**
+The required arguments for a consume_then_new_order instruction.
+*/
+pub struct Params<C> {
+    /// The number of events to pop off the event queue before executing the order
+    pub number_of_entries_to_consume: u64,
+    /// The order to execute once the events have been consumed
+    pub order: new_order::Params<C>,
+}
+
+impl<C: BorshSize> BorshSize for Params<C> {
+    fn borsh_len(&self) -> usize {
+        self.number_of_entries_to_consume.borsh_len() + self.order.borsh_len()
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug)]
+/// This struct is written back into the register after a consume_then_new_order instruction.
+pub struct ConsumeThenNewOrderSummary {
+    /// Describes the events which were popped off the event queue
+    pub consume_events_summary: ConsumeEventsSummary,
+    /// Describes the outcome of the new order
+    pub order_summary: OrderSummary,
+}
+
+/// The required accounts for a consume_then_new_order instruction.
+#[derive(InstructionsAccount)]
+pub struct Accounts<'a, T> {
+    #[allow(missing_docs)]
+    pub market: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub event_queue: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub bids: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub asks: &'a T,
+}
+
+impl<'a, 'b: 'a> Accounts<'a, AccountInfo<'b>> {
+    pub(crate) fn parse(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let a = Self {
+            market: next_account_info(accounts_iter)?,
+            event_queue: next_account_info(accounts_iter)?,
+            bids: next_account_info(accounts_iter)?,
+            asks: next_account_info(accounts_iter)?,
+        };
+        Ok(a)
+    }
+    pub(crate) fn perform_checks(&self, program_id: &Pubkey) -> Result<(), ProgramError> {
+        check_account_owner(
+            self.market,
+            &program_id.to_bytes(),
+            AoError::WrongMarketOwner,
+        )?;
+        check_account_owner(
+            self.event_queue,
+            &program_id.to_bytes(),
+            AoError::WrongEventQueueOwner,
+        )?;
+        check_account_owner(self.bids, &program_id.to_bytes(), AoError::WrongBidsOwner)?;
+        check_account_owner(self.asks, &program_id.to_bytes(), AoError::WrongAsksOwner)?;
+        Ok(())
+    }
+
+    fn as_new_order_accounts(&self) -> new_order::Accounts<'a, AccountInfo<'b>> {
+        new_order::Accounts {
+            market: self.market,
+            event_queue: self.event_queue,
+            bids: self.bids,
+            asks: self.asks,
+        }
+    }
+}
+
+/// Apply the consume_then_new_order instruction to the provided accounts
+///
+/// The events are popped before the order is matched, which frees up space in the event queue for the order's own events.
+pub fn process<'a, 'b: 'a, C: Pod + CallbackInfo + PartialEq>(
+    program_id: &Pubkey,
+    accounts: Accounts<'a, AccountInfo<'b>>,
+    params: Params<C>,
+) -> Result<ConsumeThenNewOrderSummary, ProgramError>
+where
+    <C as CallbackInfo>::CallbackId: PartialEq,
+{
+    accounts.perform_checks(program_id)?;
+    let Params {
+        number_of_entries_to_consume,
+        mut order,
+    } = params;
+    let mut market_data = accounts.market.data.borrow_mut();
+    let market_state = MarketState::from_buffer(&mut market_data, AccountTag::Market)?;
+
+    new_order::check_accounts(&accounts.as_new_order_accounts(), market_state)?;
+    new_order::check_limit_price(market_state, &mut order)?;
+
+    let mut event_queue_guard = accounts.event_queue.data.borrow_mut();
+    let mut event_queue = EventQueue::from_buffer(&mut event_queue_guard, AccountTag::EventQueue)?;
+
+    let consume_events_summary =
+        consume_events::consume(&mut event_queue, number_of_entries_to_consume, None)?;
+
+    let mut bids_guard = accounts.bids.data.borrow_mut();
+    let mut asks_guard = accounts.asks.data.borrow_mut();
+
+    let mut order_book = OrderBookState::new_safe(&mut bids_guard, &mut asks_guard)?;
+
+    let current_ts = Clock::get()?.unix_timestamp as u64;
+    let order_summary = order_book.new_order(order, &mut event_queue, market_state, current_ts)?;
+    msg!("Order summary : {:?}", order_summary);
+
+    Ok(ConsumeThenNewOrderSummary {
+        consume_events_summary,
+        order_summary,
+    })
+}
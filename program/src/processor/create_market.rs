@@ -5,6 +5,7 @@ use bonfida_utils::{
 };
 use borsh::{BorshDeserialize, BorshSerialize};
 use bytemuck::Pod;
+use enumflags2::BitFlags;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
@@ -15,10 +16,20 @@ use solana_program::{
 
 use crate::{
     error::AoError,
-    state::{critbit::Slab, event_queue::EventQueue, market_state::MarketState, AccountTag},
+    state::{
+        critbit::Slab,
+        event_queue::EventQueue,
+        market_state::{MarketFlag, MarketState},
+        AccountTag,
+    },
     utils::check_account_owner,
 };
 
+/// The maximum size in bytes of the callback info objects a market can be created with.
+///
+/// Every orderbook slot and every event queue entry carries callback infos, so larger ones bloat accounts and event copies.
+pub const MAX_CALLBACK_INFO_LEN: usize = 256;
+
 #[derive(BorshDeserialize, BorshSerialize, BorshSize)]
 /**
 The required arguments for a create_market instruction.
@@ -26,8 +37,14 @@ The required arguments for a create_market instruction.
 pub struct Params {
     /// The minimum order size that can be inserted into the orderbook after matching.
     pub min_base_order_size: u64,
-    /// Enables the limiting of price precision on the orderbook (price ticks)
+    /// Enables the limiting of price precision on the orderbook (price ticks). A tick size of 0 disables this constraint.
     pub tick_size: u64,
+    /// A bitmask of [`MarketFlag`] values enabling optional market behaviors
+    pub flags: u64,
+    /// The maximum limit price (FP32) of orders, which bounds the order id space. Defaults to `u64::MAX`, for no bound.
+    pub max_price: Option<u64>,
+    /// The maximum number of better price levels an order can post behind, which bounds the book's depth. Use 0 for no bound.
+    pub max_post_depth: u32,
 }
 
 /// The required accounts for a create_market instruction.
@@ -81,12 +98,29 @@ pub fn process<'a, 'b: 'a, C: Pod>(
     let Params {
         min_base_order_size,
         tick_size,
+        flags,
+        max_price,
+        max_post_depth,
     } = params;
 
+    let max_price = max_price.unwrap_or(u64::MAX);
+
+    check_callback_info_len(std::mem::size_of::<C>())?;
+
     check_rent(&accounts)?;
 
-    if min_base_order_size == 0 || tick_size == 0 {
-        msg!("min_base_order_size and tick_size must be > 0");
+    if min_base_order_size == 0 {
+        msg!("min_base_order_size must be > 0");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if max_price < tick_size {
+        msg!("max_price must be >= tick_size");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if BitFlags::<MarketFlag>::from_bits(flags).is_err() {
+        msg!("Unknown market flags");
         return Err(ProgramError::InvalidArgument);
     }
 
@@ -103,6 +137,13 @@ pub fn process<'a, 'b: 'a, C: Pod>(
         asks: *accounts.asks.key,
         min_base_order_size,
         tick_size,
+        flags,
+        max_price,
+        current_session: 0,
+        max_post_depth,
+        ref_price: 0,
+        ref_price_ts: 0,
+        ref_price_max_age: 0,
     };
 
     let mut event_queue_data = accounts.event_queue.data.borrow_mut();
@@ -117,6 +158,17 @@ pub fn process<'a, 'b: 'a, C: Pod>(
     Ok(())
 }
 
+fn check_callback_info_len(callback_info_len: usize) -> Result<(), AoError> {
+    if callback_info_len > MAX_CALLBACK_INFO_LEN {
+        msg!(
+            "The callback info size must be at most {} bytes",
+            MAX_CALLBACK_INFO_LEN
+        );
+        return Err(AoError::CallbackInfoTooLarge);
+    }
+    Ok(())
+}
+
 fn check_rent(accounts: &Accounts<AccountInfo>) -> ProgramResult {
     check_rent_exempt(accounts.asks)?;
     check_rent_exempt(accounts.bids)?;
@@ -125,3 +177,18 @@ fn check_rent(accounts: &Accounts<AccountInfo>) -> ProgramResult {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_callback_info_len() {
+        assert!(check_callback_info_len(0).is_ok());
+        assert!(check_callback_info_len(MAX_CALLBACK_INFO_LEN).is_ok());
+        assert!(matches!(
+            check_callback_info_len(MAX_CALLBACK_INFO_LEN + 1),
+            Err(AoError::CallbackInfoTooLarge)
+        ));
+    }
+}
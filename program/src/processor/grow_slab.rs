@@ -0,0 +1,106 @@
+//! Relocate the contents of a bids or asks account after it was reallocated to a larger size.
+
+use bonfida_utils::{checks::check_rent_exempt, BorshSize, InstructionsAccount};
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::Pod;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    error::AoError,
+    state::{critbit::Slab, market_state::MarketState, AccountTag, Side},
+    utils::{check_account_key, check_account_owner},
+};
+#[derive(BorshDeserialize, BorshSerialize, Clone, BorshSize)]
+/**
+The required arguments for a grow_slab instruction.
+*/
+pub struct Params {
+    /// The side of the orderbook whose account was grown
+    pub side: Side,
+    /// The length in bytes of the account before it was reallocated, which must match the capacity stored in the slab
+    pub previous_len: u64,
+}
+
+/// The required accounts for a grow_slab instruction.
+#[derive(InstructionsAccount)]
+pub struct Accounts<'a, T> {
+    #[allow(missing_docs)]
+    pub market: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub bids: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub asks: &'a T,
+}
+
+impl<'a, 'b: 'a> Accounts<'a, AccountInfo<'b>> {
+    pub(crate) fn parse(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let a = Self {
+            market: next_account_info(accounts_iter)?,
+            bids: next_account_info(accounts_iter)?,
+            asks: next_account_info(accounts_iter)?,
+        };
+        Ok(a)
+    }
+    /// Perform basic security checks on the accounts
+    pub(crate) fn perform_checks(&self, program_id: &Pubkey) -> Result<(), ProgramError> {
+        check_account_owner(
+            self.market,
+            &program_id.to_bytes(),
+            AoError::WrongMarketOwner,
+        )?;
+        check_account_owner(self.bids, &program_id.to_bytes(), AoError::WrongBidsOwner)?;
+        check_account_owner(self.asks, &program_id.to_bytes(), AoError::WrongAsksOwner)?;
+        Ok(())
+    }
+}
+/// Apply the grow_slab instruction to the provided accounts
+///
+/// The caller program is expected to have reallocated the account to its new size beforehand, within the same transaction.
+pub fn process<'a, 'b: 'a, C: Pod>(
+    program_id: &Pubkey,
+    accounts: Accounts<'a, AccountInfo<'b>>,
+    params: Params,
+) -> ProgramResult {
+    accounts.perform_checks(program_id)?;
+    let Params { side, previous_len } = params;
+    let mut market_data = accounts.market.data.borrow_mut();
+    let market_state = MarketState::from_buffer(&mut market_data, AccountTag::Market)?;
+
+    check_accounts(&accounts, market_state)?;
+
+    let (slab_account, tag) = match side {
+        Side::Bid => (accounts.bids, AccountTag::Bids),
+        Side::Ask => (accounts.asks, AccountTag::Asks),
+    };
+    check_rent_exempt(slab_account)?;
+
+    let mut slab_guard = slab_account.data.borrow_mut();
+    if AccountTag::peek(&slab_guard) != Some(tag) {
+        return Err(AoError::AccountTagMismatch.into());
+    }
+    Slab::<C>::grow_buffer(&mut slab_guard, previous_len as usize)?;
+    let slab = Slab::<C>::from_buffer(&mut slab_guard, tag)?;
+    msg!("New slab capacity: {}", slab.leaf_nodes.len());
+
+    Ok(())
+}
+
+fn check_accounts<'a, 'b: 'a>(
+    accounts: &Accounts<'a, AccountInfo<'b>>,
+    market_state: &MarketState,
+) -> ProgramResult {
+    check_account_key(accounts.bids, &market_state.bids, AoError::WrongBidsAccount)?;
+    check_account_key(accounts.asks, &market_state.asks, AoError::WrongAsksAccount)?;
+
+    Ok(())
+}
@@ -1,11 +1,12 @@
 //! Cancel a series of existing orders in the orderbook.
 
-use bonfida_utils::{fp_math::fp32_mul_floor, BorshSize, InstructionsAccount};
+use bonfida_utils::{BorshSize, InstructionsAccount};
 use borsh::{BorshDeserialize, BorshSerialize};
 use bytemuck::Pod;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
+    msg,
     program_error::ProgramError,
     pubkey::Pubkey,
 };
@@ -13,7 +14,7 @@ use solana_program::{
 use crate::{
     error::AoError,
     state::{
-        get_side_from_order_id,
+        event_queue::EventQueue,
         market_state::MarketState,
         orderbook::{CallbackInfo, OrderBookState, OrderSummary},
         AccountTag,
@@ -27,8 +28,19 @@ The required arguments for a cancel_order instruction.
 pub struct Params {
     /// The order id is a unique identifier for a particular order
     pub order_ids: Vec<u128>,
+    /// When set, order ids which can't be found are skipped instead of failing the instruction, and whether each order
+    /// was cancelled is written to the register after the summary.
+    ///
+    /// At most [`MAX_REPORTED_OUTCOMES`] order ids can then be given.
+    pub report_outcomes: bool,
 }
 
+/// The maximum number of order ids whose cancellation outcome can be reported by a single instruction
+pub const MAX_REPORTED_OUTCOMES: usize = 64;
+
+/// The `(order_id, cancelled)` outcome of each order id given to a mass_cancel_orders instruction
+pub type CancelOutcomes = Vec<(u128, bool)>;
+
 /// The required accounts for a cancel_order instruction.
 #[derive(InstructionsAccount)]
 pub struct Accounts<'a, T> {
@@ -75,11 +87,13 @@ impl<'a, 'b: 'a> Accounts<'a, AccountInfo<'b>> {
     }
 }
 /// Apply the cancel_order instruction to the provided accounts
+///
+/// An out event is pushed for each cancelled order. When outcomes are reported, they are returned as `(order_id, cancelled)` pairs in the order of the given ids.
 pub fn process<'a, 'b: 'a, C: CallbackInfo + Pod + PartialEq>(
     program_id: &Pubkey,
     accounts: Accounts<'a, AccountInfo<'b>>,
     params: Params,
-) -> Result<OrderSummary, ProgramError>
+) -> Result<(OrderSummary, Option<CancelOutcomes>), ProgramError>
 where
     <C as CallbackInfo>::CallbackId: PartialEq,
 {
@@ -94,26 +108,25 @@ where
 
     let mut order_book = OrderBookState::<C>::new_safe(&mut bids_guard, &mut asks_guard)?;
 
-    let mut total_base_qty = 0u64;
-    let mut total_quote_qty = 0u64;
+    let mut event_queue_guard = accounts.event_queue.data.borrow_mut();
+    let mut event_queue = EventQueue::from_buffer(&mut event_queue_guard, AccountTag::EventQueue)?;
 
-    for order_id in params.order_ids {
-        let slab = order_book.get_tree(get_side_from_order_id(order_id));
-        let (leaf_node, _) = slab.remove_by_key(order_id).ok_or(AoError::OrderNotFound)?;
-        total_base_qty = total_base_qty.checked_add(leaf_node.base_quantity).unwrap();
-        total_quote_qty = fp32_mul_floor(leaf_node.base_quantity, leaf_node.price())
-            .and_then(|n| n.checked_add(total_quote_qty))
-            .unwrap();
+    if !params.report_outcomes {
+        let order_summary = order_book.cancel_orders(&params.order_ids, &mut event_queue)?;
+        return Ok((order_summary, None));
     }
 
-    let order_summary = OrderSummary {
-        posted_order_id: None,
-        total_base_qty,
-        total_quote_qty,
-        total_base_qty_posted: 0,
-    };
+    if params.order_ids.len() > MAX_REPORTED_OUTCOMES {
+        msg!(
+            "At most {} orders can be cancelled when reporting outcomes",
+            MAX_REPORTED_OUTCOMES
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+    let (order_summary, outcomes) =
+        order_book.cancel_orders_with_outcomes(&params.order_ids, &mut event_queue)?;
 
-    Ok(order_summary)
+    Ok((order_summary, Some(outcomes)))
 }
 
 fn check_accounts<'a, 'b: 'a>(
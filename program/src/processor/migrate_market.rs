@@ -0,0 +1,81 @@
+//! Grow a market account created with the legacy market state layout to the current one.
+
+use bonfida_utils::{checks::check_rent_exempt, BorshSize, InstructionsAccount};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    error::AoError,
+    state::{
+        market_state::{LegacyMarketState, MarketState},
+        AccountTag,
+    },
+    utils::check_account_owner,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, BorshSize)]
+/**
+The required arguments for a migrate_market instruction.
+*/
+pub struct Params {}
+
+/// The required accounts for a migrate_market instruction.
+#[derive(InstructionsAccount)]
+pub struct Accounts<'a, T> {
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub market: &'a T,
+}
+
+impl<'a, 'b: 'a> Accounts<'a, AccountInfo<'b>> {
+    pub(crate) fn parse(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let a = Self {
+            market: next_account_info(accounts_iter)?,
+        };
+        Ok(a)
+    }
+
+    /// Perform basic security checks on the accounts
+    pub(crate) fn perform_checks(&self, program_id: &Pubkey) -> Result<(), ProgramError> {
+        check_account_owner(
+            self.market,
+            &program_id.to_bytes(),
+            AoError::WrongMarketOwner,
+        )?;
+        Ok(())
+    }
+}
+
+/// Apply the migrate_market instruction to the provided accounts
+///
+/// The caller program is expected to have topped up the market account's lamports for its new size beforehand.
+/// The new fields keep the behavior of legacy markets: no market flags, no price or depth bound and no reference price.
+pub fn process<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    accounts: Accounts<'a, AccountInfo<'b>>,
+    _params: Params,
+) -> ProgramResult {
+    accounts.perform_checks(program_id)?;
+
+    LegacyMarketState::read(&accounts.market.data.borrow())?;
+    if accounts.market.data_len() != 8 + LegacyMarketState::LEN {
+        msg!("The market account was already migrated");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    accounts.market.realloc(8 + MarketState::LEN, true)?;
+    check_rent_exempt(accounts.market)?;
+
+    let mut market_data = accounts.market.data.borrow_mut();
+    let market_state = MarketState::from_buffer(&mut market_data, AccountTag::Market)?;
+    market_state.max_price = u64::MAX;
+
+    Ok(())
+}
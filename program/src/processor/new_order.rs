@@ -5,10 +5,12 @@ use borsh::{BorshDeserialize, BorshSerialize};
 use bytemuck::Pod;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    clock::Clock,
     entrypoint::ProgramResult,
     msg,
     program_error::ProgramError,
     pubkey::Pubkey,
+    sysvar::Sysvar,
 };
 
 use crate::{
@@ -17,9 +19,9 @@ use crate::{
         event_queue::EventQueue,
         market_state::MarketState,
         orderbook::{CallbackInfo, OrderBookState},
-        AccountTag, OrderSummary, SelfTradeBehavior, Side,
+        AccountTag, OrderSummary, OrderType, SelfTradeBehavior, Side, TakerMode,
     },
-    utils::{check_account_key, check_account_owner},
+    utils::{check_account_key, check_account_owner, round_price},
 };
 
 #[derive(Clone, BorshSerialize, BorshDeserialize)]
@@ -32,7 +34,7 @@ pub struct Params<C> {
     /// The maximum quantity of quote to be traded.
     pub max_quote_qty: u64,
     /// The limit price of the order. This value is understood as a 32-bit fixed point number.
-    /// Must be rounded to the nearest tick size multiple (see [`round_price`][`crate::utils::round_price`])
+    /// Must be rounded to the nearest tick size multiple (see [`round_price`][`crate::utils::round_price`]), unless `round_to_tick` is set.
     pub limit_price: u64,
     /// The order's side.
     pub side: Side,
@@ -53,6 +55,74 @@ pub struct Params<C> {
     pub post_allowed: bool,
     /// Describes what would happen if this order was matched against an order with an equal `callback_info` field.
     pub self_trade_behavior: SelfTradeBehavior,
+    /// When set, a `limit_price` which isn't a tick size multiple is rounded to a valid tick instead of being rejected.
+    ///
+    /// Bids are rounded down and asks are rounded up so that the order never becomes more aggressive.
+    /// The adjusted price is reported in the [`OrderSummary`][`crate::state::OrderSummary`]'s `posted_price` field.
+    pub round_to_tick: bool,
+    /// When set, an order which would post at a price strictly less aggressive than the current best order on its side
+    /// is rejected with [`AoError::WouldNotImprove`] instead.
+    ///
+    /// Orders which are fully matched or which don't post are unaffected.
+    pub require_top_of_book: bool,
+    /// When set, the remainder of the order is posted in full, even if its quote value exceeds what is left of `max_quote_qty`.
+    ///
+    /// Matching still respects the quote budget. The posted quote quantity is included in the summary's `total_quote_qty`.
+    pub post_full_base: bool,
+    /// When set, the order is pegged: its limit price is the market's reference price offset by this (FP32) amount,
+    /// and `limit_price` is ignored.
+    ///
+    /// The order is rejected with [`AoError::StaleReferencePrice`] if the market's reference price is unset or stale.
+    pub peg_offset: Option<i64>,
+    /// Whether a limit price exactly equal to the best opposite price counts as crossing. This should usually be set.
+    ///
+    /// When unset, an order priced exactly at the best opposite price joins the book instead of matching against it.
+    pub cross_on_equal: bool,
+    /// Describes how the order treats the outcome of its matching, see [`TakerMode`].
+    pub taker_mode: TakerMode,
+    /// When set, the order is matched as usual but is never posted: whatever remains once matching stops, including
+    /// because `match_limit` was reached, is dropped.
+    pub immediate_or_cancel: bool,
+    /// When set, the order must be entirely filled, which means that matching must exhaust either `max_base_qty`
+    /// or `max_quote_qty`. The order is otherwise rejected with [`AoError::FillOrKillUnfulfilled`].
+    ///
+    /// The crossing liquidity is checked before matching. The order is never posted.
+    pub fill_or_kill: bool,
+    /// Whether the order is constrained by its limit price, see [`OrderType`].
+    pub order_type: OrderType,
+    /// The unix timestamp after which the posted order expires. A value of 0 means that the order doesn't expire.
+    ///
+    /// Expired orders are removed from the orderbook when a taker order reaches them.
+    pub max_ts: u64,
+    /// The base quantity of the posted order which is displayed in the orderbook's depth. A value of 0 means that the
+    /// order is fully displayed.
+    ///
+    /// The hidden quantity can still be matched, and the order keeps its time priority as its displayed quantity is filled.
+    pub display_qty: u64,
+    /// When set along with `post_only`, an order which would cross the spread is repriced one tick inside the best
+    /// opposite price and posted there, instead of not being posted.
+    ///
+    /// The order isn't posted if no such price exists. The adjusted price is reported in the [`OrderSummary`][`crate::state::OrderSummary`]'s
+    /// `posted_price` field.
+    pub post_only_slide: bool,
+    /// When set, the posted order can only be matched in its entirety.
+    ///
+    /// Taker orders which can't fill it completely skip over it and match the orders behind it instead.
+    pub all_or_nothing: bool,
+    /// The minimum base quantity which must be matched for the order to execute, or the instruction fails.
+    /// A value of 0 means that there is no minimum.
+    pub min_fill_base_qty: u64,
+}
+
+impl<C> Params<C> {
+    /// Checks whether the remainder of the order can be posted to the orderbook after matching
+    pub fn may_post(&self) -> bool {
+        self.post_allowed
+            && !self.immediate_or_cancel
+            && !self.fill_or_kill
+            && self.order_type != OrderType::Market
+            && self.taker_mode != TakerMode::BestEffort
+    }
 }
 
 impl<C: BorshSize> BorshSize for Params<C> {
@@ -66,6 +136,20 @@ impl<C: BorshSize> BorshSize for Params<C> {
             + self.post_only.borsh_len()
             + self.post_allowed.borsh_len()
             + self.self_trade_behavior.borsh_len()
+            + self.round_to_tick.borsh_len()
+            + self.require_top_of_book.borsh_len()
+            + self.post_full_base.borsh_len()
+            + self.peg_offset.borsh_len()
+            + self.cross_on_equal.borsh_len()
+            + self.taker_mode.borsh_len()
+            + self.immediate_or_cancel.borsh_len()
+            + self.fill_or_kill.borsh_len()
+            + self.order_type.borsh_len()
+            + self.max_ts.borsh_len()
+            + self.display_qty.borsh_len()
+            + self.post_only_slide.borsh_len()
+            + self.all_or_nothing.borsh_len()
+            + self.min_fill_base_qty.borsh_len()
     }
 }
 
@@ -118,7 +202,7 @@ impl<'a, 'b: 'a> Accounts<'a, AccountInfo<'b>> {
 pub fn process<'a, 'b: 'a, C: Pod + CallbackInfo + PartialEq>(
     program_id: &Pubkey,
     accounts: Accounts<'a, AccountInfo<'b>>,
-    params: Params<C>,
+    mut params: Params<C>,
 ) -> Result<OrderSummary, ProgramError>
 where
     <C as CallbackInfo>::CallbackId: PartialEq,
@@ -128,17 +212,7 @@ where
     let market_state = MarketState::from_buffer(&mut market_data, AccountTag::Market)?;
 
     check_accounts(&accounts, market_state)?;
-
-    if params.limit_price % market_state.tick_size != 0 {
-        return Err(AoError::InvalidLimitPrice.into());
-    }
-
-    if params.post_allowed && params.limit_price < market_state.tick_size {
-        msg!(
-            "Can't attempt to post an order of price less than market tick size to the orderbook!"
-        );
-        return Err(AoError::InvalidLimitPrice.into());
-    }
+    check_limit_price(market_state, &mut params)?;
 
     let mut bids_guard = accounts.bids.data.borrow_mut();
     let mut asks_guard = accounts.asks.data.borrow_mut();
@@ -148,14 +222,47 @@ where
     let mut event_queue_guard = accounts.event_queue.data.borrow_mut();
     let mut event_queue = EventQueue::from_buffer(&mut event_queue_guard, AccountTag::EventQueue)?;
 
-    let order_summary =
-        order_book.new_order(params, &mut event_queue, market_state.min_base_order_size)?;
+    let current_ts = Clock::get()?.unix_timestamp as u64;
+    let order_summary = order_book.new_order(params, &mut event_queue, market_state, current_ts)?;
     msg!("Order summary : {:?}", order_summary);
 
     Ok(order_summary)
 }
 
-fn check_accounts<'a, 'b: 'a>(
+/// Checks the order's limit price against the market's tick size, rounding it if the order allows it
+///
+/// The limit price of a pegged order is first resolved from the market's reference price.
+pub(crate) fn check_limit_price<C>(
+    market_state: &MarketState,
+    params: &mut Params<C>,
+) -> ProgramResult {
+    // The limit price of a market order is ignored
+    if params.order_type == OrderType::Market {
+        return Ok(());
+    }
+
+    if let Some(peg_offset) = params.peg_offset {
+        params.limit_price = market_state.pegged_price(peg_offset, Clock::get()?.unix_timestamp)?;
+    }
+
+    // A tick size of 0 means that any price is valid
+    if market_state.tick_size != 0 && params.limit_price % market_state.tick_size != 0 {
+        if !params.round_to_tick {
+            return Err(AoError::InvalidLimitPrice.into());
+        }
+        params.limit_price = round_price(market_state.tick_size, params.limit_price, params.side);
+    }
+
+    if params.may_post() && params.limit_price < market_state.tick_size.max(1) {
+        msg!(
+            "Can't attempt to post an order of price less than market tick size to the orderbook!"
+        );
+        return Err(AoError::InvalidLimitPrice.into());
+    }
+    Ok(())
+}
+
+pub(crate) fn check_accounts<'a, 'b: 'a>(
     accounts: &Accounts<'a, AccountInfo<'b>>,
     market_state: &MarketState,
 ) -> ProgramResult {
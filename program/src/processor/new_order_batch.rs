@@ -0,0 +1,225 @@
+//! Execute a batch of new orders on the orderbook
+
+use bonfida_utils::{BorshSize, InstructionsAccount};
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::Pod;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+
+use crate::{
+    error::AoError,
+    processor::new_order,
+    state::{
+        event_queue::EventQueue,
+        market_state::MarketState,
+        orderbook::{CallbackInfo, OrderBookState},
+        AccountTag, OrderSummary, ORDER_SUMMARY_SIZE,
+    },
+    utils::check_account_owner,
+};
+
+/// The maximum number of orders which can be submitted in a single new_order_batch instruction.
+///
+/// This bounds the size of the register's output and the instruction's compute usage, but not its event queue usage.
+/// The number of events an order pushes depends on the resting orders it meets rather than on its own parameters, so
+/// no useful capacity can be reserved up front. Instead, every event push fails with [`AoError::EventQueueFull`] once
+/// the queue is full, and the failed instruction reverts the orders of the batch which were already executed.
+pub const MAX_BATCH_SIZE: usize = 10;
+
+/// The maximum serialized size of the order summaries written back into the register by a new_order_batch instruction.
+pub const BATCH_SUMMARY_SIZE: usize = 4 + MAX_BATCH_SIZE * ORDER_SUMMARY_SIZE as usize;
+
+#[derive(Clone, BorshSerialize, BorshDeserialize)]
+/**
+The required arguments for a new_order_batch instruction.
+*/
+pub struct Params<C> {
+    /// The orders to execute, in order. At most [`MAX_BATCH_SIZE`] orders can be submitted at once.
+    pub orders: Vec<new_order::Params<C>>,
+}
+
+impl<C: BorshSize> BorshSize for Params<C> {
+    fn borsh_len(&self) -> usize {
+        4 + self.orders.iter().map(|o| o.borsh_len()).sum::<usize>()
+    }
+}
+
+/// The required accounts for a new_order_batch instruction.
+#[derive(InstructionsAccount)]
+pub struct Accounts<'a, T> {
+    #[allow(missing_docs)]
+    pub market: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub event_queue: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub bids: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub asks: &'a T,
+}
+
+impl<'a, 'b: 'a> Accounts<'a, AccountInfo<'b>> {
+    pub(crate) fn parse(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let a = Self {
+            market: next_account_info(accounts_iter)?,
+            event_queue: next_account_info(accounts_iter)?,
+            bids: next_account_info(accounts_iter)?,
+            asks: next_account_info(accounts_iter)?,
+        };
+        Ok(a)
+    }
+    pub(crate) fn perform_checks(&self, program_id: &Pubkey) -> Result<(), ProgramError> {
+        check_account_owner(
+            self.market,
+            &program_id.to_bytes(),
+            AoError::WrongMarketOwner,
+        )?;
+        check_account_owner(
+            self.event_queue,
+            &program_id.to_bytes(),
+            AoError::WrongEventQueueOwner,
+        )?;
+        check_account_owner(self.bids, &program_id.to_bytes(), AoError::WrongBidsOwner)?;
+        check_account_owner(self.asks, &program_id.to_bytes(), AoError::WrongAsksOwner)?;
+        Ok(())
+    }
+
+    fn as_new_order_accounts(&self) -> new_order::Accounts<'a, AccountInfo<'b>> {
+        new_order::Accounts {
+            market: self.market,
+            event_queue: self.event_queue,
+            bids: self.bids,
+            asks: self.asks,
+        }
+    }
+}
+
+/// Apply the new_order_batch instruction to the provided accounts
+///
+/// The orders are executed sequentially, each one seeing the orderbook as left by the previous ones.
+/// If any of the orders fails, the whole batch fails.
+pub fn process<'a, 'b: 'a, C: Pod + CallbackInfo + PartialEq>(
+    program_id: &Pubkey,
+    accounts: Accounts<'a, AccountInfo<'b>>,
+    params: Params<C>,
+) -> Result<Vec<OrderSummary>, ProgramError>
+where
+    <C as CallbackInfo>::CallbackId: PartialEq,
+{
+    accounts.perform_checks(program_id)?;
+    let Params { orders } = params;
+
+    if orders.is_empty() {
+        return Err(AoError::NoOperations.into());
+    }
+    if orders.len() > MAX_BATCH_SIZE {
+        msg!("A batch can contain at most {} orders", MAX_BATCH_SIZE);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut market_data = accounts.market.data.borrow_mut();
+    let market_state = MarketState::from_buffer(&mut market_data, AccountTag::Market)?;
+
+    new_order::check_accounts(&accounts.as_new_order_accounts(), market_state)?;
+
+    let mut bids_guard = accounts.bids.data.borrow_mut();
+    let mut asks_guard = accounts.asks.data.borrow_mut();
+
+    let mut order_book = OrderBookState::new_safe(&mut bids_guard, &mut asks_guard)?;
+
+    let mut event_queue_guard = accounts.event_queue.data.borrow_mut();
+    let mut event_queue = EventQueue::from_buffer(&mut event_queue_guard, AccountTag::EventQueue)?;
+
+    let current_ts = Clock::get()?.unix_timestamp as u64;
+    let mut order_summaries = Vec::with_capacity(orders.len());
+    for mut order in orders {
+        new_order::check_limit_price(market_state, &mut order)?;
+        let order_summary =
+            order_book.new_order(order, &mut event_queue, market_state, current_ts)?;
+        msg!("Order summary : {:?}", order_summary);
+        order_summaries.push(order_summary);
+    }
+
+    Ok(order_summaries)
+}
+
+/// Writes a series of order summaries into a register account's data, using the same length-prefixed layout as a new_order_batch instruction.
+///
+/// Fails without writing anything if the summaries don't fit in the register.
+pub fn write_summaries(
+    register: &mut [u8],
+    summaries: &[OrderSummary],
+) -> Result<(), ProgramError> {
+    let data = summaries
+        .try_to_vec()
+        .map_err(|_| ProgramError::InvalidArgument)?;
+    if data.len() > register.len() {
+        msg!("The order summaries don't fit in the register");
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    register[..data.len()].copy_from_slice(&data);
+    Ok(())
+}
+
+/// Reads the order summaries written into a register account's data by a new_order_batch instruction.
+pub fn read_summaries(register: &[u8]) -> Result<Vec<OrderSummary>, ProgramError> {
+    Vec::<OrderSummary>::deserialize(&mut &register[..])
+        .map_err(|_| ProgramError::InvalidAccountData)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summaries_round_trip() {
+        let summaries = (0..MAX_BATCH_SIZE as u64)
+            .map(|i| OrderSummary {
+                posted_order_id: (i % 2 == 0).then_some(i as u128),
+                total_base_qty: i,
+                total_quote_qty: 2 * i,
+                total_base_qty_posted: i / 2,
+                posted_price: Some(u64::MAX),
+                post_skipped_below_min: i % 3 == 0,
+                post_skipped_book_full: i % 4 == 0,
+                matched_order_count: i,
+            })
+            .collect::<Vec<_>>();
+        let mut register = vec![0; BATCH_SUMMARY_SIZE];
+        write_summaries(&mut register, &summaries).unwrap();
+
+        let decoded = read_summaries(&register).unwrap();
+        assert_eq!(decoded.len(), summaries.len());
+        for (a, b) in decoded.iter().zip(&summaries) {
+            assert_eq!(a.posted_order_id, b.posted_order_id);
+            assert_eq!(a.total_base_qty, b.total_base_qty);
+            assert_eq!(a.total_quote_qty, b.total_quote_qty);
+            assert_eq!(a.total_base_qty_posted, b.total_base_qty_posted);
+            assert_eq!(a.posted_price, b.posted_price);
+            assert_eq!(a.post_skipped_below_min, b.post_skipped_below_min);
+            assert_eq!(a.post_skipped_book_full, b.post_skipped_book_full);
+            assert_eq!(a.matched_order_count, b.matched_order_count);
+        }
+
+        // An empty register decodes to no summaries
+        assert!(read_summaries(&[0; 4]).unwrap().is_empty());
+
+        // Summaries which don't fit are rejected
+        let mut register = vec![0; summaries.try_to_vec().unwrap().len() - 1];
+        assert_eq!(
+            write_summaries(&mut register, &summaries),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        assert!(register.iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,144 @@
+//! Cancel an existing order, then execute a new order on the orderbook
+use bonfida_utils::{BorshSize, InstructionsAccount};
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::Pod;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+
+use crate::{
+    error::AoError,
+    processor::new_order,
+    state::{
+        event_queue::{EventQueue, OutEvent, OutReason},
+        get_side_from_order_id,
+        market_state::MarketState,
+        orderbook::{CallbackInfo, OrderBookState},
+        AccountTag, OrderSummary,
+    },
+    utils::check_account_owner,
+};
+
+#[derive(Clone, BorshSerialize, BorshDeserialize)]
+/**
+The required arguments for a replace_order instruction.
+*/
+pub struct Params<C> {
+    /// The order id of the order to cancel
+    pub order_id: u128,
+    /// The order to execute once the existing order has been cancelled
+    pub order: new_order::Params<C>,
+}
+
+impl<C: BorshSize> BorshSize for Params<C> {
+    fn borsh_len(&self) -> usize {
+        self.order_id.borsh_len() + self.order.borsh_len()
+    }
+}
+
+/// The required accounts for a replace_order instruction.
+#[derive(InstructionsAccount)]
+pub struct Accounts<'a, T> {
+    #[allow(missing_docs)]
+    pub market: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub event_queue: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub bids: &'a T,
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub asks: &'a T,
+}
+
+impl<'a, 'b: 'a> Accounts<'a, AccountInfo<'b>> {
+    pub(crate) fn parse(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let a = Self {
+            market: next_account_info(accounts_iter)?,
+            event_queue: next_account_info(accounts_iter)?,
+            bids: next_account_info(accounts_iter)?,
+            asks: next_account_info(accounts_iter)?,
+        };
+        Ok(a)
+    }
+    pub(crate) fn perform_checks(&self, program_id: &Pubkey) -> Result<(), ProgramError> {
+        check_account_owner(
+            self.market,
+            &program_id.to_bytes(),
+            AoError::WrongMarketOwner,
+        )?;
+        check_account_owner(
+            self.event_queue,
+            &program_id.to_bytes(),
+            AoError::WrongEventQueueOwner,
+        )?;
+        check_account_owner(self.bids, &program_id.to_bytes(), AoError::WrongBidsOwner)?;
+        check_account_owner(self.asks, &program_id.to_bytes(), AoError::WrongAsksOwner)?;
+        Ok(())
+    }
+
+    fn as_new_order_accounts(&self) -> new_order::Accounts<'a, AccountInfo<'b>> {
+        new_order::Accounts {
+            market: self.market,
+            event_queue: self.event_queue,
+            bids: self.bids,
+            asks: self.asks,
+        }
+    }
+}
+
+/// Apply the replace_order instruction to the provided accounts
+///
+/// An out event is pushed for the cancelled order. If it can't be found, the instruction fails before the new order
+/// is executed. The returned summary describes the new order.
+pub fn process<'a, 'b: 'a, C: Pod + CallbackInfo + PartialEq>(
+    program_id: &Pubkey,
+    accounts: Accounts<'a, AccountInfo<'b>>,
+    params: Params<C>,
+) -> Result<OrderSummary, ProgramError>
+where
+    <C as CallbackInfo>::CallbackId: PartialEq,
+{
+    accounts.perform_checks(program_id)?;
+    let Params {
+        order_id,
+        mut order,
+    } = params;
+    let mut market_data = accounts.market.data.borrow_mut();
+    let market_state = MarketState::from_buffer(&mut market_data, AccountTag::Market)?;
+
+    new_order::check_accounts(&accounts.as_new_order_accounts(), market_state)?;
+    new_order::check_limit_price(market_state, &mut order)?;
+
+    let mut event_queue_guard = accounts.event_queue.data.borrow_mut();
+    let mut event_queue = EventQueue::from_buffer(&mut event_queue_guard, AccountTag::EventQueue)?;
+
+    let mut bids_guard = accounts.bids.data.borrow_mut();
+    let mut asks_guard = accounts.asks.data.borrow_mut();
+
+    let mut order_book = OrderBookState::new_safe(&mut bids_guard, &mut asks_guard)?;
+
+    let side = get_side_from_order_id(order_id);
+    let (leaf_node, callback_info) = order_book
+        .get_tree(side)
+        .remove_by_key(order_id)
+        .ok_or(AoError::OrderNotFound)?;
+    let out_event = OutEvent::new(side, order_id, leaf_node.base_quantity, OutReason::Cancel);
+    event_queue
+        .push_back(out_event, Some(&callback_info), None)
+        .map_err(|_| AoError::EventQueueFull)?;
+
+    let current_ts = Clock::get()?.unix_timestamp as u64;
+    let order_summary = order_book.new_order(order, &mut event_queue, market_state, current_ts)?;
+    msg!("Order summary : {:?}", order_summary);
+
+    Ok(order_summary)
+}
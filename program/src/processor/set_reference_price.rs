@@ -0,0 +1,85 @@
+//! Update the market's cached reference price, which pegged orders are priced against.
+
+use bonfida_utils::{BorshSize, InstructionsAccount};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+
+use crate::{
+    error::AoError,
+    state::{market_state::MarketState, AccountTag},
+    utils::check_account_owner,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, BorshSize)]
+/**
+The required arguments for a set_reference_price instruction.
+*/
+pub struct Params {
+    /// The new reference price (FP32). It cannot be 0.
+    pub ref_price: u64,
+    /// The number of seconds during which pegged orders can be priced against this reference price.
+    pub max_age: u64,
+}
+
+/// The required accounts for a set_reference_price instruction.
+#[derive(InstructionsAccount)]
+pub struct Accounts<'a, T> {
+    #[allow(missing_docs)]
+    #[cons(writable)]
+    pub market: &'a T,
+}
+
+impl<'a, 'b: 'a> Accounts<'a, AccountInfo<'b>> {
+    pub(crate) fn parse(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let a = Self {
+            market: next_account_info(accounts_iter)?,
+        };
+        Ok(a)
+    }
+
+    /// Perform basic security checks on the accounts
+    pub(crate) fn perform_checks(&self, program_id: &Pubkey) -> Result<(), ProgramError> {
+        check_account_owner(
+            self.market,
+            &program_id.to_bytes(),
+            AoError::WrongMarketOwner,
+        )?;
+        Ok(())
+    }
+}
+
+/// Apply the set_reference_price instruction to the provided accounts
+///
+/// The market account being owned by the caller program, only that program can update its reference price.
+pub fn process<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    accounts: Accounts<'a, AccountInfo<'b>>,
+    params: Params,
+) -> ProgramResult {
+    accounts.perform_checks(program_id)?;
+    let Params { ref_price, max_age } = params;
+
+    if ref_price == 0 {
+        msg!("The reference price must be > 0");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut market_data = accounts.market.data.borrow_mut();
+    let market_state = MarketState::from_buffer(&mut market_data, AccountTag::Market)?;
+
+    market_state.ref_price = ref_price;
+    market_state.ref_price_ts = Clock::get()?.unix_timestamp;
+    market_state.ref_price_max_age = max_age;
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Check the integrity of a market's accounts, for instance after a program upgrade.
+
+use bonfida_utils::{BorshSize, InstructionsAccount};
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::Pod;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    error::AoError,
+    state::{
+        critbit::VerifyCursor, event_queue::EventQueue, market_state::MarketState,
+        orderbook::OrderBookState, AccountTag, Side,
+    },
+    utils::{check_account_key, check_account_owner},
+};
+#[derive(BorshDeserialize, BorshSerialize, Clone, BorshSize)]
+/**
+The required arguments for a verify_market instruction.
+*/
+pub struct Params {
+    /// The side of the orderbook to check
+    pub side: Side,
+    /// The cursor returned by the previous call from which to resume checking nodes. Use `None` for the first call.
+    pub cursor: Option<VerifyCursor>,
+    /// The maximum number of nodes to check, which bounds the instruction's compute usage
+    pub max_nodes: u64,
+}
+
+/// The required accounts for a verify_market instruction.
+#[derive(InstructionsAccount)]
+pub struct Accounts<'a, T> {
+    #[allow(missing_docs)]
+    pub market: &'a T,
+    #[allow(missing_docs)]
+    pub event_queue: &'a T,
+    #[allow(missing_docs)]
+    pub bids: &'a T,
+    #[allow(missing_docs)]
+    pub asks: &'a T,
+}
+
+impl<'a, 'b: 'a> Accounts<'a, AccountInfo<'b>> {
+    pub(crate) fn parse(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let a = Self {
+            market: next_account_info(accounts_iter)?,
+            event_queue: next_account_info(accounts_iter)?,
+            bids: next_account_info(accounts_iter)?,
+            asks: next_account_info(accounts_iter)?,
+        };
+        Ok(a)
+    }
+    /// Perform basic security checks on the accounts
+    pub(crate) fn perform_checks(&self, program_id: &Pubkey) -> Result<(), ProgramError> {
+        check_account_owner(
+            self.market,
+            &program_id.to_bytes(),
+            AoError::WrongMarketOwner,
+        )?;
+        check_account_owner(
+            self.event_queue,
+            &program_id.to_bytes(),
+            AoError::WrongEventQueueOwner,
+        )?;
+        check_account_owner(self.bids, &program_id.to_bytes(), AoError::WrongBidsOwner)?;
+        check_account_owner(self.asks, &program_id.to_bytes(), AoError::WrongAsksOwner)?;
+        Ok(())
+    }
+}
+/// Apply the verify_market instruction to the provided accounts
+///
+/// Returns the cursor to resume from in a subsequent call, or `None` once the whole slab has been checked.
+/// This instruction doesn't modify any account. Gating it behind an authority is left to the caller program.
+pub fn process<'a, 'b: 'a, C: Pod>(
+    program_id: &Pubkey,
+    accounts: Accounts<'a, AccountInfo<'b>>,
+    params: Params,
+) -> Result<Option<VerifyCursor>, ProgramError> {
+    accounts.perform_checks(program_id)?;
+    let Params {
+        side,
+        cursor,
+        max_nodes,
+    } = params;
+    let mut market_data = accounts.market.data.borrow_mut();
+    let market_state = MarketState::from_buffer(&mut market_data, AccountTag::Market)?;
+
+    check_accounts(&accounts, market_state)?;
+
+    let mut event_queue_guard = accounts.event_queue.data.borrow_mut();
+    let event_queue = EventQueue::<C>::from_buffer(&mut event_queue_guard, AccountTag::EventQueue)?;
+    event_queue.verify_header()?;
+
+    let mut bids_guard = accounts.bids.data.borrow_mut();
+    let mut asks_guard = accounts.asks.data.borrow_mut();
+
+    let mut order_book = OrderBookState::<C>::new_safe(&mut bids_guard, &mut asks_guard)?;
+
+    if order_book.is_crossed() {
+        msg!("The best bid is above the best ask");
+        return Err(AoError::MarketCorrupted.into());
+    }
+
+    let next_cursor = order_book.get_tree(side).verify(cursor, max_nodes)?;
+    msg!("Next cursor: {:?}", next_cursor);
+
+    Ok(next_cursor)
+}
+
+fn check_accounts<'a, 'b: 'a>(
+    accounts: &Accounts<'a, AccountInfo<'b>>,
+    market_state: &MarketState,
+) -> ProgramResult {
+    check_account_key(
+        accounts.event_queue,
+        &market_state.event_queue,
+        AoError::WrongEventQueueAccount,
+    )?;
+    check_account_key(accounts.bids, &market_state.bids, AoError::WrongBidsAccount)?;
+    check_account_key(accounts.asks, &market_state.asks, AoError::WrongAsksAccount)?;
+
+    Ok(())
+}
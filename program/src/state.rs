@@ -2,6 +2,7 @@ use bonfida_utils::BorshSize;
 use borsh::{BorshDeserialize, BorshSerialize};
 use bytemuck::{CheckedBitPattern, NoUninit};
 use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive;
 
 pub use crate::state::orderbook::{OrderSummary, ORDER_SUMMARY_SIZE};
 #[cfg(not(feature = "entrypoint"))]
@@ -13,7 +14,7 @@ pub mod event_queue;
 pub mod market_state;
 pub mod orderbook;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, FromPrimitive)]
 #[allow(missing_docs)]
 #[repr(u8)]
 /// Warning: the account tags are bitshifted to allow for standard tag usage in the program using the aob.
@@ -26,6 +27,16 @@ pub enum AccountTag {
     Disabled,
 }
 
+impl AccountTag {
+    /// Reads the account tag of raw account data without parsing the rest of it.
+    ///
+    /// This allows callers to check which kind of account they hold before committing to a full parse.
+    /// Returns `None` if the data is empty or doesn't start with a known tag.
+    pub fn peek(data: &[u8]) -> Option<Self> {
+        data.first().and_then(|&tag| Self::from_u8(tag))
+    }
+}
+
 #[derive(
     BorshDeserialize,
     BorshSerialize,
@@ -39,6 +50,7 @@ pub enum AccountTag {
     CheckedBitPattern,
     NoUninit,
 )]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 #[allow(missing_docs)]
 pub enum Side {
@@ -56,7 +68,8 @@ impl Side {
     }
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq, FromPrimitive, BorshSize)]
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, FromPrimitive, BorshSize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Describes what happens when two order with identical callback informations are matched together
 pub enum SelfTradeBehavior {
     /// The orders are matched together
@@ -66,6 +79,37 @@ pub enum SelfTradeBehavior {
     CancelProvide,
     /// The entire transaction fails and the program returns an error.
     AbortTransaction,
+    /// The order on the provide side is cancelled, and so is the remaining quantity of the current order, which stops
+    /// matching and isn't posted.
+    CancelBoth,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, FromPrimitive, BorshSize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Describes how an order treats the outcome of its matching
+pub enum TakerMode {
+    /// The order is matched and posted as described by its other parameters
+    Standard,
+    /// The order is matched against the orderbook up to its limit price and is never posted, whatever the value of
+    /// its `post_only` and `post_allowed` fields.
+    ///
+    /// The order never fails because of how little it matched, which includes matching nothing at all: the summary of
+    /// what was filled is returned instead. Self trades are handled according to the order's `self_trade_behavior`,
+    /// except that [`SelfTradeBehavior::AbortTransaction`] stops the matching instead of failing the transaction.
+    /// Invalid parameters and a full event queue are still reported as errors.
+    BestEffort,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, FromPrimitive, BorshSize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Describes how an order's price is constrained
+pub enum OrderType {
+    /// The order is only matched against orders at its limit price or better
+    Limit,
+    /// The order has no limit price and is matched against the best orders until its base or quote budget is exhausted.
+    ///
+    /// The order's `limit_price` is ignored and the order is never posted.
+    Market,
 }
 
 /// This byte flag is set for order_ids with side Bid, and unset for side Ask
@@ -79,3 +123,81 @@ pub fn get_side_from_order_id(order_id: u128) -> Side {
         Side::Ask
     }
 }
+
+/// This helper function splits a list of order_ids into `(bids, asks)`, preserving their relative order
+pub fn partition_by_side(order_ids: &[u128]) -> (Vec<u128>, Vec<u128>) {
+    order_ids
+        .iter()
+        .partition(|&&order_id| get_side_from_order_id(order_id) == Side::Bid)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The decoded components of an order_id, mainly useful for logging and debugging.
+///
+/// It is displayed as `price@seq (side)`, with the price in its FP32 representation.
+pub struct DecodedOrderId {
+    /// The order's limit price (FP32)
+    pub price: u64,
+    /// The event queue sequence number at which the order was created
+    pub seq: u64,
+    #[allow(missing_docs)]
+    pub side: Side,
+}
+
+impl From<u128> for DecodedOrderId {
+    fn from(order_id: u128) -> Self {
+        let side = get_side_from_order_id(order_id);
+        let lower = order_id as u64;
+        // Bid sequence numbers are negated so that older orders sort first in a price descending traversal
+        let seq = match side {
+            Side::Bid => !lower,
+            Side::Ask => lower,
+        };
+        Self {
+            price: (order_id >> 64) as u64,
+            seq,
+            side,
+        }
+    }
+}
+
+impl std::fmt::Display for DecodedOrderId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{} ({:?})", self.price, self.seq, self.side)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_order_id() {
+        let bid_order_id = (42u128 << 64) | (!7u64 as u128);
+        let decoded = DecodedOrderId::from(bid_order_id);
+        assert_eq!(
+            decoded,
+            DecodedOrderId {
+                price: 42,
+                seq: 7,
+                side: Side::Bid
+            }
+        );
+        assert_eq!(decoded.to_string(), "42@7 (Bid)");
+
+        let ask_order_id = (42u128 << 64) | 7;
+        assert_eq!(DecodedOrderId::from(ask_order_id).to_string(), "42@7 (Ask)");
+    }
+
+    #[test]
+    fn test_partition_by_side() {
+        let bid = |price: u128, seq: u64| (price << 64) | (!seq as u128);
+        let ask = |price: u128, seq: u64| (price << 64) | seq as u128;
+        let order_ids = [bid(10, 0), ask(11, 1), ask(12, 2), bid(9, 3), bid(10, 4)];
+        let (bids, asks) = partition_by_side(&order_ids);
+        assert_eq!(bids, vec![bid(10, 0), bid(9, 3), bid(10, 4)]);
+        assert_eq!(asks, vec![ask(11, 1), ask(12, 2)]);
+
+        assert_eq!(partition_by_side(&[]), (vec![], vec![]));
+    }
+}
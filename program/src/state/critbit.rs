@@ -1,9 +1,16 @@
 #![allow(missing_docs)]
 use crate::error::AoError;
-use crate::state::AccountTag;
+#[cfg(any(test, feature = "utils"))]
+use crate::state::orderbook::CallbackInfo;
+use crate::state::{get_side_from_order_id, AccountTag, Side};
+#[cfg(any(test, feature = "utils"))]
+use bonfida_utils::fp_math::fp32_mul_floor;
+use bonfida_utils::BorshSize;
 use borsh::{BorshDeserialize, BorshSerialize};
 use bytemuck::{Pod, Zeroable};
-use solana_program::program_error::ProgramError;
+use enumflags2::{bitflags, BitFlags};
+use solana_program::{msg, program_error::ProgramError};
+use std::convert::TryFrom;
 // A Slab contains the data for a slab header and two type-split arrays of inner nodes and leaves arranger in a critbit tree
 // whose leaves contain data referencing an order of the orderbook.
 
@@ -23,12 +30,75 @@ pub struct SlabHeader {
 
     root_node: u32,
     pub leaf_count: u32,
+    /// The number of orders the slab can hold, which is checked against the length of the account
+    pub order_capacity: u32,
+    _padding: u32,
 }
 
 impl SlabHeader {
     pub const LEN: usize = std::mem::size_of::<Self>();
 }
 
+/// The current layout version of slab accounts, stored in the second byte of the account tag.
+///
+/// Version 0 is the original layout, with a 32-byte header and 24-byte leaves. Its account tags only use their first
+/// byte, so that the version of these slabs reads as 0. Version 1 grew the header to 40 bytes, and leaves to 48 bytes
+/// by adding [`LeafNode::max_ts`], [`LeafNode::display_qty`], [`LeafNode::session_id`] and [`LeafNode::flags`].
+///
+/// Orders can't be matched against version 0 slabs anymore, but the prune_orders instruction still removes them with
+/// [`Slab::prune_legacy`]. Once empty, the slab is laid out again with the current version, after which the market can
+/// be traded on or closed as usual. The close_market instruction lays out empty version 0 slabs again by itself.
+pub const SLAB_VERSION: u8 = 1;
+
+/// The offset of the layout version in slab account data, which is in the spare bytes of the account tag
+const SLAB_VERSION_OFFSET: usize = 1;
+
+/// The byte size of the header of a version 0 slab, see [`SLAB_VERSION`]
+const LEGACY_HEADER_LEN: usize = 32;
+
+/// The byte size of the leaves of a version 0 slab, which only hold the key and base quantity of their order
+const LEGACY_LEAF_LEN: usize = 24;
+
+/// Returns the layout version of raw slab account data if it was laid out by a legacy version which can still be
+/// pruned, see [`SLAB_VERSION`].
+pub fn legacy_slab_version(data: &[u8]) -> Option<u8> {
+    match (AccountTag::peek(data)?, *data.get(SLAB_VERSION_OFFSET)?) {
+        (AccountTag::Bids | AccountTag::Asks, 0) => Some(0),
+        _ => None,
+    }
+}
+
+/// Returns the number of orders left in raw slab account data laid out by a legacy version, see [`SLAB_VERSION`].
+pub fn legacy_slab_leaf_count(data: &[u8]) -> Option<u32> {
+    legacy_slab_version(data)?;
+    // The leaf count is the last field of the legacy header
+    let offset = 8 + LEGACY_HEADER_LEN - 4;
+    data.get(offset..offset + 4)
+        .map(bytemuck::pod_read_unaligned::<u32>)
+}
+
+/// The part of a Slab which [`Slab::verify`] is checking
+#[derive(BorshDeserialize, BorshSerialize, BorshSize, Clone, Copy, Debug, PartialEq)]
+pub enum VerifyStage {
+    /// The critbit tree, walked in key order
+    Tree,
+    /// The list of free leaves
+    LeafFreeList,
+    /// The list of free inner nodes
+    InnerFreeList,
+}
+
+/// The position from which [`Slab::verify`] resumes checking a Slab
+#[derive(BorshDeserialize, BorshSerialize, BorshSize, Clone, Copy, Debug, PartialEq)]
+pub struct VerifyCursor {
+    #[allow(missing_docs)]
+    pub stage: VerifyStage,
+    /// In the tree stage, the smallest key left to check. In the free list stages, the next free node to check.
+    pub position: u128,
+    /// The number of leaves or free nodes which were checked in the current stage
+    pub checked: u32,
+}
+
 pub struct Slab<'a, C> {
     pub header: &'a mut SlabHeader,
     pub leaf_nodes: &'a mut [LeafNode],
@@ -45,6 +115,27 @@ pub struct LeafNode {
     pub key: [u64; 2],
     /// The quantity of base asset associated with the underlying order
     pub base_quantity: u64,
+    /// The unix timestamp after which the order expires. A value of 0 means that the order doesn't expire.
+    pub max_ts: u64,
+    /// The base quantity displayed in the orderbook's depth. A value of 0 means that the order is fully displayed.
+    ///
+    /// Matching always fills against the order's entire base quantity.
+    pub display_qty: u64,
+    /// The trading session during which the order was posted. Orders of version 0 slabs have no session, see
+    /// [`SLAB_VERSION`].
+    pub session_id: u32,
+    /// A bitmask of [`LeafFlag`] values describing the order
+    pub flags: u8,
+    pub(crate) _padding: [u8; 3],
+}
+
+#[bitflags]
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Optional properties of a posted order
+pub enum LeafFlag {
+    /// The order can only be matched in its entirety
+    AllOrNothing = 1 << 0,
 }
 
 impl LeafNode {
@@ -71,6 +162,25 @@ impl LeafNode {
         }
     }
 
+    /// Get the base quantity of the order which is displayed in the orderbook's depth
+    pub fn displayed_quantity(&self) -> u64 {
+        if self.display_qty == 0 {
+            self.base_quantity
+        } else {
+            self.display_qty.min(self.base_quantity)
+        }
+    }
+
+    /// Checks whether a [`LeafFlag`] is set for this order
+    pub fn has_flag(&self, flag: LeafFlag) -> bool {
+        BitFlags::<LeafFlag>::from_bits_truncate(self.flags).contains(flag)
+    }
+
+    /// Checks whether the order has expired at the given unix timestamp
+    pub fn is_expired(&self, current_ts: u64) -> bool {
+        self.max_ts != 0 && self.max_ts < current_ts
+    }
+
     /// Deduce an associated price from an order_id
     pub(crate) fn price_from_key(key: u128) -> u64 {
         (key >> 64) as u64
@@ -122,9 +232,26 @@ impl<'slab, C> Slab<'slab, C> {
         }
         asks_data[0] = AccountTag::Asks as u8;
         bids_data[0] = AccountTag::Bids as u8;
+        Self::write_layout(asks_data);
+        Self::write_layout(bids_data);
         Ok(())
     }
 
+    /// Writes the current layout version and the order capacity given by the account length into raw slab account data
+    fn write_layout(data: &mut [u8]) {
+        data[SLAB_VERSION_OFFSET] = SLAB_VERSION;
+        let capacity = Self::capacity_from_len(data.len());
+        bytemuck::from_bytes_mut::<SlabHeader>(&mut data[8..8 + SlabHeader::LEN]).order_capacity =
+            capacity as u32 + 1;
+    }
+
+    /// Returns the number of inner nodes of a slab account of the given length, which is one less than its number of
+    /// leaves
+    fn capacity_from_len(len: usize) -> usize {
+        let leaf_size = LeafNode::LEN + std::mem::size_of::<C>();
+        (len - SlabHeader::LEN - 8 - leaf_size) / (leaf_size + InnerNode::LEN)
+    }
+
     pub fn compute_allocation_size(desired_order_capacity: usize) -> usize {
         8 + SlabHeader::LEN
             + LeafNode::LEN
@@ -137,17 +264,27 @@ impl<'slab, C> Slab<'slab, C> {
 impl<'a, C: Pod> Slab<'a, C> {
     pub fn from_buffer(buf: &'a mut [u8], expected_tag: AccountTag) -> Result<Self, ProgramError> {
         let callback_info_len = std::mem::size_of::<C>();
-        let leaf_size = LeafNode::LEN + callback_info_len;
-        let capacity = (buf.len() - SlabHeader::LEN - 8 - leaf_size) / (leaf_size + InnerNode::LEN);
+        let capacity = Self::capacity_from_len(buf.len());
 
         if buf[0] != expected_tag as u8 {
             return Err(AoError::AccountTagMismatch.into());
         }
+        if buf[SLAB_VERSION_OFFSET] != SLAB_VERSION {
+            msg!("Unsupported slab layout version");
+            return Err(AoError::SlabVersionMismatch.into());
+        }
         let (_, rem) = buf.split_at_mut(8);
         let (header, rem) = rem.split_at_mut(SlabHeader::LEN);
         let (leaves, rem) = rem.split_at_mut((capacity + 1) * LeafNode::LEN);
-        let (inner_nodes, callback_infos) = rem.split_at_mut(capacity * InnerNode::LEN);
+        let (inner_nodes, rem) = rem.split_at_mut(capacity * InnerNode::LEN);
+        // Slabs laid out again from a legacy version may have trailing bytes
+        let callback_infos = &mut rem[..(capacity + 1) * callback_info_len];
         let header = bytemuck::from_bytes_mut::<SlabHeader>(header);
+        // Grown accounts must be relocated with the grow_slab instruction first
+        if header.order_capacity as usize != capacity + 1 {
+            msg!("The slab's capacity doesn't match its account length");
+            return Err(ProgramError::InvalidAccountData);
+        }
 
         Ok(Self {
             header,
@@ -156,6 +293,153 @@ impl<'a, C: Pod> Slab<'a, C> {
             callback_infos: bytemuck::cast_slice_mut::<_, C>(callback_infos),
         })
     }
+
+    /// Relocates the contents of a slab account which was grown from `previous_len` bytes to the new length of `buf`,
+    /// so that [`Slab::from_buffer`] reads back the same orders with a larger capacity.
+    ///
+    /// The inner nodes and callback infos regions start at capacity-dependent offsets, so they are moved to the end
+    /// of their new, larger regions. The newly available space is zeroed out. `previous_len` must match the order
+    /// capacity stored in the slab header, so that a slab can't be relocated twice.
+    pub fn grow_buffer(buf: &mut [u8], previous_len: usize) -> Result<(), ProgramError> {
+        let callback_info_len = std::mem::size_of::<C>();
+        if buf.get(SLAB_VERSION_OFFSET) != Some(&SLAB_VERSION) {
+            return Err(AoError::SlabVersionMismatch.into());
+        }
+        if buf.len() < previous_len || previous_len < Self::compute_allocation_size(1) {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let header = bytemuck::pod_read_unaligned::<SlabHeader>(&buf[8..8 + SlabHeader::LEN]);
+        if header.order_capacity == 0
+            || previous_len != Self::compute_allocation_size(header.order_capacity as usize)
+        {
+            msg!("The previous length doesn't match the slab's capacity");
+            return Err(ProgramError::InvalidArgument);
+        }
+        let old_capacity = header.order_capacity as usize - 1;
+        if header.leaf_bump_index as usize > old_capacity + 1
+            || header.inner_node_bump_index as usize > old_capacity
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let new_capacity = Self::capacity_from_len(buf.len());
+
+        let leaves_start = 8 + SlabHeader::LEN;
+        let old_inner_start = leaves_start + (old_capacity + 1) * LeafNode::LEN;
+        let old_callback_start = old_inner_start + old_capacity * InnerNode::LEN;
+        let new_inner_start = leaves_start + (new_capacity + 1) * LeafNode::LEN;
+        let new_callback_start = new_inner_start + new_capacity * InnerNode::LEN;
+        let old_inner_len = old_capacity * InnerNode::LEN;
+        let old_callback_len = (old_capacity + 1) * callback_info_len;
+
+        // The regions move right, so the furthest one is moved first to avoid overwriting the other
+        buf.copy_within(
+            old_callback_start..old_callback_start + old_callback_len,
+            new_callback_start,
+        );
+        buf.copy_within(
+            old_inner_start..old_inner_start + old_inner_len,
+            new_inner_start,
+        );
+        buf[old_inner_start..new_inner_start].fill(0);
+        buf[new_inner_start + old_inner_len..new_callback_start].fill(0);
+        buf[new_callback_start + old_callback_len..].fill(0);
+        Self::write_layout(buf);
+        Ok(())
+    }
+
+    /// Removes up to `max_orders` orders from slab account data laid out by a legacy version, see [`SLAB_VERSION`].
+    ///
+    /// Orders are removed in price ascending order if `ascending` is set, in price descending order otherwise, and
+    /// returned as `(order_id, base_quantity, callback_info)` tuples. The free lists of the legacy slab aren't maintained:
+    /// once it is empty, the slab is laid out again with the current version so that [`Slab::from_buffer`] accepts it.
+    pub fn prune_legacy(
+        buf: &mut [u8],
+        expected_tag: AccountTag,
+        max_orders: usize,
+        ascending: bool,
+    ) -> Result<Vec<(u128, u64, C)>, ProgramError> {
+        if buf.first() != Some(&(expected_tag as u8)) {
+            return Err(AoError::AccountTagMismatch.into());
+        }
+        if legacy_slab_version(buf).is_none() {
+            return Err(AoError::SlabVersionMismatch.into());
+        }
+        let callback_info_len = std::mem::size_of::<C>();
+        let leaf_size = LEGACY_LEAF_LEN + callback_info_len;
+        let capacity =
+            (buf.len() - LEGACY_HEADER_LEN - 8 - leaf_size) / (leaf_size + InnerNode::LEN);
+        let leaves_start = 8 + LEGACY_HEADER_LEN;
+        let inner_start = leaves_start + (capacity + 1) * LEGACY_LEAF_LEN;
+        let callback_start = inner_start + capacity * InnerNode::LEN;
+        // The children handles are the last 8 bytes of an inner node
+        let child_offset = |handle: NodeHandle, crit_bit: bool| {
+            inner_start + (!handle) as usize * InnerNode::LEN + InnerNode::LEN - 8
+                + 4 * crit_bit as usize
+        };
+        let read_u32 = |buf: &[u8], offset: usize| {
+            bytemuck::pod_read_unaligned::<u32>(&buf[offset..offset + 4])
+        };
+
+        // The root node and leaf count are the last fields of the legacy header
+        let mut root_node = read_u32(buf, leaves_start - 8);
+        let mut leaf_count = read_u32(buf, leaves_start - 4);
+        let mut removed = Vec::with_capacity(max_orders.min(leaf_count as usize));
+        while leaf_count != 0 && removed.len() < max_orders {
+            // Walk down to the extreme leaf, remembering where its parent is referenced from
+            let mut parent = None;
+            let mut parent_slot = None;
+            let mut slot = None;
+            let mut node = root_node;
+            while let Node::Inner = Node::from_handle(node) {
+                parent = Some(node);
+                parent_slot = slot;
+                slot = Some(child_offset(node, !ascending));
+                node = read_u32(buf, child_offset(node, !ascending));
+            }
+            // The leaf's sibling takes the place of their parent
+            if let Some(parent) = parent {
+                let sibling = read_u32(buf, child_offset(parent, ascending));
+                match parent_slot {
+                    Some(offset) => buf[offset..offset + 4].copy_from_slice(&sibling.to_le_bytes()),
+                    None => root_node = sibling,
+                }
+            }
+            leaf_count -= 1;
+
+            let leaf = &buf[leaves_start + node as usize * LEGACY_LEAF_LEN..][..LEGACY_LEAF_LEN];
+            let order_id = bytemuck::pod_read_unaligned::<u128>(&leaf[..16]);
+            let base_quantity = bytemuck::pod_read_unaligned::<u64>(&leaf[16..24]);
+            let callback_info = bytemuck::pod_read_unaligned::<C>(
+                &buf[callback_start + node as usize * callback_info_len..][..callback_info_len],
+            );
+            removed.push((order_id, base_quantity, callback_info));
+        }
+
+        if leaf_count == 0 {
+            buf[8..].fill(0);
+            Self::write_layout(buf);
+        } else {
+            buf[leaves_start - 8..leaves_start - 4].copy_from_slice(&root_node.to_le_bytes());
+            buf[leaves_start - 4..leaves_start].copy_from_slice(&leaf_count.to_le_bytes());
+        }
+        Ok(removed)
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Initializes a fresh empty slab in the given buffer, zeroing it out and setting its account tag.
+    ///
+    /// The buffer's length should be a valid allocation size as given by [`Slab::compute_allocation_size`].
+    pub fn from_buffer_zeroed(buf: &'a mut [u8], tag: AccountTag) -> Result<Self, ProgramError> {
+        let min_size = Self::compute_allocation_size(1);
+        let slot_size = LeafNode::LEN + InnerNode::LEN + std::mem::size_of::<C>();
+        if buf.len() < min_size || (buf.len() - min_size) % slot_size != 0 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        buf.fill(0);
+        buf[0] = tag as u8;
+        Self::write_layout(buf);
+        Self::from_buffer(buf, tag)
+    }
 }
 
 impl<'a, C> Slab<'a, C> {
@@ -166,6 +450,12 @@ impl<'a, C> Slab<'a, C> {
             Some(self.header.root_node)
         }
     }
+
+    /// Returns true when no leaf can be allocated without first freeing one
+    pub fn is_full(&self) -> bool {
+        self.header.leaf_free_list_len == 0
+            && self.header.leaf_bump_index as usize >= self.leaf_nodes.len()
+    }
     pub(crate) fn allocate_leaf(&mut self) -> Result<NodeHandle, IoError> {
         if self.header.leaf_free_list_len == 0 {
             if self.header.leaf_bump_index as usize >= self.leaf_nodes.len() {
@@ -224,9 +514,14 @@ impl<'a, C> Slab<'a, C> {
         self.header.inner_node_free_list_head = !handle;
     }
 
+    /// Inserts a new leaf into the tree.
+    ///
+    /// If a leaf with the same key already exists, it is either clobbered and returned when `allow_clobber` is set,
+    /// or left untouched with an [`AoError::DuplicateOrderId`] error.
     pub(crate) fn insert_leaf(
         &mut self,
         new_leaf: &LeafNode,
+        allow_clobber: bool,
     ) -> Result<(NodeHandle, Option<LeafNode>), AoError> {
         let mut root: NodeHandle = if self.header.leaf_count == 0 {
             // create a new root if none exists
@@ -266,6 +561,9 @@ impl<'a, C> Slab<'a, C> {
                 Node::Leaf => {
                     let root_node = &mut self.leaf_nodes[root as usize];
                     if root_node.key == new_leaf.key {
+                        if !allow_clobber {
+                            return Err(AoError::DuplicateOrderId);
+                        }
                         // clobber the existing leaf
                         let leaf_copy = *root_node;
                         *root_node = *new_leaf;
@@ -327,7 +625,11 @@ impl<'a, C> Slab<'a, C> {
         &mut self.callback_infos[leaf_handle as usize]
     }
 
-    pub fn remove_by_key(&mut self, search_key: u128) -> Option<(LeafNode, &C)> {
+    /// Removes the order with the given key, returning its leaf and a copy of its callback info
+    pub fn remove_by_key(&mut self, search_key: u128) -> Option<(LeafNode, C)>
+    where
+        C: Copy,
+    {
         let mut grandparent_h: Option<NodeHandle> = None;
         if self.header.leaf_count == 0 {
             return None;
@@ -365,7 +667,7 @@ impl<'a, C> Slab<'a, C> {
 
             self.header.root_node = 0;
             self.header.leaf_count = 0;
-            return Some((leaf_copy, self.get_callback_info(parent_h)));
+            return Some((leaf_copy, *self.get_callback_info(parent_h)));
         }
         loop {
             match Node::from_handle(child_h) {
@@ -411,7 +713,7 @@ impl<'a, C> Slab<'a, C> {
         let removed_leaf = self.leaf_nodes[child_h as usize];
         self.free_leaf(child_h);
         self.free_inner_node(parent_h);
-        Some((removed_leaf, self.get_callback_info(child_h)))
+        Some((removed_leaf, *self.get_callback_info(child_h)))
     }
 
     fn find_min_max(&self, find_max: bool) -> Option<NodeHandle> {
@@ -440,6 +742,34 @@ impl<'a, C> Slab<'a, C> {
         self.find_min_max(true)
     }
 
+    /// Get the `(best, worst)` prices in the Slab, walking down both of its extremal branches at once.
+    ///
+    /// The Slab's side is read off the order ids: the best price is the highest for bids and the lowest for asks.
+    pub fn price_extremes(&self) -> Option<(u64, u64)> {
+        let mut min_handle = self.root()?;
+        let mut max_handle = min_handle;
+        loop {
+            let mut done = true;
+            if let Node::Inner = Node::from_handle(min_handle) {
+                min_handle = self.inner_nodes[(!min_handle) as usize].children[0];
+                done = false;
+            }
+            if let Node::Inner = Node::from_handle(max_handle) {
+                max_handle = self.inner_nodes[(!max_handle) as usize].children[1];
+                done = false;
+            }
+            if done {
+                break;
+            }
+        }
+        let min_leaf = &self.leaf_nodes[min_handle as usize];
+        let max_price = self.leaf_nodes[max_handle as usize].price();
+        match get_side_from_order_id(min_leaf.order_id()) {
+            Side::Bid => Some((max_price, min_leaf.price())),
+            Side::Ask => Some((min_leaf.price(), max_price)),
+        }
+    }
+
     /// Get a price ascending or price descending iterator over all the Slab's orders
     pub fn into_iter(self, price_ascending: bool) -> SlabIterator<'a, C> {
         SlabIterator {
@@ -453,6 +783,228 @@ impl<'a, C> Slab<'a, C> {
         }
     }
 
+    /// Get a price ascending or price descending iterator over all the Slab's orders and their callback information, without consuming the Slab
+    pub fn iter_with_callbacks(
+        &self,
+        ascending: bool,
+    ) -> impl Iterator<Item = (LeafNode, &C)> + '_ {
+        self.leaf_handles(ascending)
+            .map(move |h| (self.leaf_nodes[h as usize], self.get_callback_info(h)))
+    }
+
+    /// Get a price ascending or price descending iterator over the handles of all the Slab's leaves, without consuming the Slab
+    pub(crate) fn leaf_handles(&self, ascending: bool) -> impl Iterator<Item = NodeHandle> + '_ {
+        self.subtree_leaf_handles(self.root(), ascending)
+    }
+
+    /// Get a price ascending or price descending iterator over the handles of the leaves below a given node
+    pub(crate) fn subtree_leaf_handles(
+        &self,
+        sub_root: Option<NodeHandle>,
+        ascending: bool,
+    ) -> impl Iterator<Item = NodeHandle> + '_ {
+        let mut search_stack: Vec<NodeHandle> = sub_root.into_iter().collect();
+        std::iter::from_fn(move || {
+            while let Some(current) = search_stack.pop() {
+                match Node::from_handle(current) {
+                    Node::Inner => {
+                        let n = &self.inner_nodes[(!current) as usize];
+                        search_stack.push(n.children[ascending as usize]);
+                        search_stack.push(n.children[!ascending as usize]);
+                    }
+                    Node::Leaf => return Some(current),
+                }
+            }
+            None
+        })
+    }
+
+    /// Get the `(price, base_qty)` of up to `depth` price levels, in price ascending or price descending order.
+    ///
+    /// Only the [displayed quantity][`LeafNode::displayed_quantity`] of each order is counted. Quantities saturate at `u64::MAX`.
+    pub fn find_l2_depth(&self, depth: usize, ascending: bool) -> Vec<(u64, u64)> {
+        let mut result: Vec<(u64, u64)> = Vec::with_capacity(depth);
+        for h in self.leaf_handles(ascending) {
+            let leaf = &self.leaf_nodes[h as usize];
+            let price = leaf.price();
+            match result.last_mut() {
+                Some((level_price, level_qty)) if *level_price == price => {
+                    *level_qty = level_qty.saturating_add(leaf.displayed_quantity());
+                }
+                _ => {
+                    if result.len() == depth {
+                        break;
+                    }
+                    result.push((price, leaf.displayed_quantity()));
+                }
+            }
+        }
+        result
+    }
+
+    /// Get the `(order_id, price, base_qty, callback_info)` of up to `depth` orders, in price-time priority order when
+    /// `ascending` matches the Slab's side.
+    ///
+    /// Only the [displayed quantity][`LeafNode::displayed_quantity`] of each order is returned.
+    pub fn find_l3_depth(&self, depth: usize, ascending: bool) -> Vec<(u128, u64, u64, C)>
+    where
+        C: Clone,
+    {
+        self.leaf_handles(ascending)
+            .take(depth)
+            .map(|h| {
+                let leaf = &self.leaf_nodes[h as usize];
+                (
+                    leaf.order_id(),
+                    leaf.price(),
+                    leaf.displayed_quantity(),
+                    self.get_callback_info(h).clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Get the order ids of all the Slab's orders, in price ascending or price descending order
+    pub fn order_ids(&self, ascending: bool) -> Vec<u128> {
+        self.leaf_handles(ascending)
+            .map(|h| self.leaf_nodes[h as usize].order_id())
+            .collect()
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Get a price ascending or price descending iterator over the Slab's price levels, yielding `(price, level_qty, cumulative_qty)`.
+    ///
+    /// The cumulative quantity of a level includes every level yielded before it, which is the shape depth charts consume.
+    /// Only the [displayed quantity][`LeafNode::displayed_quantity`] of each order is counted. Quantities saturate at `u64::MAX`.
+    pub fn cumulative_depth(&self, ascending: bool) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        self.cumulative_levels(ascending, LeafNode::displayed_quantity)
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Get the `(price, level_qty, cumulative_qty)` of the Slab's price levels, counting `quantity` for each order
+    fn cumulative_levels(
+        &self,
+        ascending: bool,
+        quantity: fn(&LeafNode) -> u64,
+    ) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        let mut leaves = self
+            .leaf_handles(ascending)
+            .map(move |h| &self.leaf_nodes[h as usize])
+            .peekable();
+        let mut cumulative_qty = 0u64;
+        std::iter::from_fn(move || {
+            let first = leaves.next()?;
+            let price = first.price();
+            let mut level_qty = quantity(first);
+            while let Some(leaf) = leaves.next_if(|l| l.price() == price) {
+                level_qty = level_qty.saturating_add(quantity(leaf));
+            }
+            cumulative_qty = cumulative_qty.saturating_add(level_qty);
+            Some((price, level_qty, cumulative_qty))
+        })
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Get the price of the level at which the cumulative base quantity, walking from the best price, reaches `target_qty`.
+    ///
+    /// This is the limit price a sweep of `target_qty` would need. Since sweeps fill against entire orders, hidden quantities
+    /// are counted along with displayed ones. Returns `None` if the Slab doesn't hold enough quantity.
+    pub fn price_at_cumulative_qty(&self, target_qty: u64) -> Option<u64> {
+        let any_leaf = &self.leaf_nodes[self.find_min()? as usize];
+        let ascending = get_side_from_order_id(any_leaf.order_id()) == Side::Ask;
+        self.cumulative_levels(ascending, |leaf| leaf.base_quantity)
+            .find(|&(_, _, cumulative_qty)| cumulative_qty >= target_qty)
+            .map(|(price, _, _)| price)
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Get the order ids and base quantities of the `n` least aggressive orders, from the least aggressive one.
+    ///
+    /// Within a price level, newer orders are less aggressive since they have lower time priority.
+    pub fn worst_n_orders(&self, n: usize) -> Vec<(u128, u64)> {
+        let any_leaf = match self.find_min() {
+            Some(h) => &self.leaf_nodes[h as usize],
+            None => return vec![],
+        };
+        let ascending = get_side_from_order_id(any_leaf.order_id()) == Side::Bid;
+        self.leaf_handles(ascending)
+            .take(n)
+            .map(|h| {
+                let leaf = &self.leaf_nodes[h as usize];
+                (leaf.order_id(), leaf.base_quantity)
+            })
+            .collect()
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Count the orders with a base quantity strictly below the given threshold. Walks through the entire tree.
+    pub fn count_below_size(&self, threshold: u64) -> u32 {
+        self.leaf_handles(true)
+            .filter(|&h| self.leaf_nodes[h as usize].base_quantity < threshold)
+            .count() as u32
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Get the order ids of up to `max` orders with a base quantity strictly below the given threshold, in price ascending order.
+    ///
+    /// This is mainly useful to find dust orders after the market's minimum order size has been raised.
+    pub fn collect_below_size(&self, threshold: u64, max: usize) -> Vec<u128> {
+        self.leaf_handles(true)
+            .map(|h| &self.leaf_nodes[h as usize])
+            .filter(|leaf| leaf.base_quantity < threshold)
+            .map(|leaf| leaf.order_id())
+            .take(max)
+            .collect()
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Get the total quote value (FP32 price times base quantity, rounded down) of all the Slab's orders.
+    ///
+    /// Walks through the entire tree, which makes this an O(n) operation. Returns `None` if the sum overflows.
+    pub fn total_quote_liquidity(&self) -> Option<u64> {
+        self.leaf_handles(true).try_fold(0u64, |total, h| {
+            let leaf = &self.leaf_nodes[h as usize];
+            total.checked_add(fp32_mul_floor(leaf.base_quantity, leaf.price())?)
+        })
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Walk the Slab's orders in price ascending or price descending order until `base_qty` is reached, returning the
+    /// `(filled_base_qty, quote_qty)` a market order of that size would trade.
+    ///
+    /// The quote quantity of each order is its FP32 price times the filled quantity, rounded down. Both quantities saturate at
+    /// `u64::MAX`. The filled quantity is less than `base_qty` when the Slab doesn't hold enough.
+    pub fn vwap_for_base_qty(&self, base_qty: u64, ascending: bool) -> (u64, u64) {
+        let mut filled = 0u64;
+        let mut quote = 0u64;
+        for h in self.leaf_handles(ascending) {
+            if filled >= base_qty {
+                break;
+            }
+            let leaf = &self.leaf_nodes[h as usize];
+            let qty = leaf.base_quantity.min(base_qty - filled);
+            filled += qty;
+            quote = quote.saturating_add(fp32_mul_floor(qty, leaf.price()).unwrap_or(u64::MAX));
+        }
+        (filled, quote)
+    }
+
+    /// Get the total base quantity resting in the Slab that a taker order on the given `side` with the given limit price
+    /// could match, the Slab being the opposite side's tree.
+    ///
+    /// Orders are walked from the best price, stopping at the first one which doesn't cross. The total saturates at `u64::MAX`.
+    pub fn fillable_base_qty(&self, limit_price: u64, side: Side) -> u64 {
+        let crosses = |price: u64| match side {
+            Side::Bid => price <= limit_price,
+            Side::Ask => price >= limit_price,
+        };
+        self.leaf_handles(side == Side::Bid)
+            .map(|h| &self.leaf_nodes[h as usize])
+            .take_while(|leaf| crosses(leaf.price()))
+            .fold(0u64, |total, leaf| total.saturating_add(leaf.base_quantity))
+    }
+
     #[cfg(feature = "utils")]
     /// Get the current critbit's depth. Walks though the entire tree.
     pub fn get_depth(&self) -> usize {
@@ -474,6 +1026,195 @@ impl<'a, C> Slab<'a, C> {
         max_depth
     }
 
+    /// Checks a bounded part of the Slab's structural invariants, returning the cursor to resume from when the Slab wasn't fully walked.
+    ///
+    /// The header's node counts are checked on every call. The tree is then walked in key order from the `cursor`, checking each
+    /// node for a valid handle and consistent key prefixes, followed by the leaf and inner node free lists. Up to `max_nodes`
+    /// nodes are checked per call, on top of the nodes leading from the root to the cursor.
+    pub fn verify(
+        &self,
+        cursor: Option<VerifyCursor>,
+        max_nodes: u64,
+    ) -> Result<Option<VerifyCursor>, AoError> {
+        let h = &self.header;
+        let inner_node_count = h
+            .inner_node_bump_index
+            .checked_sub(h.inner_node_free_list_len)
+            .ok_or(AoError::MarketCorrupted)?;
+        if h.leaf_bump_index as usize > self.leaf_nodes.len()
+            || h.inner_node_bump_index as usize > self.inner_nodes.len()
+            || h.leaf_count.checked_add(h.leaf_free_list_len) != Some(h.leaf_bump_index)
+            || inner_node_count != h.leaf_count.saturating_sub(1)
+        {
+            return Err(AoError::MarketCorrupted);
+        }
+
+        let mut cursor = cursor.unwrap_or(VerifyCursor {
+            stage: VerifyStage::Tree,
+            position: 0,
+            checked: 0,
+        });
+        let mut budget = max_nodes;
+        loop {
+            let paused = match cursor.stage {
+                VerifyStage::Tree => self.verify_tree(cursor, &mut budget)?,
+                VerifyStage::LeafFreeList => self.verify_free_list(
+                    cursor,
+                    &mut budget,
+                    h.leaf_free_list_len,
+                    h.leaf_bump_index,
+                    |handle| self.leaf_nodes[handle as usize].base_quantity as u32,
+                )?,
+                VerifyStage::InnerFreeList => self.verify_free_list(
+                    cursor,
+                    &mut budget,
+                    h.inner_node_free_list_len,
+                    h.inner_node_bump_index,
+                    |index| self.inner_nodes[index as usize].prefix_len as u32,
+                )?,
+            };
+            if paused.is_some() {
+                return Ok(paused);
+            }
+            let (stage, position) = match cursor.stage {
+                VerifyStage::Tree => (VerifyStage::LeafFreeList, h.leaf_free_list_head),
+                VerifyStage::LeafFreeList => {
+                    (VerifyStage::InnerFreeList, h.inner_node_free_list_head)
+                }
+                VerifyStage::InnerFreeList => return Ok(None),
+            };
+            cursor = VerifyCursor {
+                stage,
+                position: position as u128,
+                checked: 0,
+            };
+        }
+    }
+
+    /// Walks the tree in key order from the cursor's key, returning the cursor to resume from once the budget runs out.
+    ///
+    /// Subtrees whose keys are all below the cursor were checked by previous calls and are skipped, which leaves the nodes
+    /// leading to the cursor to be walked again without counting against the budget.
+    fn verify_tree(
+        &self,
+        cursor: VerifyCursor,
+        budget: &mut u64,
+    ) -> Result<Option<VerifyCursor>, AoError> {
+        let h = &self.header;
+        let mut leaves_checked = cursor.checked;
+        let mut last_leaf_key = None;
+        // Each entry holds a node handle along with its parent's key, prefix length and the crit bit leading to the node
+        let mut stack = vec![];
+        if let Some(root) = self.root() {
+            stack.push((root, None::<(u128, u64, bool)>));
+        }
+        while let Some((handle, parent)) = stack.pop() {
+            let (key, prefix_len) = match Node::from_handle(handle) {
+                Node::Leaf => {
+                    if handle >= h.leaf_bump_index {
+                        return Err(AoError::MarketCorrupted);
+                    }
+                    (self.leaf_nodes[handle as usize].order_id(), 128)
+                }
+                Node::Inner => {
+                    if !handle >= h.inner_node_bump_index {
+                        return Err(AoError::MarketCorrupted);
+                    }
+                    let n = self.inner_nodes[(!handle) as usize];
+                    if n.prefix_len >= 128 {
+                        return Err(AoError::MarketCorrupted);
+                    }
+                    (n.key, n.prefix_len)
+                }
+            };
+            // Prefix lengths strictly increase down the tree, which also rules out cycles
+            if let Some((parent_key, parent_prefix_len, crit_bit)) = parent {
+                let prefix_mask = !(u128::MAX >> parent_prefix_len);
+                let crit_bit_mask = (1u128 << 127) >> parent_prefix_len;
+                if prefix_len <= parent_prefix_len
+                    || (key ^ parent_key) & prefix_mask != 0
+                    || (key & crit_bit_mask != 0) != crit_bit
+                {
+                    return Err(AoError::MarketCorrupted);
+                }
+            }
+            let (min_key, max_key) = match prefix_len {
+                128 => (key, key),
+                _ => (
+                    key & !(u128::MAX >> prefix_len),
+                    key | (u128::MAX >> prefix_len),
+                ),
+            };
+            if max_key < cursor.position {
+                continue;
+            }
+            if min_key >= cursor.position {
+                if *budget == 0 {
+                    return Ok(Some(VerifyCursor {
+                        position: min_key,
+                        checked: leaves_checked,
+                        ..cursor
+                    }));
+                }
+                *budget -= 1;
+            }
+            match Node::from_handle(handle) {
+                Node::Leaf => {
+                    if matches!(last_leaf_key, Some(k) if k >= key)
+                        || leaves_checked >= h.leaf_count
+                    {
+                        return Err(AoError::MarketCorrupted);
+                    }
+                    last_leaf_key = Some(key);
+                    leaves_checked += 1;
+                }
+                Node::Inner => {
+                    let n = self.inner_nodes[(!handle) as usize];
+                    stack.push((n.children[1], Some((n.key, n.prefix_len, true))));
+                    stack.push((n.children[0], Some((n.key, n.prefix_len, false))));
+                }
+            }
+        }
+        if leaves_checked != h.leaf_count {
+            return Err(AoError::MarketCorrupted);
+        }
+        Ok(None)
+    }
+
+    /// Walks a free list from the cursor's handle, returning the cursor to resume from once the budget runs out.
+    ///
+    /// `next` reads the handle of the free node following the given one.
+    fn verify_free_list<F: Fn(u32) -> u32>(
+        &self,
+        cursor: VerifyCursor,
+        budget: &mut u64,
+        free_list_len: u32,
+        bump_index: u32,
+        next: F,
+    ) -> Result<Option<VerifyCursor>, AoError> {
+        let mut handle = u32::try_from(cursor.position).map_err(|_| AoError::MarketCorrupted)?;
+        let mut checked = cursor.checked;
+        while checked < free_list_len {
+            if *budget == 0 {
+                return Ok(Some(VerifyCursor {
+                    position: handle as u128,
+                    checked,
+                    ..cursor
+                }));
+            }
+            if handle >= bump_index {
+                return Err(AoError::MarketCorrupted);
+            }
+            *budget -= 1;
+            checked += 1;
+            // The last free node's link isn't maintained
+            if checked < free_list_len {
+                handle = next(handle);
+            }
+        }
+        Ok(None)
+    }
+
     #[cfg(test)]
     fn dump(&self) {
         // println!("Callback info length {:?}", self.callback_info_len);
@@ -602,7 +1343,6 @@ impl<'a, C> Slab<'a, C> {
     /////////////////////////////////////////
     // Misc
 
-    #[cfg(any(test, feature = "utils"))]
     pub fn find_by_key(&self, search_key: u128) -> Option<NodeHandle> {
         let mut node_handle: NodeHandle = self.root()?;
         loop {
@@ -633,6 +1373,123 @@ impl<'a, C> Slab<'a, C> {
             }
         }
     }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Get the callback information of the order with the given order id, if it exists in the Slab
+    pub fn callback_info_by_order_id(&self, order_id: u128) -> Option<&C> {
+        self.find_by_key(order_id)
+            .map(|h| self.get_callback_info(h))
+    }
+
+    /// Get a copy of the order with the given order id along with its callback information, if it exists in the Slab
+    pub fn get_order(&self, order_id: u128) -> Option<(LeafNode, &C)> {
+        self.find_by_key(order_id)
+            .map(|h| (self.leaf_nodes[h as usize], self.get_callback_info(h)))
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Get the order right next to the given one in price-time priority, if there is one.
+    ///
+    /// When `more_aggressive` is set, this is the order which is matched right before the given one, otherwise the order which is matched right after it.
+    /// Returns `None` as well if the given order doesn't exist in the Slab.
+    pub fn neighbor(&self, order_id: u128, more_aggressive: bool) -> Option<LeafNode> {
+        // Asks are matched in key ascending order, bids in key descending order
+        let ascending = match get_side_from_order_id(order_id) {
+            Side::Bid => more_aggressive,
+            Side::Ask => !more_aggressive,
+        };
+        let mut node_handle: NodeHandle = self.root()?;
+        // The last subtree branching off the search path on the requested side contains the neighbor
+        let mut neighbor_subtree = None;
+        loop {
+            match Node::from_handle(node_handle) {
+                Node::Leaf => {
+                    if self.leaf_nodes[node_handle as usize].order_id() != order_id {
+                        return None;
+                    }
+                    break;
+                }
+                Node::Inner => {
+                    let n = self.inner_nodes[!node_handle as usize];
+                    let (child, crit_bit) = n.walk_down(order_id);
+                    if crit_bit != ascending {
+                        neighbor_subtree = Some(n.children[ascending as usize]);
+                    }
+                    node_handle = child;
+                }
+            }
+        }
+        self.subtree_leaf_handles(neighbor_subtree, ascending)
+            .next()
+            .map(|h| self.leaf_nodes[h as usize])
+    }
+
+    /// Find the root of the smallest subtree which contains all of the orders at a given price, if there are any.
+    pub(crate) fn find_price_subtree(&self, price: u64) -> Option<NodeHandle> {
+        let search_key = (price as u128) << 64;
+        let mut node_handle: NodeHandle = self.root()?;
+        loop {
+            match Node::from_handle(node_handle) {
+                Node::Leaf => {
+                    let n = &self.leaf_nodes[node_handle as usize];
+                    return (n.price() == price).then_some(node_handle);
+                }
+                Node::Inner => {
+                    let n = &self.inner_nodes[!node_handle as usize];
+                    let common_prefix_len = (search_key ^ n.key).leading_zeros();
+                    if n.prefix_len >= 64 {
+                        // All of the leaves below this node share the same price
+                        return (common_prefix_len >= 64).then_some(node_handle);
+                    }
+                    if common_prefix_len < n.prefix_len as u32 {
+                        return None;
+                    }
+                    node_handle = n.walk_down(search_key).0;
+                }
+            }
+        }
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Returns true if any order in the tree is priced at or beyond the given trigger price.
+    ///
+    /// For an asks tree this means a price greater than or equal to the trigger, for a bids tree a price lower than or equal to it.
+    /// Only the extremal branch of the tree is walked.
+    pub fn has_order_crossing(&self, price: u64, is_bid_tree: bool) -> bool {
+        let extremum = if is_bid_tree {
+            self.find_min()
+        } else {
+            self.find_max()
+        };
+        match extremum {
+            Some(h) => {
+                let leaf_price = self.leaf_nodes[h as usize].price();
+                if is_bid_tree {
+                    leaf_price <= price
+                } else {
+                    leaf_price >= price
+                }
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "utils"))]
+impl<'a, C: CallbackInfo> Slab<'a, C>
+where
+    <C as CallbackInfo>::CallbackId: PartialEq,
+{
+    /// Get the total base quantity of the orders resting at the given price which belong to the owner with the given callback id.
+    ///
+    /// Only the price's subtree is traversed. The total saturates at `u64::MAX`.
+    pub fn owner_qty_at_price(&self, callback_id: &C::CallbackId, price: u64) -> u64 {
+        self.subtree_leaf_handles(self.find_price_subtree(price), true)
+            .filter(|&h| self.get_callback_info(h).as_callback_id() == callback_id)
+            .fold(0u64, |total, h| {
+                total.saturating_add(self.leaf_nodes[h as usize].base_quantity)
+            })
+    }
 }
 
 impl<'queue, C: Clone> Slab<'queue, C> {
@@ -736,8 +1593,7 @@ mod tests {
 
         for trial in 0..10u64 {
             let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(10_000)];
-            bytes[0] = AccountTag::Asks as u8;
-            let mut slab = Slab::from_buffer(&mut bytes, AccountTag::Asks).unwrap();
+            let mut slab = Slab::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
 
             let mut model: BTreeMap<u128, (LeafNode, TestCallbackInfo)> = BTreeMap::new();
 
@@ -755,6 +1611,7 @@ mod tests {
                 let leaf = LeafNode {
                     key,
                     base_quantity: qty,
+                    ..Zeroable::zeroed()
                 };
                 #[cfg(target_os = "solana")]
                 println!("key : {:x}", key);
@@ -762,7 +1619,7 @@ mod tests {
                 println!("key : {:x}", leaf.order_id());
                 println!("owner : {:?}", &owner.to_bytes());
                 println!("{}", i);
-                let h = slab.insert_leaf(&leaf).unwrap().0;
+                let h = slab.insert_leaf(&leaf, true).unwrap().0;
                 let callback_info = TestCallbackInfo {
                     key: owner.to_bytes(),
                 };
@@ -814,6 +1671,1047 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_has_order_crossing() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+
+        assert!(!slab.has_order_crossing(0, false));
+        assert!(!slab.has_order_crossing(u64::MAX, true));
+
+        for (price, seq) in [(100u64, 0u64), (120, 1), (110, 2)] {
+            let key = ((price as u128) << 64) | (seq as u128);
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity: 1,
+                ..Zeroable::zeroed()
+            };
+            slab.insert_leaf(&leaf, false).unwrap();
+        }
+
+        assert!(slab.has_order_crossing(120, false));
+        assert!(slab.has_order_crossing(50, false));
+        assert!(!slab.has_order_crossing(121, false));
+
+        assert!(slab.has_order_crossing(100, true));
+        assert!(slab.has_order_crossing(200, true));
+        assert!(!slab.has_order_crossing(99, true));
+    }
+
+    #[test]
+    fn test_order_ids() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert!(slab.order_ids(true).is_empty());
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut keys = vec![];
+        for _ in 0..50 {
+            let key: u128 = rng.gen();
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity: rng.gen(),
+                ..Zeroable::zeroed()
+            };
+            slab.insert_leaf(&leaf, false).unwrap();
+            keys.push(key);
+        }
+        keys.sort_unstable();
+        assert_eq!(slab.order_ids(true), keys);
+        keys.reverse();
+        assert_eq!(slab.order_ids(false), keys);
+    }
+
+    #[test]
+    fn test_below_size() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.count_below_size(u64::MAX), 0);
+        assert!(slab.collect_below_size(u64::MAX, 10).is_empty());
+
+        let sizes = [5, 20, 9, 10, 1];
+        for (seq, &base_quantity) in sizes.iter().enumerate() {
+            let key = (((seq + 1) as u128) << 64) | seq as u128;
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            };
+            slab.insert_leaf(&leaf, false).unwrap();
+        }
+        let order_id = |seq: u128| ((seq + 1) << 64) | seq;
+
+        assert_eq!(slab.count_below_size(10), 3);
+        assert_eq!(
+            slab.collect_below_size(10, 10),
+            vec![order_id(0), order_id(2), order_id(4)]
+        );
+        assert_eq!(
+            slab.collect_below_size(10, 2),
+            vec![order_id(0), order_id(2)]
+        );
+        assert_eq!(slab.count_below_size(1), 0);
+    }
+
+    #[test]
+    fn test_cumulative_depth() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.cumulative_depth(true).next(), None);
+
+        let make_leaf = |price: u64, seq: u64, base_quantity: u64| {
+            let key = ((price as u128) << 64) | seq as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            }
+        };
+        slab.insert_leaf(&make_leaf(12, 0, 4), false).unwrap();
+        slab.insert_leaf(&make_leaf(10, 1, 5), false).unwrap();
+        slab.insert_leaf(&make_leaf(12, 2, 1), false).unwrap();
+        slab.insert_leaf(&make_leaf(10, 3, 7), false).unwrap();
+        slab.insert_leaf(&make_leaf(11, 4, 2), false).unwrap();
+
+        assert_eq!(
+            slab.cumulative_depth(true).collect::<Vec<_>>(),
+            vec![(10, 12, 12), (11, 2, 14), (12, 5, 19)]
+        );
+        assert_eq!(
+            slab.cumulative_depth(false).collect::<Vec<_>>(),
+            vec![(12, 5, 5), (11, 2, 7), (10, 12, 19)]
+        );
+
+        slab.insert_leaf(&make_leaf(13, 5, u64::MAX), false)
+            .unwrap();
+        assert_eq!(
+            slab.cumulative_depth(true).last(),
+            Some((13, u64::MAX, u64::MAX))
+        );
+    }
+
+    #[test]
+    fn test_find_l2_depth() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.find_l2_depth(10, true), vec![]);
+
+        let make_leaf = |price: u64, seq: u64, base_quantity: u64| {
+            let key = ((price as u128) << 64) | seq as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            }
+        };
+        slab.insert_leaf(&make_leaf(11, 0, 2), false).unwrap();
+        slab.insert_leaf(&make_leaf(10, 1, 5), false).unwrap();
+        slab.insert_leaf(&make_leaf(10, 2, 7), false).unwrap();
+        slab.insert_leaf(&make_leaf(12, 3, 4), false).unwrap();
+
+        // Both orders at 10 are aggregated into a single level
+        assert_eq!(
+            slab.find_l2_depth(10, true),
+            vec![(10, 12), (11, 2), (12, 4)]
+        );
+        assert_eq!(slab.find_l2_depth(2, true), vec![(10, 12), (11, 2)]);
+        assert_eq!(slab.find_l2_depth(2, false), vec![(12, 4), (11, 2)]);
+        assert_eq!(slab.find_l2_depth(0, true), vec![]);
+    }
+
+    #[test]
+    fn test_find_l3_depth() {
+        let make_leaf = |price: u64, seq: u64, base_quantity: u64, side: Side| {
+            let seq = match side {
+                Side::Bid => !seq,
+                Side::Ask => seq,
+            };
+            let key = ((price as u128) << 64) | seq as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            }
+        };
+        // Newer orders are inserted first, they must still come out after older ones at the same price
+        let orders = [(10, 3, 1), (11, 2, 2), (10, 1, 3), (10, 0, 4)];
+        for &side in [Side::Bid, Side::Ask].iter() {
+            let tag = match side {
+                Side::Bid => AccountTag::Bids,
+                Side::Ask => AccountTag::Asks,
+            };
+            let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+            let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, tag).unwrap();
+            assert_eq!(slab.find_l3_depth(10, true), vec![]);
+            for &(price, seq, base_quantity) in orders.iter() {
+                let leaf = make_leaf(price, seq, base_quantity, side);
+                let h = slab.insert_leaf(&leaf, false).unwrap().0;
+                *slab.get_callback_info_mut(h) = [seq as u8; 32];
+            }
+
+            let depth = slab.find_l3_depth(3, side == Side::Ask);
+            let summary = depth
+                .iter()
+                .map(|&(order_id, price, base_qty, callback_info)| {
+                    assert_eq!(order_id >> 64, price as u128);
+                    (price, base_qty, callback_info[0])
+                })
+                .collect::<Vec<_>>();
+            match side {
+                Side::Bid => assert_eq!(summary, vec![(11, 2, 2), (10, 4, 0), (10, 3, 1)]),
+                Side::Ask => assert_eq!(summary, vec![(10, 4, 0), (10, 3, 1), (10, 1, 3)]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_price_at_cumulative_qty() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.price_at_cumulative_qty(0), None);
+
+        let make_leaf = |price: u64, seq: u64, base_quantity: u64, side: Side| {
+            let seq = match side {
+                Side::Bid => !seq,
+                Side::Ask => seq,
+            };
+            let key = ((price as u128) << 64) | seq as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            }
+        };
+        let levels = [(10, 5), (11, 2), (10, 7), (12, 5)];
+        for (seq, &(price, base_quantity)) in levels.iter().enumerate() {
+            slab.insert_leaf(
+                &make_leaf(price, seq as u64, base_quantity, Side::Ask),
+                false,
+            )
+            .unwrap();
+        }
+        assert_eq!(slab.price_at_cumulative_qty(0), Some(10));
+        assert_eq!(slab.price_at_cumulative_qty(12), Some(10));
+        assert_eq!(slab.price_at_cumulative_qty(13), Some(11));
+        assert_eq!(slab.price_at_cumulative_qty(19), Some(12));
+        assert_eq!(slab.price_at_cumulative_qty(20), None);
+
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Bids).unwrap();
+        for (seq, &(price, base_quantity)) in levels.iter().enumerate() {
+            slab.insert_leaf(
+                &make_leaf(price, seq as u64, base_quantity, Side::Bid),
+                false,
+            )
+            .unwrap();
+        }
+        assert_eq!(slab.price_at_cumulative_qty(5), Some(12));
+        assert_eq!(slab.price_at_cumulative_qty(7), Some(11));
+        assert_eq!(slab.price_at_cumulative_qty(8), Some(10));
+        assert_eq!(slab.price_at_cumulative_qty(20), None);
+
+        // Hidden quantities are swept along with displayed ones
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        for (seq, &(price, base_quantity)) in levels.iter().enumerate() {
+            let leaf = LeafNode {
+                display_qty: 1,
+                ..make_leaf(price, seq as u64, base_quantity, Side::Ask)
+            };
+            slab.insert_leaf(&leaf, false).unwrap();
+        }
+        assert_eq!(slab.cumulative_depth(true).last(), Some((12, 1, 4)));
+        assert_eq!(slab.price_at_cumulative_qty(12), Some(10));
+        assert_eq!(slab.price_at_cumulative_qty(19), Some(12));
+    }
+
+    #[test]
+    fn test_worst_n_orders() {
+        let make_leaf = |price: u64, seq: u64, side: Side| {
+            let base_quantity = price * 10 + seq;
+            let seq = match side {
+                Side::Bid => !seq,
+                Side::Ask => seq,
+            };
+            let key = ((price as u128) << 64) | seq as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            }
+        };
+        let prices = [10, 12, 11, 10, 12];
+        for (side, tag) in [(Side::Bid, AccountTag::Bids), (Side::Ask, AccountTag::Asks)] {
+            let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+            let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, tag).unwrap();
+            assert!(slab.worst_n_orders(3).is_empty());
+            let leaves = prices
+                .iter()
+                .enumerate()
+                .map(|(seq, &price)| make_leaf(price, seq as u64, side))
+                .collect::<Vec<_>>();
+            for leaf in &leaves {
+                slab.insert_leaf(leaf, false).unwrap();
+            }
+            let expected = |seqs: &[usize]| {
+                seqs.iter()
+                    .map(|&s| (leaves[s].order_id(), leaves[s].base_quantity))
+                    .collect::<Vec<_>>()
+            };
+            // The newest order is the least aggressive one within a level
+            match side {
+                Side::Bid => {
+                    assert_eq!(slab.worst_n_orders(3), expected(&[3, 0, 2]));
+                }
+                Side::Ask => {
+                    assert_eq!(slab.worst_n_orders(3), expected(&[4, 1, 2]));
+                }
+            }
+            assert_eq!(slab.worst_n_orders(10).len(), 5);
+            assert!(slab.worst_n_orders(0).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_remove_by_key_owned_callback_info() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        let make_leaf = |key: u128| LeafNode {
+            #[cfg(target_os = "solana")]
+            key,
+            #[cfg(not(target_os = "solana"))]
+            key: [key as u64, (key >> 64) as u64],
+            base_quantity: 1,
+            ..Zeroable::zeroed()
+        };
+        for (key, callback_info) in [(1u128 << 64, [1; 32]), (2 << 64, [2; 32])] {
+            let (h, _) = slab.insert_leaf(&make_leaf(key), false).unwrap();
+            *slab.get_callback_info_mut(h) = callback_info;
+        }
+
+        let (leaf, callback_info) = slab.remove_by_key(2 << 64).unwrap();
+        assert_eq!(leaf.order_id(), 2 << 64);
+        // The returned callback info is owned, so the Slab can be mutated while it is held
+        let (h, _) = slab.insert_leaf(&make_leaf(3 << 64), false).unwrap();
+        *slab.get_callback_info_mut(h) = [3; 32];
+        assert_eq!(callback_info, [2; 32]);
+
+        assert_eq!(slab.remove_by_key(1 << 64).unwrap().1, [1; 32]);
+        assert_eq!(slab.remove_by_key(3 << 64).unwrap().1, [3; 32]);
+        assert!(slab.remove_by_key(3 << 64).is_none());
+    }
+
+    #[test]
+    fn test_grow_buffer() {
+        let previous_len = Slab::<[u8; 32]>::compute_allocation_size(10);
+        let mut bytes = vec![0u8; previous_len];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        let make_leaf = |key: u128| LeafNode {
+            #[cfg(target_os = "solana")]
+            key,
+            #[cfg(not(target_os = "solana"))]
+            key: [key as u64, (key >> 64) as u64],
+            base_quantity: key as u64 + 1,
+            ..Zeroable::zeroed()
+        };
+        let insert = |slab: &mut Slab<[u8; 32]>, i: u64| {
+            let (h, _) = slab
+                .insert_leaf(&make_leaf(((i as u128 * 7 % 23) << 64) | i as u128), false)
+                .unwrap();
+            *slab.get_callback_info_mut(h) = [i as u8; 32];
+        };
+        for i in 0..10 {
+            insert(&mut slab, i);
+        }
+        // Free up a leaf and an inner node to exercise the free lists
+        let removed = slab.order_ids(true)[4];
+        slab.remove_by_key(removed).unwrap();
+        assert!(slab.insert_leaf(&make_leaf(1 << 100), false).is_ok());
+        slab.remove_by_key(1 << 100).unwrap();
+        let orders = |slab: &Slab<[u8; 32]>| {
+            slab.leaf_handles(true)
+                .map(|h| {
+                    let leaf = &slab.leaf_nodes[h as usize];
+                    (
+                        leaf.order_id(),
+                        leaf.base_quantity,
+                        *slab.get_callback_info(h),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = orders(&slab);
+
+        bytes.resize(Slab::<[u8; 32]>::compute_allocation_size(25), 0xff);
+        Slab::<[u8; 32]>::grow_buffer(&mut bytes, previous_len).unwrap();
+        let mut slab = Slab::<[u8; 32]>::from_buffer(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(orders(&slab), expected);
+        assert_eq!(slab.verify(None, u64::MAX).unwrap(), None);
+
+        // The grown slab can hold the extra orders without booting
+        for i in 10..25 {
+            insert(&mut slab, i);
+        }
+        assert_eq!(slab.header.leaf_count, 24);
+        assert_eq!(slab.header.order_capacity, 25);
+        assert_eq!(slab.verify(None, u64::MAX).unwrap(), None);
+
+        // Slabs can't shrink
+        let len = bytes.len();
+        assert!(Slab::<[u8; 32]>::grow_buffer(&mut bytes, len + 1).is_err());
+
+        // Slabs which were already relocated can't be relocated again
+        assert!(Slab::<[u8; 32]>::grow_buffer(&mut bytes, previous_len).is_err());
+
+        // The previous length must match the slab's capacity
+        bytes.resize(Slab::<[u8; 32]>::compute_allocation_size(40), 0);
+        for bogus_len in [
+            Slab::<[u8; 32]>::compute_allocation_size(1),
+            Slab::<[u8; 32]>::compute_allocation_size(24),
+            len + 1,
+        ] {
+            assert_eq!(
+                Slab::<[u8; 32]>::grow_buffer(&mut bytes, bogus_len),
+                Err(ProgramError::InvalidArgument)
+            );
+        }
+        // Grown accounts can't be used before they are relocated
+        assert_eq!(
+            Slab::<[u8; 32]>::from_buffer(&mut bytes, AccountTag::Asks).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
+        Slab::<[u8; 32]>::grow_buffer(&mut bytes, len).unwrap();
+        let slab = Slab::<[u8; 32]>::from_buffer(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.header.leaf_count, 24);
+        assert_eq!(slab.verify(None, u64::MAX).unwrap(), None);
+    }
+
+    #[test]
+    fn test_total_quote_liquidity() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.total_quote_liquidity(), Some(0));
+
+        let make_leaf = |price: u64, seq: u64, base_quantity: u64| {
+            let key = ((price as u128) << 64) | seq as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            }
+        };
+        slab.insert_leaf(&make_leaf(10 << 32, 0, 5), false).unwrap();
+        slab.insert_leaf(&make_leaf(10 << 32, 1, 7), false).unwrap();
+        slab.insert_leaf(&make_leaf(3 << 31, 2, 3), false).unwrap();
+        // 10 * 5 + 10 * 7 + 1.5 * 3 rounded down
+        assert_eq!(slab.total_quote_liquidity(), Some(124));
+
+        slab.insert_leaf(&make_leaf(u64::MAX, 3, u64::MAX), false)
+            .unwrap();
+        assert_eq!(slab.total_quote_liquidity(), None);
+    }
+
+    #[test]
+    fn test_vwap_for_base_qty() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.vwap_for_base_qty(10, true), (0, 0));
+
+        let make_leaf = |price: u64, seq: u64, base_quantity: u64| {
+            let key = ((price as u128) << 64) | seq as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            }
+        };
+        slab.insert_leaf(&make_leaf(12 << 32, 0, 4), false).unwrap();
+        slab.insert_leaf(&make_leaf(10 << 32, 1, 5), false).unwrap();
+        slab.insert_leaf(&make_leaf(3 << 31, 2, 3), false).unwrap();
+
+        assert_eq!(slab.vwap_for_base_qty(0, true), (0, 0));
+        // 1.5 * 2 = 3
+        assert_eq!(slab.vwap_for_base_qty(2, true), (2, 3));
+        // 1.5 * 3 + 10 * 5 rounded down, stopping exactly at the end of a level
+        assert_eq!(slab.vwap_for_base_qty(8, true), (8, 54));
+        // 1.5 * 3 + 10 * 5 + 12 * 2 rounded down
+        assert_eq!(slab.vwap_for_base_qty(10, true), (10, 78));
+        // The slab only holds 12
+        assert_eq!(slab.vwap_for_base_qty(100, true), (12, 102));
+        // 12 * 4 + 10 * 2, walking from the highest price
+        assert_eq!(slab.vwap_for_base_qty(6, false), (6, 68));
+    }
+
+    #[test]
+    fn test_fillable_base_qty() {
+        let make_leaf = |price: u64, seq: u64, base_quantity: u64, side: Side| {
+            let seq = match side {
+                Side::Bid => !seq,
+                Side::Ask => seq,
+            };
+            let key = ((price as u128) << 64) | seq as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            }
+        };
+        let levels = [(10, 5), (11, 2), (10, 7), (12, 4)];
+
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut asks = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(asks.fillable_base_qty(u64::MAX, Side::Bid), 0);
+        for (seq, &(price, base_quantity)) in levels.iter().enumerate() {
+            asks.insert_leaf(
+                &make_leaf(price, seq as u64, base_quantity, Side::Ask),
+                false,
+            )
+            .unwrap();
+        }
+        assert_eq!(asks.fillable_base_qty(9, Side::Bid), 0);
+        // Crossing exactly at the limit price
+        assert_eq!(asks.fillable_base_qty(10, Side::Bid), 12);
+        assert_eq!(asks.fillable_base_qty(11, Side::Bid), 14);
+        assert_eq!(asks.fillable_base_qty(12, Side::Bid), 18);
+        assert_eq!(asks.fillable_base_qty(13, Side::Bid), 18);
+
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut bids = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Bids).unwrap();
+        for (seq, &(price, base_quantity)) in levels.iter().enumerate() {
+            bids.insert_leaf(
+                &make_leaf(price, seq as u64, base_quantity, Side::Bid),
+                false,
+            )
+            .unwrap();
+        }
+        assert_eq!(bids.fillable_base_qty(13, Side::Ask), 0);
+        assert_eq!(bids.fillable_base_qty(12, Side::Ask), 4);
+        // The 10 level sits just past the limit price
+        assert_eq!(bids.fillable_base_qty(11, Side::Ask), 6);
+        assert_eq!(bids.fillable_base_qty(10, Side::Ask), 18);
+        assert_eq!(bids.fillable_base_qty(0, Side::Ask), 18);
+    }
+
+    #[test]
+    fn test_price_extremes() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.price_extremes(), None);
+
+        let make_leaf = |key: u128| LeafNode {
+            #[cfg(target_os = "solana")]
+            key,
+            #[cfg(not(target_os = "solana"))]
+            key: [key as u64, (key >> 64) as u64],
+            base_quantity: 1,
+            ..Zeroable::zeroed()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let prices = (0..50)
+            .map(|_| rng.gen_range(1..1000u64))
+            .collect::<Vec<_>>();
+
+        // Asks
+        for (seq, &price) in prices.iter().enumerate() {
+            slab.insert_leaf(&make_leaf(((price as u128) << 64) | seq as u128), false)
+                .unwrap();
+        }
+        let (min, max) = (*prices.iter().min().unwrap(), *prices.iter().max().unwrap());
+        assert_eq!(slab.price_extremes(), Some((min, max)));
+        assert_eq!(
+            slab.price_extremes().map(|(best, _)| best),
+            slab.find_min().map(|h| slab.leaf_nodes[h as usize].price())
+        );
+
+        // Bids
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Bids).unwrap();
+        for (seq, &price) in prices.iter().enumerate() {
+            slab.insert_leaf(
+                &make_leaf(((price as u128) << 64) | !(seq as u64) as u128),
+                false,
+            )
+            .unwrap();
+        }
+        assert_eq!(slab.price_extremes(), Some((max, min)));
+
+        // A single order is both the best and the worst
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        slab.insert_leaf(&make_leaf(42 << 64), false).unwrap();
+        assert_eq!(slab.price_extremes(), Some((42, 42)));
+    }
+
+    #[test]
+    fn test_neighbor() {
+        let make_leaf = |key: u128| LeafNode {
+            #[cfg(target_os = "solana")]
+            key,
+            #[cfg(not(target_os = "solana"))]
+            key: [key as u64, (key >> 64) as u64],
+            base_quantity: 1,
+            ..Zeroable::zeroed()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for tag in [AccountTag::Asks, AccountTag::Bids] {
+            let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+            let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, tag).unwrap();
+            assert!(slab.neighbor(0, true).is_none());
+
+            for seq in 0..50u64 {
+                let price = rng.gen_range(1..10u64) as u128;
+                let key = match tag {
+                    AccountTag::Bids => (price << 64) | (!seq as u128),
+                    _ => (price << 64) | seq as u128,
+                };
+                slab.insert_leaf(&make_leaf(key), false).unwrap();
+            }
+
+            // Orders in matching priority
+            let order_ids = slab.order_ids(tag != AccountTag::Bids);
+            for (i, &order_id) in order_ids.iter().enumerate() {
+                let better = slab.neighbor(order_id, true).map(|l| l.order_id());
+                let worse = slab.neighbor(order_id, false).map(|l| l.order_id());
+                assert_eq!(better, i.checked_sub(1).map(|j| order_ids[j]));
+                assert_eq!(worse, order_ids.get(i + 1).copied());
+            }
+
+            // Unknown orders don't have neighbors
+            assert!(slab.neighbor(order_ids[0] ^ (1 << 62), true).is_none());
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert!(matches!(slab.verify(None, 10), Ok(None)));
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..50 {
+            let key: u128 = rng.gen();
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity: 1,
+                ..Zeroable::zeroed()
+            };
+            slab.insert_leaf(&leaf, false).unwrap();
+        }
+        let order_ids = slab.order_ids(true);
+        for order_id in &order_ids[..10] {
+            slab.remove_by_key(*order_id).unwrap();
+        }
+
+        // 40 leaves and 39 inner nodes, followed by 10 free leaves and 10 free inner nodes
+        assert!(matches!(slab.verify(None, u64::MAX), Ok(None)));
+        let mut cursor = None;
+        let mut stages = vec![];
+        while let Some(next_cursor) = slab.verify(cursor, 10).unwrap() {
+            assert_ne!(Some(next_cursor), cursor);
+            stages.push(next_cursor.stage);
+            cursor = Some(next_cursor);
+        }
+        assert_eq!(
+            stages,
+            [
+                [VerifyStage::Tree; 7].as_slice(),
+                &[VerifyStage::LeafFreeList, VerifyStage::InnerFreeList]
+            ]
+            .concat()
+        );
+
+        // Inconsistent free list
+        slab.header.leaf_free_list_len += 1;
+        assert!(matches!(
+            slab.verify(None, 0),
+            Err(AoError::MarketCorrupted)
+        ));
+        slab.header.leaf_free_list_len -= 1;
+
+        // Free leaf out of bounds, only found by walking the free list
+        let head = slab.header.leaf_free_list_head as usize;
+        let next = slab.leaf_nodes[head].base_quantity;
+        slab.leaf_nodes[head].base_quantity = slab.header.leaf_bump_index as u64;
+        assert!(matches!(slab.verify(None, 79), Ok(Some(_))));
+        assert!(matches!(
+            slab.verify(None, u64::MAX),
+            Err(AoError::MarketCorrupted)
+        ));
+        slab.leaf_nodes[head].base_quantity = next;
+
+        // Inconsistent prefixes
+        let root = slab.root().unwrap();
+        let child = slab.inner_nodes[(!root) as usize].children[0];
+        assert!(matches!(Node::from_handle(child), Node::Inner));
+        slab.inner_nodes[(!child) as usize].prefix_len = 0;
+        assert!(matches!(
+            slab.verify(None, u64::MAX),
+            Err(AoError::MarketCorrupted)
+        ));
+    }
+
+    #[test]
+    fn test_find_price_subtree() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(200)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.find_price_subtree(0), None);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut keys = vec![];
+        for _ in 0..100 {
+            let key = ((rng.gen_range(0..20u64) as u128) << 64) | rng.gen::<u64>() as u128;
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity: 1,
+                ..Zeroable::zeroed()
+            };
+            slab.insert_leaf(&leaf, false).unwrap();
+            keys.push(key);
+        }
+        keys.sort_unstable();
+        for price in 0..21u64 {
+            let expected = keys
+                .iter()
+                .copied()
+                .filter(|k| (k >> 64) as u64 == price)
+                .collect::<Vec<_>>();
+            let found = slab
+                .subtree_leaf_handles(slab.find_price_subtree(price), true)
+                .map(|h| slab.leaf_nodes[h as usize].order_id())
+                .collect::<Vec<_>>();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_from_buffer_zeroed() {
+        let size = Slab::<[u8; 32]>::compute_allocation_size(10);
+        let mut bytes = vec![0xffu8; size];
+        let slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Bids).unwrap();
+        assert_eq!(slab.header.leaf_count, 0);
+        assert_eq!(slab.root(), None);
+        assert!(Slab::<[u8; 32]>::from_buffer(&mut bytes, AccountTag::Bids).is_ok());
+
+        assert!(
+            Slab::<[u8; 32]>::from_buffer_zeroed(&mut vec![0; size + 1], AccountTag::Bids).is_err()
+        );
+        assert!(Slab::<[u8; 32]>::from_buffer_zeroed(&mut [0; 10], AccountTag::Bids).is_err());
+    }
+
+    #[test]
+    fn test_peek_tag() {
+        let size = Slab::<[u8; 32]>::compute_allocation_size(10);
+        let mut asks = vec![0u8; size];
+        let mut bids = vec![0u8; size];
+        assert_eq!(AccountTag::peek(&asks), Some(AccountTag::Uninitialized));
+        Slab::<[u8; 32]>::initialize(&mut asks, &mut bids).unwrap();
+        assert_eq!(AccountTag::peek(&asks), Some(AccountTag::Asks));
+        assert_eq!(AccountTag::peek(&bids), Some(AccountTag::Bids));
+        assert_eq!(AccountTag::peek(&[]), None);
+        assert_eq!(AccountTag::peek(&[1]), None);
+    }
+
+    #[test]
+    fn test_slab_version() {
+        assert_eq!(LeafNode::LEN, 48);
+        let size = Slab::<[u8; 32]>::compute_allocation_size(10);
+        let mut asks = vec![0u8; size];
+        let mut bids = vec![0u8; size];
+        Slab::<[u8; 32]>::initialize(&mut asks, &mut bids).unwrap();
+        assert_eq!(asks[SLAB_VERSION_OFFSET], SLAB_VERSION);
+        assert_eq!(legacy_slab_version(&asks), None);
+        assert!(Slab::<[u8; 32]>::from_buffer(&mut asks, AccountTag::Asks).is_ok());
+
+        // Slabs laid out by earlier versions are rejected
+        let mut legacy = vec![0u8; size];
+        legacy[0] = AccountTag::Bids as u8;
+        assert_eq!(legacy_slab_version(&legacy), Some(0));
+        assert_eq!(
+            Slab::<[u8; 32]>::from_buffer(&mut legacy, AccountTag::Bids).err(),
+            Some(AoError::SlabVersionMismatch.into())
+        );
+
+        // Uninitialized accounts aren't legacy slabs
+        assert_eq!(legacy_slab_version(&vec![0u8; size]), None);
+    }
+
+    #[test]
+    fn test_prune_legacy() {
+        let capacity = 30;
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(capacity)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut orders = vec![];
+        for i in 0..20u8 {
+            let key: u128 = rng.gen();
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity: i as u64 + 1,
+                ..Zeroable::zeroed()
+            };
+            let h = slab.insert_leaf(&leaf, false).unwrap().0;
+            *slab.get_callback_info_mut(h) = [i; 32];
+            orders.push((key, i as u64 + 1, [i; 32]));
+        }
+
+        // Lay out the same slab with the version 0 layout, whose header is 32 bytes long and whose leaves are 24 bytes long
+        let mut legacy = vec![AccountTag::Asks as u8, 0, 0, 0, 0, 0, 0, 0];
+        legacy.extend_from_slice(&bytemuck::bytes_of(&*slab.header)[..LEGACY_HEADER_LEN]);
+        for leaf in slab.leaf_nodes.iter() {
+            legacy.extend_from_slice(&bytemuck::bytes_of(leaf)[..LEGACY_LEAF_LEN]);
+        }
+        legacy.extend_from_slice(bytemuck::cast_slice(slab.inner_nodes));
+        legacy.extend_from_slice(bytemuck::cast_slice(slab.callback_infos));
+        assert_eq!(legacy_slab_version(&legacy), Some(0));
+        assert_eq!(legacy_slab_version(&bytes), None);
+        assert!(Slab::<[u8; 32]>::from_buffer(&mut legacy, AccountTag::Asks).is_err());
+        assert!(Slab::<[u8; 32]>::prune_legacy(&mut legacy, AccountTag::Bids, 1, true).is_err());
+
+        orders.sort_unstable_by_key(|o| std::cmp::Reverse(o.0));
+        let mut removed =
+            Slab::<[u8; 32]>::prune_legacy(&mut legacy, AccountTag::Asks, 5, false).unwrap();
+        assert_eq!(removed, orders[..5]);
+        assert_eq!(legacy_slab_version(&legacy), Some(0));
+        assert_eq!(legacy_slab_leaf_count(&legacy), Some(15));
+
+        removed =
+            Slab::<[u8; 32]>::prune_legacy(&mut legacy, AccountTag::Asks, 100, false).unwrap();
+        assert_eq!(removed, orders[5..]);
+
+        // The emptied slab is laid out again with the current version
+        assert_eq!(legacy_slab_version(&legacy), None);
+        let mut slab = Slab::<[u8; 32]>::from_buffer(&mut legacy, AccountTag::Asks).unwrap();
+        assert_eq!(slab.root(), None);
+        let leaf = LeafNode {
+            #[cfg(target_os = "solana")]
+            key: orders[0].0,
+            #[cfg(not(target_os = "solana"))]
+            key: [orders[0].0 as u64, (orders[0].0 >> 64) as u64],
+            base_quantity: 1,
+            ..Zeroable::zeroed()
+        };
+        slab.insert_leaf(&leaf, false).unwrap();
+        assert!(slab.find_by_key(orders[0].0).is_some());
+
+        // Empty legacy slabs are laid out again without removing any order
+        let mut empty = vec![0u8; legacy.len()];
+        empty[0] = AccountTag::Bids as u8;
+        assert_eq!(legacy_slab_leaf_count(&empty), Some(0));
+        assert!(
+            Slab::<[u8; 32]>::prune_legacy(&mut empty, AccountTag::Bids, 0, true)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(legacy_slab_version(&empty), None);
+        assert!(Slab::<[u8; 32]>::from_buffer(&mut empty, AccountTag::Bids).is_ok());
+    }
+
+    #[test]
+    fn test_callback_info_by_order_id() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut keys = vec![];
+        for i in 0..20u8 {
+            let key: u128 = rng.gen();
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity: 1,
+                ..Zeroable::zeroed()
+            };
+            let h = slab.insert_leaf(&leaf, false).unwrap().0;
+            *slab.get_callback_info_mut(h) = [i; 32];
+            keys.push(key);
+        }
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(slab.callback_info_by_order_id(*key), Some(&[i as u8; 32]));
+        }
+        assert_eq!(slab.callback_info_by_order_id(keys[0] ^ 1), None);
+    }
+
+    #[test]
+    fn test_iter_with_callbacks() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert_eq!(slab.iter_with_callbacks(true).next(), None);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for i in 0..20u8 {
+            let key: u128 = rng.gen();
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity: i as u64,
+                ..Zeroable::zeroed()
+            };
+            let h = slab.insert_leaf(&leaf, false).unwrap().0;
+            *slab.get_callback_info_mut(h) = [i; 32];
+        }
+
+        let expected = slab.traverse();
+        let ascending = slab
+            .iter_with_callbacks(true)
+            .map(|(leaf, callback_info)| (leaf, *callback_info))
+            .collect::<Vec<_>>();
+        assert_eq!(ascending, expected);
+        let mut descending = slab
+            .iter_with_callbacks(false)
+            .map(|(leaf, callback_info)| (leaf, *callback_info))
+            .collect::<Vec<_>>();
+        descending.reverse();
+        assert_eq!(descending, expected);
+    }
+
+    #[test]
+    fn test_get_order() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        assert!(slab.get_order(0).is_none());
+
+        let make_leaf = |price: u64, seq: u64, base_quantity: u64| {
+            let key = ((price as u128) << 64) | seq as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            }
+        };
+        for (seq, &price) in [10, 12, 10, 11].iter().enumerate() {
+            let leaf = make_leaf(price, seq as u64, 100 + seq as u64);
+            let h = slab.insert_leaf(&leaf, false).unwrap().0;
+            *slab.get_callback_info_mut(h) = [seq as u8; 32];
+        }
+
+        let order_id = (10u128 << 64) | 2;
+        let (leaf, callback_info) = slab.get_order(order_id).unwrap();
+        assert_eq!(leaf.order_id(), order_id);
+        assert_eq!(leaf.price(), 10);
+        assert_eq!(leaf.base_quantity, 102);
+        assert_eq!(callback_info, &[2; 32]);
+
+        // Same price, unknown sequence number
+        assert!(slab.get_order((10u128 << 64) | 4).is_none());
+        assert!(slab.get_order((13u128 << 64) | 2).is_none());
+    }
+
+    #[test]
+    fn test_owner_qty_at_price() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+        let (alice, bob) = ([1; 32], [2; 32]);
+        assert_eq!(slab.owner_qty_at_price(&alice, 10), 0);
+
+        let orders = [
+            (10, alice, 5),
+            (10, bob, 7),
+            (10, alice, 11),
+            (11, alice, 13),
+            (9, bob, 17),
+        ];
+        for (seq, &(price, owner, base_quantity)) in orders.iter().enumerate() {
+            let key = ((price as u128) << 64) | seq as u128;
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            };
+            let h = slab.insert_leaf(&leaf, false).unwrap().0;
+            *slab.get_callback_info_mut(h) = owner;
+        }
+
+        assert_eq!(slab.owner_qty_at_price(&alice, 10), 16);
+        assert_eq!(slab.owner_qty_at_price(&bob, 10), 7);
+        assert_eq!(slab.owner_qty_at_price(&alice, 11), 13);
+        assert_eq!(slab.owner_qty_at_price(&alice, 9), 0);
+        assert_eq!(slab.owner_qty_at_price(&bob, 12), 0);
+    }
+
+    #[test]
+    fn test_insert_duplicate_key() {
+        let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(100)];
+        let mut slab = Slab::<[u8; 32]>::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
+
+        let make_leaf = |key: u128, base_quantity: u64| LeafNode {
+            #[cfg(target_os = "solana")]
+            key,
+            #[cfg(not(target_os = "solana"))]
+            key: [key as u64, (key >> 64) as u64],
+            base_quantity,
+            ..Zeroable::zeroed()
+        };
+        let key = (42u128 << 64) | 7;
+        slab.insert_leaf(&make_leaf(key, 10), false).unwrap();
+        slab.insert_leaf(&make_leaf(key + 1, 20), false).unwrap();
+
+        // Rejecting duplicates leaves the existing order untouched
+        let err = slab.insert_leaf(&make_leaf(key, 30), false).unwrap_err();
+        assert!(matches!(err, AoError::DuplicateOrderId));
+        let h = slab.find_by_key(key).unwrap();
+        assert_eq!(slab.leaf_nodes[h as usize].base_quantity, 10);
+        assert_eq!(slab.header.leaf_count, 2);
+
+        // Clobbering replaces the existing order and returns it
+        let (_, old_leaf) = slab.insert_leaf(&make_leaf(key, 30), true).unwrap();
+        assert_eq!(old_leaf, Some(make_leaf(key, 10)));
+        let h = slab.find_by_key(key).unwrap();
+        assert_eq!(slab.leaf_nodes[h as usize].base_quantity, 30);
+        assert_eq!(slab.header.leaf_count, 2);
+    }
+
     #[test]
     #[cfg(not(feature = "quick-test"))]
     fn simulate_operations() {
@@ -821,8 +2719,7 @@ mod tests {
         use std::collections::BTreeMap;
 
         let mut bytes = vec![0u8; Slab::<[u8; 32]>::compute_allocation_size(10_000)];
-        bytes[0] = AccountTag::Asks as u8;
-        let mut slab = Slab::from_buffer(&mut bytes, AccountTag::Asks).unwrap();
+        let mut slab = Slab::from_buffer_zeroed(&mut bytes, AccountTag::Asks).unwrap();
 
         let mut model: BTreeMap<u128, (LeafNode, [u8; 32])> = BTreeMap::new();
 
@@ -885,8 +2782,9 @@ mod tests {
                                 k
                             },
                             base_quantity: qty,
+                            ..Zeroable::zeroed()
                         };
-                        let (leaf_h, old_leaf) = slab.insert_leaf(&leaf).unwrap();
+                        let (leaf_h, old_leaf) = slab.insert_leaf(&leaf, true).unwrap();
                         let old_owner = *slab.get_callback_info(leaf_h);
                         *slab.get_callback_info_mut(leaf_h) = owner;
 
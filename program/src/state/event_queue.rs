@@ -17,6 +17,7 @@ pub use crate::utils::get_spread;
 use super::{AccountTag, Side};
 
 #[derive(Clone, Zeroable, Pod, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 /// Represents an order being filled, a match between two parties.
 pub struct FillEvent {
@@ -35,6 +36,10 @@ pub struct FillEvent {
     pub maker_order_id: [u64; 2],
     /// The total base size of the transaction
     pub base_size: u64,
+    /// The price at which the trade was executed, as a FP32.
+    ///
+    /// Fill events of version 0 event queues don't carry it, see [`EVENT_QUEUE_VERSION`].
+    pub price: u64,
 }
 
 impl FillEvent {
@@ -43,14 +48,32 @@ impl FillEvent {
 }
 
 #[derive(Clone, Zeroable, Pod, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 /// Represents an order being modified or yanked from the orderbook without being matched
+///
+/// Its size must match the size of a [`FillEvent`].
 pub struct OutEvent {
     /// The u8 representation for an [`AccountTag`] enum
     pub tag: u8,
     /// The u8 representation for a [`Side`] enum
     pub side: u8,
-    pub(crate) _padding: [u8; 14],
+    /// Set to 1 when the order was cancelled by a [`SelfTradeBehavior::CancelProvide`][`crate::state::SelfTradeBehavior::CancelProvide`]
+    /// or [`SelfTradeBehavior::CancelBoth`][`crate::state::SelfTradeBehavior::CancelBoth`] match, that is when `reason` is
+    /// [`OutReason::SelfTrade`].
+    ///
+    /// The callback info of the taker order which caused the cancellation is then stored alongside the event.
+    pub self_trade: u8,
+    /// Set to 1 when the order was removed because it had expired when a taker order reached it, that is when `reason` is
+    /// [`OutReason::Expired`]
+    pub expired: u8,
+    /// Set to 1 when the order was removed from the orderbook.
+    ///
+    /// When set to 0, the order was only reduced in place and `base_size` is the size of the reduction.
+    pub delete: u8,
+    /// The u8 representation for the [`OutReason`] the order left the orderbook
+    pub reason: u8,
+    pub(crate) _padding: [u8; 10],
     /// The order id of the maker order
     #[cfg(target_os = "solana")]
     pub order_id: u128,
@@ -59,6 +82,50 @@ pub struct OutEvent {
     pub order_id: [u64; 2],
     /// The total base size of the transaction
     pub base_size: u64,
+    pub(crate) _padding_end: [u8; 8],
+}
+
+impl OutEvent {
+    /// Creates an out event for an order which was removed from the orderbook.
+    ///
+    /// The `self_trade` and `expired` flags are derived from the `reason`, so that they can't disagree with it.
+    pub fn new(side: Side, order_id: u128, base_size: u64, reason: OutReason) -> Self {
+        Self {
+            tag: EventTag::Out as u8,
+            side: side as u8,
+            self_trade: (reason == OutReason::SelfTrade) as u8,
+            expired: (reason == OutReason::Expired) as u8,
+            delete: 1,
+            reason: reason as u8,
+            _padding: [0; 10],
+            #[cfg(target_os = "solana")]
+            order_id,
+            #[cfg(not(target_os = "solana"))]
+            order_id: [order_id as u64, (order_id >> 64) as u64],
+            base_size,
+            _padding_end: [0; 8],
+        }
+    }
+}
+
+#[derive(Clone, Zeroable, Pod, Copy, Debug, PartialEq)]
+#[repr(C)]
+/// Represents a price level being fully swept by a taker order.
+///
+/// This event is only emitted for markets which have the [`MarketFlag::EmitLevelCleared`][`crate::state::market_state::MarketFlag::EmitLevelCleared`] flag set.
+/// It is pushed after the fill and out events of the level's orders, and doesn't carry any callback information.
+/// Its size must match the size of a [`FillEvent`].
+pub struct LevelClearedEvent {
+    /// The u8 representation for an [`AccountTag`] enum
+    pub tag: u8,
+    /// The u8 representation for the [`Side`] of the cleared price level
+    pub side: u8,
+    pub(crate) _padding: [u8; 6],
+    /// The price of the cleared level
+    pub price: u64,
+    /// The total base quantity matched against the level by the taker order
+    pub base_size: u64,
+    pub(crate) _padding_end: [u8; 24],
 }
 
 #[derive(PartialEq, Debug)]
@@ -68,6 +135,8 @@ pub enum EventRef<'a, C> {
     Fill(FillEventRef<'a, C>),
     #[allow(missing_docs)]
     Out(OutEventRef<'a, C>),
+    #[allow(missing_docs)]
+    LevelCleared(LevelClearedEventRef<'a>),
 }
 
 #[derive(PartialEq, Debug)]
@@ -88,17 +157,53 @@ pub struct OutEventRef<'a, C> {
     pub event: &'a OutEvent,
     #[allow(missing_docs)]
     pub callback_info: &'a C,
+    /// The callback info of the taker order, for orders cancelled by a self-trade
+    pub taker_callback_info: Option<&'a C>,
+}
+
+#[derive(PartialEq, Debug)]
+/// An immutable reference to a LevelCleared event in the EventQueue.
+pub struct LevelClearedEventRef<'a> {
+    #[allow(missing_docs)]
+    pub event: &'a LevelClearedEvent,
 }
 
-#[derive(FromPrimitive, Clone, Copy, CheckedBitPattern, NoUninit)]
+#[derive(FromPrimitive, Clone, Copy, CheckedBitPattern, NoUninit, PartialEq, Debug)]
 #[repr(u8)]
-pub(crate) enum EventTag {
+#[allow(missing_docs)]
+/// Describes the type of an event in the queue
+pub enum EventTag {
     Fill,
     Out,
+    LevelCleared,
 }
 
+#[derive(FromPrimitive, Clone, Copy, CheckedBitPattern, NoUninit, PartialEq, Debug)]
+#[repr(u8)]
+/// Describes why an order was taken out of the orderbook
+pub enum OutReason {
+    /// The order was cancelled, or reduced, by its owner
+    Cancel,
+    /// The order was booted out of a full orderbook by a better priced order
+    Booted,
+    /// The order was cancelled by a self-trade
+    SelfTrade,
+    /// The order had expired when a taker order reached it
+    Expired,
+    /// The order's remaining quantity fell below the market's minimum order size after being matched
+    MinSizePurge,
+}
+
+/// All events are stored in the queue as [`FillEvent`] slots, and cast back and forth with `bytemuck`.
+///
+/// Every event type must therefore have exactly the same size as a [`FillEvent`], which is checked at compile time.
 pub(crate) type GenericEvent = FillEvent;
 
+const _: () = assert!(std::mem::size_of::<OutEvent>() == GenericEvent::LEN);
+const _: () = assert!(std::mem::size_of::<LevelClearedEvent>() == GenericEvent::LEN);
+// Changing the size of events requires a new EVENT_QUEUE_VERSION
+const _: () = assert!(GenericEvent::LEN == 48);
+
 pub(crate) trait Event {
     fn to_generic(&mut self) -> &GenericEvent;
 }
@@ -117,6 +222,13 @@ impl Event for OutEvent {
     }
 }
 
+impl Event for LevelClearedEvent {
+    fn to_generic(&mut self) -> &GenericEvent {
+        self.tag = EventTag::LevelCleared as u8;
+        bytemuck::cast_ref(self)
+    }
+}
+
 ////////////////////////////////////////////////////
 // Event Queue
 
@@ -129,6 +241,11 @@ pub struct EventQueueHeader {
     /// The current event queue length
     pub count: u64,
     seq_num: u64,
+    /// The byte size of the callback information objects stored in the queue, set at creation
+    pub callback_info_size: u32,
+    /// The layout version of the event queue account, see [`EVENT_QUEUE_VERSION`]
+    pub version: u8,
+    _padding: [u8; 3],
 }
 
 impl EventQueueHeader {
@@ -136,6 +253,73 @@ impl EventQueueHeader {
     pub const LEN: usize = std::mem::size_of::<Self>();
 }
 
+/// The current layout version of event queue accounts.
+///
+/// Version 1 added [`EventQueueHeader::callback_info_size`] and [`EventQueueHeader::version`], growing the header from 24
+/// to 32 bytes, and [`FillEvent::price`], growing events from 40 to 48 bytes. The version field of a version 0 queue overlaps the padding of its first event slot, which is always zero,
+/// so that these queues can still be recognized.
+///
+/// Events can't be pushed to version 0 queues anymore, but the consume_events instruction still pops them with
+/// [`EventQueue::pop_legacy`]. Once empty, the queue is laid out again with the current version, after which the market
+/// can be traded on or closed as usual.
+pub const EVENT_QUEUE_VERSION: u8 = 1;
+
+/// The byte size of the header of a version 0 event queue, see [`EVENT_QUEUE_VERSION`]
+const LEGACY_HEADER_LEN: usize = 24;
+
+/// Returns the layout version of raw event queue account data if it was laid out by a legacy version which can still be
+/// consumed, see [`EVENT_QUEUE_VERSION`].
+pub fn legacy_event_queue_version(data: &[u8]) -> Option<u8> {
+    let (tag, header) = (data.get(..8)?, data.get(8..8 + EventQueueHeader::LEN)?);
+    if tag != (AccountTag::EventQueue as u64).to_le_bytes() {
+        return None;
+    }
+    match bytemuck::pod_read_unaligned::<EventQueueHeader>(header).version {
+        0 => Some(0),
+        _ => None,
+    }
+}
+
+/// Reads the callback information size of an initialized event queue from raw account data, which doesn't need to be aligned.
+///
+/// This allows decoders which don't have access to the market account to determine the event queue's layout.
+pub fn callback_info_size_from_bytes(account_data: &[u8]) -> Result<u32, ProgramError> {
+    if account_data.len() < 8 + EventQueueHeader::LEN {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let (tag, header) = account_data.split_at(8);
+    if tag != (AccountTag::EventQueue as u64).to_le_bytes() {
+        return Err(AoError::AccountTagMismatch.into());
+    }
+    let header: EventQueueHeader = bytemuck::pod_read_unaligned(&header[..EventQueueHeader::LEN]);
+    if header.version != EVENT_QUEUE_VERSION {
+        return Err(AoError::EventQueueVersionMismatch.into());
+    }
+    Ok(header.callback_info_size)
+}
+
+#[derive(Clone, Zeroable, Pod, Copy, Debug, PartialEq)]
+#[repr(C)]
+/// A fill or out event of a version 0 event queue, see [`EVENT_QUEUE_VERSION`]
+pub struct LegacyEvent {
+    /// The u8 representation for an [`EventTag`] enum, which is either [`EventTag::Fill`] or [`EventTag::Out`]
+    pub tag: u8,
+    /// The u8 representation for a [`Side`] enum
+    pub side: u8,
+    pub(crate) _padding: [u8; 6],
+    /// The total quote size of a fill event, which is 0 for out events
+    pub quote_size: u64,
+    /// The order id of the maker order
+    pub order_id: [u64; 2],
+    /// The total base size of the transaction
+    pub base_size: u64,
+}
+
+impl LegacyEvent {
+    /// Byte length of the LegacyEvent object
+    pub const LEN: usize = std::mem::size_of::<Self>();
+}
+
 /// The event queue account contains a serialized header, a register
 /// and a circular buffer of serialized events.
 ///
@@ -165,13 +349,69 @@ impl<'queue, C: Pod> EventQueue<'queue, C> {
 
         let (header, remaining) = buf[8..].split_at_mut(EventQueueHeader::LEN);
 
+        let header: &mut EventQueueHeader = bytemuck::from_bytes_mut(header);
+        if expected_tag == AccountTag::Uninitialized {
+            header.callback_info_size = callback_info_len as u32;
+            header.version = EVENT_QUEUE_VERSION;
+        } else if header.version != EVENT_QUEUE_VERSION {
+            msg!("Unsupported event queue layout version");
+            return Err(AoError::EventQueueVersionMismatch.into());
+        } else if header.callback_info_size as usize != callback_info_len {
+            return Err(AoError::CallbackSizeMismatch.into());
+        }
+
         let (events, callback_infos) = remaining.split_at_mut(capacity * FillEvent::LEN);
+        // Queues laid out again from a legacy version may have trailing bytes
+        let callback_infos = &mut callback_infos[..2 * capacity * callback_info_len];
         Ok(Self {
-            header: bytemuck::from_bytes_mut(header),
+            header,
             events: bytemuck::cast_slice_mut(events),
             callback_infos: bytemuck::cast_slice_mut(callback_infos),
         })
     }
+
+    /// Pops up to `number_of_entries_to_pop` events off a version 0 event queue, see [`EVENT_QUEUE_VERSION`].
+    ///
+    /// Once empty, the queue is laid out again with the current version. Its sequence number is kept so that new order ids
+    /// don't collide with the ones of resting orders.
+    pub fn pop_legacy(
+        buf: &mut [u8],
+        number_of_entries_to_pop: u64,
+    ) -> Result<Vec<LegacyEvent>, ProgramError> {
+        if legacy_event_queue_version(buf) != Some(0) {
+            return Err(AoError::EventQueueVersionMismatch.into());
+        }
+        let capacity = ((buf.len() - 8 - LEGACY_HEADER_LEN)
+            / (LegacyEvent::LEN + 2 * std::mem::size_of::<C>())) as u64;
+        let (header, events) = buf[8..].split_at_mut(LEGACY_HEADER_LEN);
+        let [head, count, seq_num]: [u64; 3] = bytemuck::pod_read_unaligned(header);
+        if head >= capacity || count > capacity {
+            return Err(AoError::MarketCorrupted.into());
+        }
+
+        let popped_len = std::cmp::min(count, number_of_entries_to_pop);
+        let popped = (0..popped_len)
+            .map(|i| {
+                let offset = ((head + i) % capacity) as usize * LegacyEvent::LEN;
+                bytemuck::pod_read_unaligned(&events[offset..offset + LegacyEvent::LEN])
+            })
+            .collect();
+
+        if popped_len == count {
+            buf[8..].fill(0);
+            let header = EventQueueHeader {
+                seq_num,
+                callback_info_size: std::mem::size_of::<C>() as u32,
+                version: EVENT_QUEUE_VERSION,
+                ..Zeroable::zeroed()
+            };
+            buf[8..8 + EventQueueHeader::LEN].copy_from_slice(bytemuck::bytes_of(&header));
+        } else {
+            let header = [(head + popped_len) % capacity, count - popped_len, seq_num];
+            buf[8..8 + LEGACY_HEADER_LEN].copy_from_slice(bytemuck::bytes_of(&header));
+        }
+        Ok(popped)
+    }
 }
 
 impl<'queue, C: Clone> EventQueue<'queue, C> {
@@ -203,6 +443,36 @@ impl<'queue, C: Clone> EventQueue<'queue, C> {
     }
 }
 
+impl<'queue, C: Clone> EventQueue<'queue, C> {
+    /// Pop up to n events of the given type off the queue, preserving the order of all other events.
+    ///
+    /// The remaining events are compacted towards the head of the queue. This walks through the whole queue.
+    /// Returns the number of events which were popped.
+    pub fn pop_matching(&mut self, number_of_entries_to_pop: u64, tag: EventTag) -> u64 {
+        let capacity = self.events.len();
+        let head = self.header.head as usize;
+        let mut popped = 0;
+        let mut write_index = 0;
+        for read_index in 0..self.header.count as usize {
+            let read_idx = (head + read_index) % capacity;
+            if popped < number_of_entries_to_pop && self.events[read_idx].tag == tag as u8 {
+                popped += 1;
+                continue;
+            }
+            if write_index != read_index {
+                let write_idx = (head + write_index) % capacity;
+                self.events[write_idx] = self.events[read_idx];
+                self.callback_infos[2 * write_idx] = self.callback_infos[2 * read_idx].clone();
+                self.callback_infos[2 * write_idx + 1] =
+                    self.callback_infos[2 * read_idx + 1].clone();
+            }
+            write_index += 1;
+        }
+        self.header.count = write_index as u64;
+        popped
+    }
+}
+
 impl<'queue, C> EventQueue<'queue, C> {
     /// Compute the allocation size for an event queue of a desired capacity
     pub fn compute_allocation_size(desired_event_capacity: usize) -> usize {
@@ -242,6 +512,15 @@ impl<'queue, C> EventQueue<'queue, C> {
         seq_num
     }
 
+    /// Checks that the queue's head and length are consistent with its capacity
+    pub fn verify_header(&self) -> Result<(), AoError> {
+        let capacity = self.events.len() as u64;
+        if self.header.head >= capacity || self.header.count > capacity {
+            return Err(AoError::MarketCorrupted);
+        }
+        Ok(())
+    }
+
     pub(crate) fn full(&self) -> bool {
         self.header.count as usize == self.events.len()
     }
@@ -256,6 +535,16 @@ impl<'queue, C> EventQueue<'queue, C> {
         Some(self.get_event(event_idx))
     }
 
+    /// Returns an iterator over at most `len` events, starting at position `start` in the queue.
+    ///
+    /// The window is clamped to the queue's current length, and is empty if `start` is out of bounds.
+    pub fn peek_range(&self, start: u64, len: u64) -> impl Iterator<Item = EventRef<'_, C>> {
+        let len = std::cmp::min(len, self.header.count.saturating_sub(start));
+        let capacity = self.events.len() as u64;
+        (start..start + len)
+            .map(move |index| self.get_event(((self.header.head + index) % capacity) as usize))
+    }
+
     fn get_event(&self, event_idx: usize) -> EventRef<'_, C> {
         let event = &self.events[event_idx];
         match EventTag::from_u8(event.tag).unwrap() {
@@ -264,9 +553,20 @@ impl<'queue, C> EventQueue<'queue, C> {
                 maker_callback_info: &self.callback_infos[2 * event_idx],
                 taker_callback_info: &self.callback_infos[2 * event_idx + 1],
             }),
-            EventTag::Out => EventRef::Out(OutEventRef {
+            EventTag::Out => {
+                let event: &OutEvent = bytemuck::cast_ref(event);
+                EventRef::Out(OutEventRef {
+                    event,
+                    callback_info: &self.callback_infos[2 * event_idx],
+                    taker_callback_info: if event.self_trade != 0 {
+                        Some(&self.callback_infos[2 * event_idx + 1])
+                    } else {
+                        None
+                    },
+                })
+            }
+            EventTag::LevelCleared => EventRef::LevelCleared(LevelClearedEventRef {
                 event: bytemuck::cast_ref(event),
-                callback_info: &self.callback_infos[2 * event_idx],
             }),
         }
     }
@@ -289,6 +589,16 @@ impl<'queue, C> EventQueue<'queue, C> {
         }
     }
 
+    /// Returns an iterator over the queue's events up to, but not including, the first one satisfying `pred`.
+    ///
+    /// The number of yielded events can be passed to [`EventQueue::pop_n`] to consume exactly up to that boundary.
+    pub fn iter_until<F: Fn(&EventRef<C>) -> bool>(
+        &self,
+        pred: F,
+    ) -> impl Iterator<Item = EventRef<'_, C>> {
+        self.iter().take_while(move |e| !pred(e))
+    }
+
     /// Checks whether the event queue is currently empty
     pub fn is_empty(&self) -> bool {
         self.header.count == 0
@@ -298,6 +608,21 @@ impl<'queue, C> EventQueue<'queue, C> {
     pub fn len(&self) -> u64 {
         self.header.count
     }
+
+    /// Returns the maximum number of events the queue can hold
+    pub fn capacity(&self) -> u64 {
+        self.events.len() as u64
+    }
+
+    /// Returns the number of events which can still be pushed before the queue is full
+    pub fn remaining_capacity(&self) -> u64 {
+        self.capacity() - self.header.count
+    }
+
+    /// Returns the proportion of the queue's capacity which is currently in use, in basis points (0 to 10 000)
+    pub fn utilization_bps(&self) -> u64 {
+        self.header.count * 10_000 / self.capacity()
+    }
 }
 
 /// Utility struct for iterating over a queue
@@ -361,6 +686,7 @@ mod tests {
                                 s
                             },
                             base_size: seq_gen.next().unwrap(),
+                            price: seq_gen.next().unwrap(),
                         },
                         Some(&[seq_gen.next().unwrap() as u8; 32]),
                         Some(&[seq_gen.next().unwrap() as u8; 32]),
@@ -373,7 +699,12 @@ mod tests {
                         OutEvent {
                             tag: EventTag::Out as u8,
                             side: Side::Ask as u8,
-                            _padding: [0; 14],
+                            self_trade: 0,
+                            expired: 0,
+                            delete: 1,
+                            reason: OutReason::Cancel as u8,
+                            _padding: [0; 10],
+                            _padding_end: [0; 8],
                             base_size: seq_gen.next().unwrap(),
                             order_id: {
                                 let s = seq_gen.next().unwrap() as u128;
@@ -401,6 +732,7 @@ mod tests {
                 s
             },
             base_size: seq_gen.next().unwrap(),
+            price: seq_gen.next().unwrap(),
         };
         assert_eq!(
             extra_event,
@@ -425,7 +757,12 @@ mod tests {
                                 event: &OutEvent {
                                     tag: EventTag::Out as u8,
                                     side: Side::Ask as u8,
-                                    _padding: [0; 14],
+                                    self_trade: 0,
+                                    expired: 0,
+                                    delete: 1,
+                                    reason: OutReason::Cancel as u8,
+                                    _padding: [0; 10],
+                                    _padding_end: [0; 8],
                                     base_size: seq_gen.next().unwrap(),
                                     order_id: {
                                         let s = seq_gen.next().unwrap() as u128;
@@ -434,7 +771,8 @@ mod tests {
                                         s
                                     },
                                 },
-                                callback_info: &[seq_gen.next().unwrap() as u8; 32]
+                                callback_info: &[seq_gen.next().unwrap() as u8; 32],
+                                taker_callback_info: None,
                             }
                         );
                     }
@@ -458,6 +796,7 @@ mod tests {
                                         s
                                     },
                                     base_size: seq_gen.next().unwrap(),
+                                    price: seq_gen.next().unwrap(),
                                 },
                                 maker_callback_info: &[seq_gen.next().unwrap() as u8; 32],
                                 taker_callback_info: &[seq_gen.next().unwrap() as u8; 32]
@@ -466,9 +805,337 @@ mod tests {
                     }
                     assert_eq!(EventRef::Fill(e), event_queue.peek_at(i as u64).unwrap());
                 }
+                EventRef::LevelCleared(_) => unreachable!(),
             }
             number_of_events = i + 1;
         }
         assert_eq!(number_of_events, 100);
     }
+
+    #[test]
+    fn test_event_queue_callback_size_mismatch() {
+        let allocation_size = EventQueue::<[u8; 32]>::compute_allocation_size(100);
+        let mut buffer = vec![0; allocation_size];
+        EventQueueTest::from_buffer(&mut buffer, AccountTag::Uninitialized).unwrap();
+
+        assert!(EventQueueTest::from_buffer(&mut buffer, AccountTag::EventQueue).is_ok());
+        assert!(matches!(
+            EventQueue::<[u8; 16]>::from_buffer(&mut buffer, AccountTag::EventQueue),
+            Err(e) if e == AoError::CallbackSizeMismatch.into()
+        ));
+    }
+
+    #[test]
+    fn test_callback_info_size_from_bytes() {
+        let allocation_size = EventQueue::<[u8; 32]>::compute_allocation_size(100);
+        let mut buffer = vec![0; allocation_size];
+        assert!(matches!(
+            callback_info_size_from_bytes(&buffer),
+            Err(e) if e == AoError::AccountTagMismatch.into()
+        ));
+
+        EventQueueTest::from_buffer(&mut buffer, AccountTag::Uninitialized).unwrap();
+        assert_eq!(callback_info_size_from_bytes(&buffer), Ok(32));
+        assert_eq!(
+            callback_info_size_from_bytes(&buffer[..10]),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        // The account data doesn't need to be aligned
+        let mut unaligned = vec![0u8; buffer.len() + 1];
+        unaligned[1..].copy_from_slice(&buffer);
+        assert_eq!(callback_info_size_from_bytes(&unaligned[1..]), Ok(32));
+    }
+
+    #[test]
+    fn test_event_queue_pop_matching() {
+        let allocation_size = EventQueue::<[u8; 32]>::compute_allocation_size(10);
+        let mut buffer = vec![0; allocation_size];
+        let mut event_queue =
+            EventQueueTest::from_buffer(&mut buffer, AccountTag::Uninitialized).unwrap();
+
+        let push = |event_queue: &mut EventQueueTest, is_fill: bool, id: u8| {
+            if is_fill {
+                event_queue
+                    .push_back(
+                        FillEvent {
+                            base_size: id as u64,
+                            ..Zeroable::zeroed()
+                        },
+                        Some(&[id; 32]),
+                        Some(&[id + 100; 32]),
+                    )
+                    .unwrap();
+            } else {
+                event_queue
+                    .push_back(
+                        OutEvent {
+                            base_size: id as u64,
+                            ..Zeroable::zeroed()
+                        },
+                        Some(&[id; 32]),
+                        None,
+                    )
+                    .unwrap();
+            }
+        };
+        let remaining = |event_queue: &EventQueueTest| {
+            event_queue
+                .iter()
+                .map(|e| match e {
+                    EventRef::Fill(f) => {
+                        assert_eq!(f.maker_callback_info, &[f.event.base_size as u8; 32]);
+                        assert_eq!(f.taker_callback_info, &[f.event.base_size as u8 + 100; 32]);
+                        (true, f.event.base_size as u8)
+                    }
+                    EventRef::Out(o) => {
+                        assert_eq!(o.callback_info, &[o.event.base_size as u8; 32]);
+                        (false, o.event.base_size as u8)
+                    }
+                    EventRef::LevelCleared(_) => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Move the head forward so that the queue wraps around
+        for id in 0..6 {
+            push(&mut event_queue, true, id);
+        }
+        event_queue.pop_n(6);
+
+        let mut model = vec![];
+        for id in 0..9 {
+            let is_fill = id % 3 != 1;
+            push(&mut event_queue, is_fill, id);
+            model.push((is_fill, id));
+        }
+        assert_eq!(remaining(&event_queue), model);
+
+        // Pop the first 4 fills
+        assert_eq!(event_queue.pop_matching(4, EventTag::Fill), 4);
+        let mut popped = 0;
+        model.retain(|&(is_fill, _)| {
+            if is_fill && popped < 4 {
+                popped += 1;
+                return false;
+            }
+            true
+        });
+        assert_eq!(remaining(&event_queue), model);
+        assert_eq!(event_queue.len(), 5);
+
+        // Pop all the outs
+        assert_eq!(event_queue.pop_matching(10, EventTag::Out), 3);
+        model.retain(|&(is_fill, _)| is_fill);
+        assert_eq!(remaining(&event_queue), model);
+
+        // New events are pushed after the compacted ones
+        push(&mut event_queue, false, 42);
+        model.push((false, 42));
+        assert_eq!(remaining(&event_queue), model);
+        assert_eq!(event_queue.pop_matching(1, EventTag::LevelCleared), 0);
+    }
+
+    #[test]
+    fn test_event_queue_utilization() {
+        let allocation_size = EventQueue::<[u8; 32]>::compute_allocation_size(8);
+        let mut buffer = vec![0; allocation_size];
+        let mut event_queue =
+            EventQueueTest::from_buffer(&mut buffer, AccountTag::Uninitialized).unwrap();
+        assert_eq!(event_queue.capacity(), 8);
+        assert_eq!(event_queue.remaining_capacity(), 8);
+        assert_eq!(event_queue.utilization_bps(), 0);
+
+        for _ in 0..3 {
+            event_queue
+                .push_back(OutEvent::zeroed(), Some(&[0; 32]), None)
+                .unwrap();
+        }
+        assert_eq!(event_queue.remaining_capacity(), 5);
+        assert_eq!(event_queue.utilization_bps(), 3_750);
+
+        while !event_queue.full() {
+            event_queue
+                .push_back(OutEvent::zeroed(), Some(&[0; 32]), None)
+                .unwrap();
+        }
+        assert_eq!(event_queue.remaining_capacity(), 0);
+        assert_eq!(event_queue.utilization_bps(), 10_000);
+    }
+
+    #[test]
+    fn test_event_queue_iter_until() {
+        let allocation_size = EventQueue::<[u8; 32]>::compute_allocation_size(8);
+        let mut buffer = vec![0; allocation_size];
+        let mut event_queue =
+            EventQueueTest::from_buffer(&mut buffer, AccountTag::Uninitialized).unwrap();
+        let sentinel = [0xff; 32];
+        let is_sentinel = |e: &EventRef<[u8; 32]>| match e {
+            EventRef::Out(o) => *o.callback_info == sentinel,
+            _ => false,
+        };
+        assert_eq!(event_queue.iter_until(is_sentinel).count(), 0);
+
+        for callback_info in [[1; 32], [2; 32], sentinel, [3; 32], sentinel] {
+            event_queue
+                .push_back(OutEvent::zeroed(), Some(&callback_info), None)
+                .unwrap();
+        }
+        let callback_infos = |event_queue: &EventQueueTest| {
+            event_queue
+                .iter_until(is_sentinel)
+                .map(|e| match e {
+                    EventRef::Out(o) => o.callback_info[0],
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(callback_infos(&event_queue), vec![1, 2]);
+
+        // Consume up to the sentinel, then skip it
+        let n = event_queue.iter_until(is_sentinel).count() as u64;
+        event_queue.pop_n(n + 1);
+        assert_eq!(callback_infos(&event_queue), vec![3]);
+
+        event_queue.pop_n(2);
+        assert_eq!(callback_infos(&event_queue), Vec::<u8>::new());
+        assert!(event_queue.is_empty());
+    }
+
+    #[test]
+    fn test_event_queue_self_trade_out_taker_callback_info() {
+        let allocation_size = EventQueue::<[u8; 32]>::compute_allocation_size(2);
+        let mut buffer = vec![0; allocation_size];
+        let mut event_queue =
+            EventQueueTest::from_buffer(&mut buffer, AccountTag::Uninitialized).unwrap();
+        let self_trade_out = OutEvent {
+            self_trade: 1,
+            ..OutEvent::zeroed()
+        };
+        event_queue
+            .push_back(self_trade_out, Some(&[1; 32]), Some(&[2; 32]))
+            .unwrap();
+        event_queue.pop_n(1);
+        event_queue
+            .push_back(self_trade_out, Some(&[3; 32]), Some(&[4; 32]))
+            .unwrap();
+        // This slot still holds a stale taker callback info, which must not be exposed
+        event_queue
+            .push_back(OutEvent::zeroed(), Some(&[5; 32]), None)
+            .unwrap();
+
+        let taker_callback_infos = event_queue
+            .iter()
+            .map(|e| match e {
+                EventRef::Out(o) => o.taker_callback_info.map(|c| c[0]),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(taker_callback_infos, vec![Some(4), None]);
+    }
+
+    #[test]
+    fn test_event_queue_peek_range() {
+        let allocation_size = EventQueue::<[u8; 32]>::compute_allocation_size(6);
+        let mut buffer = vec![0; allocation_size];
+        let mut event_queue =
+            EventQueueTest::from_buffer(&mut buffer, AccountTag::Uninitialized).unwrap();
+        let push = |event_queue: &mut EventQueueTest, i: u8| {
+            event_queue
+                .push_back(OutEvent::zeroed(), Some(&[i; 32]), None)
+                .unwrap();
+        };
+        for i in 0..6 {
+            push(&mut event_queue, i);
+        }
+        // Wrap the queue around its end
+        event_queue.pop_n(4);
+        for i in 6..9 {
+            push(&mut event_queue, i);
+        }
+        assert_eq!(event_queue.header.head, 4);
+        assert_eq!(event_queue.len(), 5);
+
+        let window = |start: u64, len: u64| {
+            event_queue
+                .peek_range(start, len)
+                .map(|e| match e {
+                    EventRef::Out(o) => o.callback_info[0],
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(window(1, 3), vec![5, 6, 7]);
+        assert_eq!(window(0, 5), vec![4, 5, 6, 7, 8]);
+        assert_eq!(window(3, 10), vec![7, 8]);
+        assert_eq!(window(5, 1), Vec::<u8>::new());
+        assert_eq!(window(u64::MAX, u64::MAX), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_out_event_new() {
+        let flags = |reason| {
+            let event = OutEvent::new(Side::Ask, 42, 7, reason);
+            assert_eq!(event.tag, EventTag::Out as u8);
+            assert_eq!(event.delete, 1);
+            assert_eq!(event.reason, reason as u8);
+            (event.self_trade, event.expired)
+        };
+        assert_eq!(flags(OutReason::Cancel), (0, 0));
+        assert_eq!(flags(OutReason::Booted), (0, 0));
+        assert_eq!(flags(OutReason::SelfTrade), (1, 0));
+        assert_eq!(flags(OutReason::Expired), (0, 1));
+        assert_eq!(flags(OutReason::MinSizePurge), (0, 0));
+    }
+
+    #[test]
+    fn test_pop_legacy() {
+        let capacity = 10;
+        let mut buffer = vec![0; 8 + LEGACY_HEADER_LEN + capacity * (LegacyEvent::LEN + 2 * 32)];
+        buffer[..8].copy_from_slice(&(AccountTag::EventQueue as u64).to_le_bytes());
+        buffer[8..8 + LEGACY_HEADER_LEN].copy_from_slice(bytemuck::bytes_of(&[9u64, 2, 42]));
+        let events = [
+            LegacyEvent {
+                tag: EventTag::Fill as u8,
+                quote_size: 5,
+                base_size: 7,
+                ..Zeroable::zeroed()
+            },
+            LegacyEvent {
+                tag: EventTag::Out as u8,
+                base_size: 3,
+                ..Zeroable::zeroed()
+            },
+        ];
+        for (slot, event) in [9, 0].iter().zip(events.iter()) {
+            let offset = 8 + LEGACY_HEADER_LEN + slot * LegacyEvent::LEN;
+            buffer[offset..offset + LegacyEvent::LEN].copy_from_slice(bytemuck::bytes_of(event));
+        }
+
+        assert_eq!(legacy_event_queue_version(&buffer), Some(0));
+        assert_eq!(
+            EventQueueTest::from_buffer(&mut buffer, AccountTag::EventQueue).err(),
+            Some(AoError::EventQueueVersionMismatch.into())
+        );
+
+        assert_eq!(
+            EventQueueTest::pop_legacy(&mut buffer, 1).unwrap(),
+            events[..1]
+        );
+        assert_eq!(legacy_event_queue_version(&buffer), Some(0));
+        assert_eq!(
+            EventQueueTest::pop_legacy(&mut buffer, 10).unwrap(),
+            events[1..]
+        );
+
+        // The emptied queue is laid out again with the current version
+        assert_eq!(legacy_event_queue_version(&buffer), None);
+        let mut event_queue =
+            EventQueueTest::from_buffer(&mut buffer, AccountTag::EventQueue).unwrap();
+        assert!(event_queue.is_empty());
+        assert_eq!(event_queue.header.head, 0);
+        assert_eq!(event_queue.header.version, EVENT_QUEUE_VERSION);
+        assert_eq!(event_queue.gen_seq_num(), 42);
+        assert!(EventQueueTest::pop_legacy(&mut buffer, 1).is_err());
+    }
 }
@@ -1,6 +1,7 @@
 //! The market state struct tracks metadata and security information about the agnostic orderbook system and its
 //! relevant accounts
 use bytemuck::{Pod, Zeroable};
+use enumflags2::{bitflags, BitFlags};
 use solana_program::{entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
 use std::mem::size_of;
 
@@ -15,6 +16,39 @@ use super::AccountTag;
 #[repr(C)]
 /// The orderbook market's central state
 pub struct MarketState {
+    /// The public key of the orderbook's event queue account
+    pub event_queue: Pubkey,
+    /// The public key of the orderbook's bids account
+    pub bids: Pubkey,
+    /// The public key of the orderbook's asks account
+    pub asks: Pubkey,
+    /// The minimum order size that can be inserted into the orderbook after matching.
+    pub min_base_order_size: u64,
+    /// Tick size (FP32). A tick size of 0 means that prices aren't constrained to a tick grid.
+    pub tick_size: u64,
+    /// A bitmask of [`MarketFlag`] values enabling optional market behaviors
+    pub flags: u64,
+    /// The maximum limit price (FP32) accepted by the orderbook. Markets which don't bound prices use `u64::MAX`.
+    pub max_price: u64,
+    /// The current trading session. Newly posted orders are tagged with this session id.
+    pub current_session: u32,
+    /// The maximum number of better price levels an order can post behind. A value of 0 means that the depth isn't limited.
+    pub max_post_depth: u32,
+    /// The cached reference price (FP32) which pegged orders are priced against. A value of 0 means that it was never set.
+    pub ref_price: u64,
+    /// The unix timestamp at which the reference price was last updated
+    pub ref_price_ts: i64,
+    /// The number of seconds after its last update during which the reference price can be used
+    pub ref_price_max_age: u64,
+}
+
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+/// The original layout of the [`MarketState`], whose fields are a prefix of the current layout.
+///
+/// Market accounts created with this layout are rejected by [`MarketState::from_buffer`] until they are grown to the
+/// current layout with the migrate_market instruction.
+pub struct LegacyMarketState {
     /// The public key of the orderbook's event queue account
     pub event_queue: Pubkey,
     /// The public key of the orderbook's bids account
@@ -27,6 +61,54 @@ pub struct MarketState {
     pub tick_size: u64,
 }
 
+impl LegacyMarketState {
+    /// Expected size in bytes of LegacyMarketState
+    pub const LEN: usize = size_of::<Self>();
+
+    /// Reads the fields shared by the legacy and current layouts from raw market account data of either layout
+    pub fn read(account_data: &[u8]) -> Result<Self, ProgramError> {
+        if account_data.len() != 8 + Self::LEN && account_data.len() != 8 + MarketState::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if account_data[0..8] != (AccountTag::Market as u64).to_le_bytes() {
+            return Err(AoError::AccountTagMismatch.into());
+        }
+        Ok(bytemuck::pod_read_unaligned(
+            &account_data[8..8 + Self::LEN],
+        ))
+    }
+}
+
+#[bitflags]
+#[repr(u64)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Optional behaviors which can be enabled for a market at creation
+pub enum MarketFlag {
+    /// Emit a [`LevelClearedEvent`][`crate::state::event_queue::LevelClearedEvent`] whenever a taker order fully sweeps a price level
+    EmitLevelCleared = 1 << 0,
+    /// Skip self trade detection entirely, matching orders with identical callback ids as if their self trade behavior
+    /// was [`SelfTradeBehavior::DecrementTake`][`crate::state::SelfTradeBehavior::DecrementTake`].
+    ///
+    /// This saves compute for markets where self trading is acceptable or handled by the caller program.
+    DisableSelfTradeCheck = 1 << 1,
+    /// Share the taker's quantity between the orders of a price level proportionally to their sizes, see
+    /// [`MatchingMode::ProRata`].
+    ProRataMatching = 1 << 2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Describes how a taker order's quantity is allocated between the resting orders of a price level
+pub enum MatchingMode {
+    /// Resting orders are filled one after the other, in time priority
+    PriceTime,
+    /// When a price level can't be entirely filled, each of its orders receives a share of the taker's quantity
+    /// proportional to its size. The rounding remainder goes to the largest order.
+    ///
+    /// Orders which would self trade are handled according to the taker's self trade behavior before the allocation.
+    /// Levels where a share would round down to no quote at all are matched in price-time order instead.
+    ProRata,
+}
+
 impl MarketState {
     /// Expected size in bytes of MarketState
     pub const LEN: usize = size_of::<Self>();
@@ -35,15 +117,82 @@ impl MarketState {
         account_data: &mut [u8],
         expected_tag: AccountTag,
     ) -> Result<&mut Self, ProgramError> {
-        let tag = bytemuck::from_bytes_mut::<u64>(&mut account_data[0..8]);
+        if account_data.len() < 8 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (tag, data) = account_data.split_at_mut(8);
+        let tag = bytemuck::from_bytes_mut::<u64>(tag);
         if tag != &(expected_tag as u64) {
             return Err(AoError::AccountTagMismatch.into());
         };
+        if expected_tag == AccountTag::Market && data.len() == LegacyMarketState::LEN {
+            msg!("The market account must be migrated");
+            return Err(AoError::MarketStateVersionMismatch.into());
+        }
+        let market_state =
+            bytemuck::try_from_bytes_mut(data).map_err(|_| ProgramError::InvalidAccountData)?;
         *tag = AccountTag::Market as u64;
 
-        let (_, data) = account_data.split_at_mut(8);
+        Ok(market_state)
+    }
+
+    /// Checks whether a [`MarketFlag`] is enabled for this market
+    pub fn has_flag(&self, flag: MarketFlag) -> bool {
+        BitFlags::<MarketFlag>::from_bits_truncate(self.flags).contains(flag)
+    }
+
+    /// Checks whether self trades are detected on this market, which is the case unless
+    /// [`MarketFlag::DisableSelfTradeCheck`] is set.
+    pub fn self_trade_check_enabled(&self) -> bool {
+        !self.has_flag(MarketFlag::DisableSelfTradeCheck)
+    }
+
+    /// Returns the market's [`MatchingMode`], which is price-time unless [`MarketFlag::ProRataMatching`] is set.
+    pub fn matching_mode(&self) -> MatchingMode {
+        if self.has_flag(MarketFlag::ProRataMatching) {
+            MatchingMode::ProRata
+        } else {
+            MatchingMode::PriceTime
+        }
+    }
+
+    /// Parses a market state from raw account data, without requiring an `AccountInfo` object.
+    ///
+    /// This is the read-only counterpart to [`MarketState::from_buffer`], mainly intended for off-chain clients.
+    pub fn from_bytes(account_data: &[u8]) -> Result<&Self, ProgramError> {
+        if account_data.len() < 8 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (tag, data) = account_data.split_at(8);
+        if tag != (AccountTag::Market as u64).to_le_bytes() {
+            return Err(AoError::AccountTagMismatch.into());
+        }
+        bytemuck::try_from_bytes(data).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Computes the limit price of a pegged order from the market's reference price and an FP32 offset.
+    ///
+    /// Fails with [`AoError::StaleReferencePrice`] if the reference price was never set or is older than its maximum age at `now`.
+    pub fn pegged_price(&self, peg_offset: i64, now: i64) -> Result<u64, AoError> {
+        let age = now.saturating_sub(self.ref_price_ts);
+        if self.ref_price == 0 || age < 0 || age as u64 > self.ref_price_max_age {
+            msg!("The market's reference price is unset or stale");
+            return Err(AoError::StaleReferencePrice);
+        }
+        self.ref_price
+            .checked_add_signed(peg_offset)
+            .ok_or(AoError::NumericalOverflow)
+    }
 
-        Ok(bytemuck::from_bytes_mut(data))
+    /// Returns the number of ticks separating two prices (FP32).
+    ///
+    /// Returns `None` if the market has no tick grid or if either price isn't a multiple of the tick size.
+    #[cfg(any(test, feature = "utils"))]
+    pub fn ticks_between(&self, a: u64, b: u64) -> Option<u64> {
+        if self.tick_size == 0 || a % self.tick_size != 0 || b % self.tick_size != 0 {
+            return None;
+        }
+        Some(a.max(b) / self.tick_size - a.min(b) / self.tick_size)
     }
 
     #[allow(missing_docs)]
@@ -63,3 +212,108 @@ fn market_cast() {
     assert!(r.is_err());
     assert_eq!(r.unwrap_err(), ProgramError::InvalidAccountData)
 }
+
+#[test]
+fn market_from_bytes() {
+    let mut buffer = vec![0u64; (MarketState::LEN + 8) / 8];
+    let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut buffer);
+    assert_eq!(
+        MarketState::from_bytes(bytes).unwrap_err(),
+        AoError::AccountTagMismatch.into()
+    );
+    let market_state = MarketState::from_buffer(bytes, AccountTag::Uninitialized).unwrap();
+    market_state.tick_size = 42;
+
+    let market_state = MarketState::from_bytes(bytes).unwrap();
+    assert_eq!(market_state.tick_size, 42);
+    assert_eq!(
+        MarketState::from_bytes(&bytes[..MarketState::LEN]).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+}
+
+#[test]
+fn market_legacy_layout() {
+    let mut buffer = vec![0u64; (MarketState::LEN + 8) / 8];
+    let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut buffer);
+    let market_state = MarketState::from_buffer(bytes, AccountTag::Uninitialized).unwrap();
+    market_state.event_queue = Pubkey::new_unique();
+    market_state.tick_size = 42;
+    let event_queue = market_state.event_queue;
+
+    let legacy_len = LegacyMarketState::LEN + 8;
+    let legacy = LegacyMarketState::read(&bytes[..legacy_len]).unwrap();
+    assert_eq!(legacy.event_queue, event_queue);
+    assert_eq!(legacy.tick_size, 42);
+    assert_eq!(
+        LegacyMarketState::read(bytes).unwrap().event_queue,
+        event_queue
+    );
+
+    // Legacy market accounts are rejected without panicking
+    assert_eq!(
+        MarketState::from_buffer(&mut bytes[..legacy_len], AccountTag::Market).unwrap_err(),
+        AoError::MarketStateVersionMismatch.into()
+    );
+    assert_eq!(
+        MarketState::from_buffer(&mut bytes[..legacy_len + 8], AccountTag::Market).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+    assert_eq!(
+        LegacyMarketState::read(&bytes[..legacy_len + 8]).unwrap_err(),
+        ProgramError::InvalidAccountData
+    );
+}
+
+#[test]
+fn market_pegged_price() {
+    let mut market_state: MarketState = Zeroable::zeroed();
+    assert!(matches!(
+        market_state.pegged_price(0, 0),
+        Err(AoError::StaleReferencePrice)
+    ));
+
+    market_state.ref_price = 1000 << 32;
+    market_state.ref_price_ts = 100;
+    market_state.ref_price_max_age = 60;
+    assert_eq!(market_state.pegged_price(0, 100).unwrap(), 1000 << 32);
+    assert_eq!(
+        market_state.pegged_price(-(1 << 32), 160).unwrap(),
+        999 << 32
+    );
+    assert_eq!(
+        market_state.pegged_price(1 << 31, 130).unwrap(),
+        (1000 << 32) + (1 << 31)
+    );
+    assert!(matches!(
+        market_state.pegged_price(0, 161),
+        Err(AoError::StaleReferencePrice)
+    ));
+    // A reference price from the future can't be trusted either
+    assert!(matches!(
+        market_state.pegged_price(0, 99),
+        Err(AoError::StaleReferencePrice)
+    ));
+    assert!(matches!(
+        market_state.pegged_price(-(1001 << 32), 100),
+        Err(AoError::NumericalOverflow)
+    ));
+}
+
+#[test]
+fn market_ticks_between() {
+    let mut market_state: MarketState = Zeroable::zeroed();
+    assert_eq!(market_state.ticks_between(10 << 32, 12 << 32), None);
+
+    market_state.tick_size = 1 << 31;
+    assert_eq!(market_state.ticks_between(10 << 32, 12 << 32), Some(4));
+    assert_eq!(market_state.ticks_between(12 << 32, 10 << 32), Some(4));
+    assert_eq!(market_state.ticks_between(10 << 32, 10 << 32), Some(0));
+    assert_eq!(
+        market_state.ticks_between(0, u64::MAX - u64::MAX % (1 << 31)),
+        Some(u64::MAX >> 31)
+    );
+    // Prices off the tick grid are rejected
+    assert_eq!(market_state.ticks_between(10 << 32, (12 << 32) + 1), None);
+    assert_eq!(market_state.ticks_between((10 << 32) + 1, 12 << 32), None);
+}
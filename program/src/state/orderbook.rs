@@ -5,11 +5,13 @@
 //! object, in order to benefit from the AOB's included security checks.
 use crate::{
     error::AoError,
-    processor::new_order,
+    processor::{modify_order, new_order, take_order},
     state::{
-        critbit::{LeafNode, NodeHandle, Slab},
-        event_queue::{EventQueue, EventTag, FillEvent, OutEvent},
-        AccountTag, SelfTradeBehavior, Side,
+        critbit::{legacy_slab_leaf_count, LeafFlag, LeafNode, NodeHandle, Slab},
+        event_queue::{EventQueue, EventTag, FillEvent, LevelClearedEvent, OutEvent, OutReason},
+        get_side_from_order_id,
+        market_state::{MarketFlag, MarketState, MatchingMode},
+        partition_by_side, AccountTag, OrderType, SelfTradeBehavior, Side, TakerMode,
     },
 };
 use bonfida_utils::fp_math::{fp32_div, fp32_mul_ceil, fp32_mul_floor};
@@ -23,7 +25,8 @@ use solana_program::{msg, program_error::ProgramError};
 /// were either matched against other orders or written into the orderbook.
 ///
 /// In the case of an order cancellation, the quantities describe what was left of the order in the orderbook.
-#[derive(Debug, BorshDeserialize, BorshSerialize)]
+#[derive(Debug, Default, BorshDeserialize, BorshSerialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderSummary {
     /// When applicable, the order id of the newly created order.
     pub posted_order_id: Option<u128>,
@@ -33,6 +36,55 @@ pub struct OrderSummary {
     pub total_quote_qty: u64,
     #[allow(missing_docs)]
     pub total_base_qty_posted: u64,
+    /// When applicable, the limit price of the newly created order, after rounding to the market's tick size.
+    pub posted_price: Option<u64>,
+    /// Set when a new order's remainder could have been posted, but was smaller than the market's minimum order size.
+    pub post_skipped_below_min: bool,
+    /// Set when a new order's remainder could have been posted, but the book side is full and the order is not
+    /// more aggressive than its least aggressive order.
+    pub post_skipped_book_full: bool,
+    /// The number of maker orders which were matched against, each of them producing a fill event.
+    pub matched_order_count: u64,
+}
+
+impl OrderSummary {
+    /// Computes the volume-weighted price (FP32) at which a new order was matched, or `None` if nothing was matched.
+    ///
+    /// The posted part of the order is left out. Its quote quantity is deducted at the posted price, rounded down,
+    /// so the result can be marginally high for bids which were partially posted.
+    pub fn average_fill_price(&self) -> Option<u64> {
+        let matched_base_qty = self.total_base_qty - self.total_base_qty_posted;
+        if matched_base_qty == 0 {
+            return None;
+        }
+        let posted_quote_qty =
+            fp32_mul_floor(self.total_base_qty_posted, self.posted_price.unwrap_or(0))?;
+        fp32_div(self.total_quote_qty - posted_quote_qty, matched_base_qty)
+    }
+}
+
+/// The action to take when a new order should be posted to a full side of the orderbook
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SlabFullAction {
+    /// Boot the least aggressive order out of the orderbook to make room for the new order
+    Boot,
+    /// Fail with [`AoError::SlabOutOfSpace`]
+    Reject,
+    /// Don't post the new order, reporting it in the summary's `post_skipped_book_full` field
+    Skip,
+}
+
+/// The best price on each side of the orderbook, along with the total base quantity of all orders at that price
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TopOfBook {
+    /// The best bid price, or `None` if there are no bids
+    pub bid_price: Option<u64>,
+    /// The total base quantity at the best bid price, or 0 if there are no bids
+    pub bid_qty: u64,
+    /// The best ask price, or `None` if there are no asks
+    pub ask_price: Option<u64>,
+    /// The total base quantity at the best ask price, or 0 if there are no asks
+    pub ask_qty: u64,
 }
 
 /// This trait defines a subobject which can be used to compare two callback information object to determine
@@ -54,7 +106,7 @@ impl CallbackInfo for [u8; 32] {
 }
 
 /// The serialized size of an OrderSummary object.
-pub const ORDER_SUMMARY_SIZE: u32 = 41;
+pub const ORDER_SUMMARY_SIZE: u32 = 60;
 
 #[doc(hidden)]
 pub struct OrderBookState<'a, C> {
@@ -73,6 +125,149 @@ impl<'slab, C: Pod + Copy> OrderBookState<'slab, C> {
         let asks = Slab::from_buffer(asks_account, AccountTag::Asks)?;
         Ok(Self { bids, asks })
     }
+
+    /// Removes up to `max_orders` orders from an orderbook whose slabs were laid out by a legacy version, see
+    /// [`SLAB_VERSION`][`crate::state::critbit::SLAB_VERSION`].
+    ///
+    /// The orders are removed in the same order as with [`OrderBookState::prune_orders_from`], but legacy slabs can't
+    /// resume from a cursor: their least aggressive orders are always removed first. A side which is already laid out
+    /// with the current version has been emptied by a previous call, and is skipped.
+    pub fn prune_legacy_orders(
+        bids_account: &mut [u8],
+        asks_account: &mut [u8],
+        max_orders: u64,
+        emit_events: bool,
+        event_queue: &mut EventQueue<'_, C>,
+    ) -> Result<(OrderSummary, Option<u128>), ProgramError> {
+        let mut order_summary = OrderSummary::default();
+        let mut remaining_orders = max_orders as usize;
+        let mut last_order_id = None;
+
+        for (side, data, tag) in [
+            (Side::Bid, bids_account, AccountTag::Bids),
+            (Side::Ask, asks_account, AccountTag::Asks),
+        ] {
+            let leaf_count = match legacy_slab_leaf_count(data) {
+                Some(leaf_count) => leaf_count,
+                None => continue,
+            };
+            // Checking for space beforehand ensures that no order is removed without its out event
+            if emit_events
+                && event_queue.remaining_capacity()
+                    < remaining_orders.min(leaf_count as usize) as u64
+            {
+                return Err(AoError::EventQueueFull.into());
+            }
+            let removed = Slab::<C>::prune_legacy(data, tag, remaining_orders, side == Side::Bid)?;
+            remaining_orders -= removed.len();
+            for (order_id, base_quantity, callback_info) in removed {
+                order_summary.total_base_qty = order_summary
+                    .total_base_qty
+                    .checked_add(base_quantity)
+                    .ok_or(AoError::NumericalOverflow)?;
+                order_summary.total_quote_qty =
+                    fp32_mul_floor(base_quantity, LeafNode::price_from_key(order_id))
+                        .and_then(|n| n.checked_add(order_summary.total_quote_qty))
+                        .ok_or(AoError::NumericalOverflow)?;
+                if emit_events {
+                    let out = OutEvent::new(side, order_id, base_quantity, OutReason::Cancel);
+                    event_queue
+                        .push_back(out, Some(&callback_info), None)
+                        .map_err(|_| AoError::EventQueueFull)?;
+                }
+                last_order_id = Some(order_id);
+            }
+        }
+
+        // Falling short of the limit means that there is nothing left to prune
+        let next_cursor = if remaining_orders == 0 {
+            last_order_id
+        } else {
+            None
+        };
+        Ok((order_summary, next_cursor))
+    }
+
+    #[cfg(any(test, feature = "utils"))]
+    /// Serializes both sides of the orderbook into a single versioned blob, which can be decoded with [`decode_snapshot`].
+    ///
+    /// Each side is encoded as a little-endian `u32` order count followed by its `(order_id, base_quantity, callback_info)` entries,
+    /// from the best price to the worst. Bids are encoded first.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let entry_len = 16 + 8 + std::mem::size_of::<C>();
+        let order_count = (self.bids.header.leaf_count + self.asks.header.leaf_count) as usize;
+        let mut data = Vec::with_capacity(1 + 8 + order_count * entry_len);
+        data.push(SNAPSHOT_VERSION);
+        for (slab, ascending) in [(&self.bids, false), (&self.asks, true)] {
+            data.extend_from_slice(&slab.header.leaf_count.to_le_bytes());
+            for h in slab.leaf_handles(ascending) {
+                let leaf = &slab.leaf_nodes[h as usize];
+                data.extend_from_slice(&leaf.order_id().to_le_bytes());
+                data.extend_from_slice(&leaf.base_quantity.to_le_bytes());
+                data.extend_from_slice(bytemuck::bytes_of(slab.get_callback_info(h)));
+            }
+        }
+        data
+    }
+}
+
+/// The current version of the orderbook snapshot encoding
+#[cfg(any(test, feature = "utils"))]
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+#[cfg(any(test, feature = "utils"))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// An order of the orderbook, detached from the underlying account data
+pub struct OwnedOrder<C> {
+    #[allow(missing_docs)]
+    pub order_id: u128,
+    #[allow(missing_docs)]
+    pub base_quantity: u64,
+    #[allow(missing_docs)]
+    pub callback_info: C,
+}
+
+#[cfg(any(test, feature = "utils"))]
+/// The decoded `(bids, asks)` of an orderbook snapshot
+pub type SnapshotSides<C> = (Vec<OwnedOrder<C>>, Vec<OwnedOrder<C>>);
+
+#[cfg(any(test, feature = "utils"))]
+/// Decodes a blob produced by [`OrderBookState::snapshot`] into its `(bids, asks)`, each from the best price to the worst.
+pub fn decode_snapshot<C: Pod>(data: &[u8]) -> Result<SnapshotSides<C>, ProgramError> {
+    use std::convert::TryInto;
+    fn take<'d>(data: &mut &'d [u8], len: usize) -> Result<&'d [u8], ProgramError> {
+        if data.len() < len {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let (head, tail) = data.split_at(len);
+        *data = tail;
+        Ok(head)
+    }
+    let mut data = data;
+    if take(&mut data, 1)? != [SNAPSHOT_VERSION] {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mut decode_side = || -> Result<Vec<OwnedOrder<C>>, ProgramError> {
+        let order_count = u32::from_le_bytes(take(&mut data, 4)?.try_into().unwrap());
+        (0..order_count)
+            .map(|_| {
+                Ok(OwnedOrder {
+                    order_id: u128::from_le_bytes(take(&mut data, 16)?.try_into().unwrap()),
+                    base_quantity: u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap()),
+                    callback_info: bytemuck::pod_read_unaligned(take(
+                        &mut data,
+                        std::mem::size_of::<C>(),
+                    )?),
+                })
+            })
+            .collect()
+    };
+    let bids = decode_side()?;
+    let asks = decode_side()?;
+    if !data.is_empty() {
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok((bids, asks))
 }
 
 impl<'a, C> OrderBookState<'a, C> {
@@ -95,6 +290,28 @@ impl<'a, C> OrderBookState<'a, C> {
         (best_bid_price, best_ask_price)
     }
 
+    /// Checks whether the best bid is strictly above the best ask, which matching should never leave behind.
+    ///
+    /// A locked book, where the best bid and ask are equal, isn't reported since orders which don't cross on equal
+    /// prices can legitimately produce one.
+    pub fn is_crossed(&self) -> bool {
+        match self.get_spread() {
+            (Some(best_bid), Some(best_ask)) => best_bid > best_ask,
+            _ => false,
+        }
+    }
+
+    /// Returns the handle of the order at the given position on one side of the orderbook, starting from the best one.
+    pub fn nth_best(&self, side: Side, n: usize) -> Option<NodeHandle> {
+        if n == 0 {
+            return self.find_bbo(side);
+        }
+        match side {
+            Side::Bid => self.bids.leaf_handles(false).nth(n),
+            Side::Ask => self.asks.leaf_handles(true).nth(n),
+        }
+    }
+
     pub fn get_tree(&mut self, side: Side) -> &mut Slab<'a, C> {
         match side {
             Side::Bid => &mut self.bids,
@@ -105,32 +322,270 @@ impl<'a, C> OrderBookState<'a, C> {
     pub fn is_empty(&self) -> bool {
         self.asks.header.leaf_count == 0 && self.bids.header.leaf_count == 0
     }
+
+    /// Returns the best price on the given side, along with the total base quantity of all orders at that price.
+    ///
+    /// Only the best price's subtree is traversed. The total quantity saturates at `u64::MAX`.
+    pub fn best_level(&self, side: Side) -> Option<(u64, u64)> {
+        let slab = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let best_price = slab.leaf_nodes[self.find_bbo(side)? as usize].price();
+        let total_base_qty = slab
+            .subtree_leaf_handles(slab.find_price_subtree(best_price), true)
+            .fold(0u64, |total, h| {
+                total.saturating_add(slab.leaf_nodes[h as usize].base_quantity)
+            });
+        Some((best_price, total_base_qty))
+    }
+
+    /// Returns the best level on each side of the orderbook, see [`OrderBookState::best_level`].
+    pub fn top_of_book(&self) -> TopOfBook {
+        let (bid_price, bid_qty) = self.best_level(Side::Bid).unzip();
+        let (ask_price, ask_qty) = self.best_level(Side::Ask).unzip();
+        TopOfBook {
+            bid_price,
+            bid_qty: bid_qty.unwrap_or(0),
+            ask_price,
+            ask_qty: ask_qty.unwrap_or(0),
+        }
+    }
+
+    /// Computes the base and quote quantities which a taker order on the given side would fill, without modifying the orderbook.
+    ///
+    /// Opposite orders priced within `limit_price` (inclusive) are walked from the best one, and quantities are computed as
+    /// in [`OrderBookState::new_order`], skipping all-or-nothing orders which can't be entirely filled. Self trades and
+    /// match limits aren't taken into account.
+    pub fn available_fill(
+        &self,
+        side: Side,
+        limit_price: u64,
+        max_base_qty: u64,
+        max_quote_qty: u64,
+    ) -> Result<(u64, u64), AoError> {
+        let opposite_slab = match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        };
+        let mut base_qty_remaining = max_base_qty;
+        let mut quote_qty_remaining = max_quote_qty;
+        for h in opposite_slab.leaf_handles(side == Side::Bid) {
+            let leaf = &opposite_slab.leaf_nodes[h as usize];
+            let trade_price = leaf.price();
+            let crosses = match side {
+                Side::Bid => trade_price <= limit_price,
+                Side::Ask => trade_price >= limit_price,
+            };
+            if !crosses {
+                break;
+            }
+            let base_trade_qty = leaf
+                .base_quantity
+                .min(base_qty_remaining)
+                .min(fp32_div(quote_qty_remaining, trade_price).unwrap_or(u64::MAX));
+            if base_trade_qty == 0 {
+                break;
+            }
+            if base_trade_qty < leaf.base_quantity && leaf.has_flag(LeafFlag::AllOrNothing) {
+                continue;
+            }
+            let quote_trade_qty = match side {
+                Side::Bid => fp32_mul_ceil(base_trade_qty, trade_price),
+                Side::Ask => fp32_mul_floor(base_trade_qty, trade_price),
+            }
+            .map(|q| q.min(quote_qty_remaining))
+            .ok_or(AoError::NumericalOverflow)?;
+            if quote_trade_qty == 0 {
+                break;
+            }
+            base_qty_remaining -= base_trade_qty;
+            quote_qty_remaining -= quote_trade_qty;
+        }
+        Ok((
+            max_base_qty - base_qty_remaining,
+            max_quote_qty - quote_qty_remaining,
+        ))
+    }
+
+    /// Counts the distinct price levels on the given side which are strictly better than the given price, up to `max_levels`
+    fn levels_ahead(&self, side: Side, price: u64, max_levels: u32) -> u32 {
+        let slab = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let mut levels = 0;
+        let mut last_price = None;
+        for h in slab.leaf_handles(side == Side::Ask) {
+            let level_price = slab.leaf_nodes[h as usize].price();
+            let is_better = match side {
+                Side::Bid => level_price > price,
+                Side::Ask => level_price < price,
+            };
+            if !is_better || levels == max_levels {
+                break;
+            }
+            if last_price != Some(level_price) {
+                levels += 1;
+                last_price = Some(level_price);
+            }
+        }
+        levels
+    }
+
+    /// Checks whether no order is left at the given price on the given side
+    fn level_cleared(&self, side: Side, price: u64) -> bool {
+        let slab = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        slab.find_price_subtree(price).is_none()
+    }
 }
 
 impl<'a, C: CallbackInfo> OrderBookState<'a, C>
 where
     <C as CallbackInfo>::CallbackId: PartialEq,
 {
+    /// Executes a new order at the unix timestamp `current_ts`, which is used to expire the maker orders it reaches.
     pub fn new_order(
         &mut self,
         params: new_order::Params<C>,
         event_queue: &mut EventQueue<'a, C>,
-        min_base_order_size: u64,
+        market_state: &MarketState,
+        current_ts: u64,
     ) -> Result<OrderSummary, AoError> {
+        self.new_order_with_policy(
+            params,
+            event_queue,
+            market_state,
+            current_ts,
+            Self::default_slab_full_action,
+        )
+    }
+
+    /// The default out-of-space policy: an order is posted by booting the least aggressive order only if it is more
+    /// aggressive, since it would otherwise be the first one booted out of the full book.
+    pub fn default_slab_full_action(&self, params: &new_order::Params<C>) -> SlabFullAction {
+        let slab = match params.side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let worst_price = match slab.price_extremes() {
+            Some((_, worst_price)) => worst_price,
+            None => return SlabFullAction::Boot,
+        };
+        let is_more_aggressive = match params.side {
+            Side::Bid => worst_price < params.limit_price,
+            Side::Ask => worst_price > params.limit_price,
+        };
+        if is_more_aggressive {
+            SlabFullAction::Boot
+        } else {
+            SlabFullAction::Skip
+        }
+    }
+
+    /// Executes a new order like [`OrderBookState::new_order`], consulting `on_slab_full` when the order should be
+    /// posted to a full side of the orderbook.
+    pub fn new_order_with_policy<F>(
+        &mut self,
+        mut params: new_order::Params<C>,
+        event_queue: &mut EventQueue<'a, C>,
+        market_state: &MarketState,
+        current_ts: u64,
+        mut on_slab_full: F,
+    ) -> Result<OrderSummary, AoError>
+    where
+        F: FnMut(&Self, &new_order::Params<C>) -> SlabFullAction,
+    {
         let new_order::Params {
             max_base_qty,
             max_quote_qty,
             side,
-            limit_price,
+            mut limit_price,
             callback_info,
             post_only,
-            post_allowed,
+            post_allowed: _,
             self_trade_behavior,
             mut match_limit,
+            round_to_tick: _,
+            require_top_of_book,
+            post_full_base,
+            peg_offset: _,
+            cross_on_equal,
+            taker_mode,
+            immediate_or_cancel: _,
+            fill_or_kill,
+            order_type,
+            max_ts,
+            display_qty,
+            post_only_slide,
+            all_or_nothing,
+            min_fill_base_qty,
         } = params;
 
+        // Best effort orders are only ever matched
+        let best_effort = taker_mode == TakerMode::BestEffort;
+        let post_only = post_only && !best_effort;
+        let post_allowed = params.may_post();
+
+        let min_base_order_size = market_state.min_base_order_size;
+        let emit_level_cleared = market_state.has_flag(MarketFlag::EmitLevelCleared);
+        let self_trade_check_enabled = market_state.self_trade_check_enabled();
+        let pro_rata = market_state.matching_mode() == MatchingMode::ProRata;
+
+        let is_market_order = order_type == OrderType::Market;
+
+        if !is_market_order && limit_price > market_state.max_price {
+            msg!("The limit price exceeds the market's maximum price");
+            return Err(AoError::LimitPriceTooHigh);
+        }
+
+        // The crossing liquidity is checked up front so that an unfulfilled order doesn't modify the orderbook
+        let inclusive_limit_price = match (side, cross_on_equal) {
+            _ if is_market_order => match side {
+                Side::Bid => u64::MAX,
+                Side::Ask => 0,
+            },
+            (_, true) => limit_price,
+            (Side::Bid, false) => limit_price.saturating_sub(1),
+            (Side::Ask, false) => limit_price.saturating_add(1),
+        };
+        let simulated_fill = if fill_or_kill || min_fill_base_qty != 0 {
+            Some(self.simulate_matching(
+                &params,
+                inclusive_limit_price,
+                market_state,
+                current_ts,
+            )?)
+        } else {
+            None
+        };
+        let fill_or_kill_base_qty = match simulated_fill {
+            Some((_, _, false)) if fill_or_kill => {
+                msg!("The fill or kill order can't be entirely filled");
+                return Err(AoError::FillOrKillUnfulfilled);
+            }
+            Some((base_qty, _, true)) if fill_or_kill => Some(base_qty),
+            _ => None,
+        };
+        if let Some((base_qty, _, _)) = simulated_fill {
+            if base_qty < min_fill_base_qty {
+                msg!("The order's minimum fill quantity can't be matched");
+                return Err(AoError::MinFillNotMet);
+            }
+        }
+
         let mut base_qty_remaining = max_base_qty;
         let mut quote_qty_remaining = max_quote_qty;
+        let mut matched_order_count = 0;
+
+        // The price level currently being matched against, and the base quantity consumed from it so far
+        let mut current_level = (0, 0);
+
+        // All-or-nothing orders which couldn't be entirely filled are left behind, at the front of the opposite side
+        let mut skipped_orders = 0;
 
         // New bid
         let mut crossed = true;
@@ -138,7 +593,7 @@ where
             if match_limit == 0 {
                 break;
             }
-            let best_bo_h = match self.find_bbo(side.opposite()) {
+            let best_bo_h = match self.nth_best(side.opposite(), skipped_orders) {
                 None => {
                     crossed = false;
                     break;
@@ -151,15 +606,199 @@ where
             let best_bo_ref = &mut opposite_slab.leaf_nodes[best_bo_h as usize];
 
             let trade_price = best_bo_ref.price();
-            crossed = match side {
-                Side::Bid => limit_price >= trade_price,
-                Side::Ask => limit_price <= trade_price,
-            };
+            crossed = is_market_order
+                || match side {
+                    Side::Bid => limit_price > trade_price,
+                    Side::Ask => limit_price < trade_price,
+                }
+                || (cross_on_equal && limit_price == trade_price);
+
+            if post_only && crossed && post_only_slide {
+                // The order is repriced one tick inside the best opposite price so that it doesn't cross anymore
+                let tick_size = market_state.tick_size.max(1);
+                let slid_price = match side {
+                    Side::Bid => trade_price.checked_sub(tick_size),
+                    Side::Ask => trade_price
+                        .checked_add(tick_size)
+                        .filter(|&p| p <= market_state.max_price),
+                };
+                if let Some(slid_price) = slid_price.filter(|&p| p != 0) {
+                    limit_price = slid_price;
+                    params.limit_price = slid_price;
+                    crossed = false;
+                }
+            }
 
             if post_only || !crossed {
                 break;
             }
 
+            if current_level.0 != trade_price {
+                current_level = (trade_price, 0);
+            }
+
+            // Expired orders are removed instead of being matched
+            if best_bo_ref.is_expired(current_ts) {
+                let expired_offer_id = best_bo_ref.order_id();
+                let expired_out = OutEvent::new(
+                    side.opposite(),
+                    expired_offer_id,
+                    best_bo_ref.base_quantity,
+                    OutReason::Expired,
+                );
+                event_queue
+                    .push_back(
+                        expired_out,
+                        Some(&opposite_slab.callback_infos[best_bo_h as usize]),
+                        None,
+                    )
+                    .map_err(|_| AoError::EventQueueFull)?;
+
+                self.get_tree(side.opposite())
+                    .remove_by_key(expired_offer_id)
+                    .unwrap();
+                if emit_level_cleared {
+                    self.push_level_cleared(event_queue, side.opposite(), current_level)?;
+                }
+
+                match_limit -= 1;
+
+                continue;
+            }
+
+            // In pro-rata mode, a price level which can't be entirely filled is shared between its orders. Orders
+            // which have expired are left untouched, and orders which would self trade are handled according to the
+            // self trade behavior before the allocation.
+            if pro_rata {
+                let level_base_qty = base_qty_remaining
+                    .min(fp32_div(quote_qty_remaining, trade_price).unwrap_or(u64::MAX));
+                let detects_self_trade = self_trade_check_enabled
+                    && self_trade_behavior != SelfTradeBehavior::DecrementTake
+                    && std::mem::size_of::<C::CallbackId>() != 0;
+                let is_self_trade = |maker_callback_info: &C| {
+                    detects_self_trade
+                        && callback_info.as_callback_id() == maker_callback_info.as_callback_id()
+                };
+                let quote_for = |base_qty: u64| match side {
+                    Side::Bid => fp32_mul_ceil(base_qty, trade_price),
+                    Side::Ask => fp32_mul_floor(base_qty, trade_price),
+                };
+                let is_eligible = |leaf: &LeafNode| {
+                    !leaf.is_expired(current_ts) && !leaf.has_flag(LeafFlag::AllOrNothing)
+                };
+                let allocations = self
+                    .pro_rata_allocations(
+                        side.opposite(),
+                        trade_price,
+                        level_base_qty,
+                        match_limit,
+                        |leaf, maker_callback_info| {
+                            (is_eligible(leaf) && !is_self_trade(maker_callback_info))
+                                .then_some(leaf.base_quantity)
+                        },
+                    )
+                    // Allocations whose quote quantity rounds down to zero would drop part of the taker's quantity,
+                    // so such levels are matched in price-time priority instead
+                    .filter(|allocations| {
+                        allocations
+                            .iter()
+                            .all(|&(_, base_qty)| base_qty == 0 || quote_for(base_qty) != Some(0))
+                    });
+                if let Some(allocations) = allocations {
+                    let self_trading_orders = self.level_orders(
+                        side.opposite(),
+                        trade_price,
+                        |leaf, maker_callback_info| {
+                            is_eligible(leaf) && is_self_trade(maker_callback_info)
+                        },
+                    );
+                    if !self_trading_orders.is_empty() {
+                        if self_trade_behavior == SelfTradeBehavior::AbortTransaction {
+                            if best_effort {
+                                break;
+                            }
+                            return Err(AoError::WouldSelfTrade);
+                        }
+                        for order_id in self_trading_orders {
+                            let (leaf, provide_out_callback_info) = self
+                                .get_tree(side.opposite())
+                                .remove_by_key(order_id)
+                                .unwrap();
+                            let provide_out = OutEvent::new(
+                                side.opposite(),
+                                order_id,
+                                leaf.base_quantity,
+                                OutReason::SelfTrade,
+                            );
+                            event_queue
+                                .push_back(
+                                    provide_out,
+                                    Some(&provide_out_callback_info),
+                                    Some(&callback_info),
+                                )
+                                .map_err(|_| AoError::EventQueueFull)?;
+                        }
+                        if self_trade_behavior == SelfTradeBehavior::CancelBoth {
+                            // The current order's remaining quantity is cancelled as well
+                            break;
+                        }
+                    }
+                    for (maker_order_id, base_trade_qty) in allocations {
+                        if base_trade_qty == 0 {
+                            continue;
+                        }
+                        let quote_maker_qty = quote_for(base_trade_qty)
+                            .map(|q| std::cmp::min(q, quote_qty_remaining))
+                            .ok_or(AoError::NumericalOverflow)?;
+
+                        let opposite_slab = self.get_tree(side.opposite());
+                        let maker_h = opposite_slab.find_by_key(maker_order_id).unwrap();
+                        let maker_ref = &mut opposite_slab.leaf_nodes[maker_h as usize];
+
+                        let maker_fill = FillEvent {
+                            taker_side: side as u8,
+                            #[cfg(target_os = "solana")]
+                            maker_order_id,
+                            #[cfg(not(target_os = "solana"))]
+                            maker_order_id: [maker_order_id as u64, (maker_order_id >> 64) as u64],
+                            quote_size: quote_maker_qty,
+                            base_size: base_trade_qty,
+                            price: trade_price,
+                            tag: EventTag::Fill as u8,
+                            _padding: [0; 6],
+                        };
+                        event_queue
+                            .push_back(
+                                maker_fill,
+                                Some(&opposite_slab.callback_infos[maker_h as usize]),
+                                Some(&callback_info),
+                            )
+                            .map_err(|_| AoError::EventQueueFull)?;
+                        matched_order_count += 1;
+
+                        maker_ref.base_quantity -= base_trade_qty;
+                        base_qty_remaining -= base_trade_qty;
+                        quote_qty_remaining -= quote_maker_qty;
+                        current_level.1 += base_trade_qty;
+
+                        if maker_ref.base_quantity < min_base_order_size {
+                            self.remove_filled_order(
+                                event_queue,
+                                side.opposite(),
+                                maker_order_id,
+                                current_level,
+                                emit_level_cleared,
+                            )?;
+                        }
+                    }
+                    // The taker's quantity was smaller than the level's, so it has been entirely matched
+                    break;
+                }
+            }
+
+            let opposite_slab = self.get_tree(side.opposite());
+            let best_bo_ref = &mut opposite_slab.leaf_nodes[best_bo_h as usize];
+
             let offer_size = best_bo_ref.base_quantity;
             let base_trade_qty = offer_size
                 .min(base_qty_remaining)
@@ -169,6 +808,12 @@ where
                 break;
             }
 
+            if base_trade_qty < offer_size && best_bo_ref.has_flag(LeafFlag::AllOrNothing) {
+                skipped_orders += 1;
+                match_limit -= 1;
+                continue;
+            }
+
             let quote_maker_qty = match side {
                 Side::Bid => fp32_mul_ceil(base_trade_qty, trade_price),
                 Side::Ask => fp32_mul_floor(base_trade_qty, trade_price),
@@ -182,36 +827,53 @@ where
 
             // The decrement take case can be handled by the caller program on event consumption, so no special logic
             // is needed for it.
-            if self_trade_behavior != SelfTradeBehavior::DecrementTake {
+            // An empty callback id carries no identity, which means that self trading cannot be detected.
+            if self_trade_check_enabled
+                && self_trade_behavior != SelfTradeBehavior::DecrementTake
+                && std::mem::size_of::<C::CallbackId>() != 0
+            {
                 let order_would_self_trade = callback_info.as_callback_id()
                     == opposite_slab.callback_infos[best_bo_h as usize].as_callback_id();
                 if order_would_self_trade {
                     let best_offer_id = best_bo_ref.order_id();
 
                     if self_trade_behavior == SelfTradeBehavior::AbortTransaction {
+                        if best_effort {
+                            break;
+                        }
                         return Err(AoError::WouldSelfTrade);
                     }
-                    assert!(self_trade_behavior == SelfTradeBehavior::CancelProvide);
+                    assert!(
+                        self_trade_behavior == SelfTradeBehavior::CancelProvide
+                            || self_trade_behavior == SelfTradeBehavior::CancelBoth
+                    );
                     let provide_out_callback_info =
                         &opposite_slab.callback_infos[best_bo_h as usize];
-                    #[cfg(target_os = "solana")]
-                    let order_id = best_offer_id;
-                    #[cfg(not(target_os = "solana"))]
-                    let order_id = [best_offer_id as u64, (best_offer_id >> 64) as u64];
-                    let provide_out = OutEvent {
-                        side: side.opposite() as u8,
-                        order_id,
-                        base_size: best_bo_ref.base_quantity,
-                        tag: EventTag::Out as u8,
-                        _padding: [0; 14],
-                    };
+                    let provide_out = OutEvent::new(
+                        side.opposite(),
+                        best_offer_id,
+                        best_bo_ref.base_quantity,
+                        OutReason::SelfTrade,
+                    );
                     event_queue
-                        .push_back(provide_out, Some(provide_out_callback_info), None)
+                        .push_back(
+                            provide_out,
+                            Some(provide_out_callback_info),
+                            Some(&callback_info),
+                        )
                         .map_err(|_| AoError::EventQueueFull)?;
 
                     self.get_tree(side.opposite())
                         .remove_by_key(best_offer_id)
                         .unwrap();
+                    if emit_level_cleared {
+                        self.push_level_cleared(event_queue, side.opposite(), current_level)?;
+                    }
+
+                    if self_trade_behavior == SelfTradeBehavior::CancelBoth {
+                        // The current order's remaining quantity is cancelled as well
+                        break;
+                    }
 
                     match_limit -= 1;
 
@@ -234,48 +896,75 @@ where
                 maker_order_id,
                 quote_size: quote_maker_qty,
                 base_size: base_trade_qty,
+                price: trade_price,
                 tag: EventTag::Fill as u8,
                 _padding: [0; 6],
             };
             event_queue
                 .push_back(maker_fill, Some(maker_callback_info), Some(&callback_info))
                 .map_err(|_| AoError::EventQueueFull)?;
+            matched_order_count += 1;
 
             best_bo_ref.base_quantity -= base_trade_qty;
             base_qty_remaining -= base_trade_qty;
             quote_qty_remaining -= quote_maker_qty;
+            current_level.1 += base_trade_qty;
 
             if best_bo_ref.base_quantity < min_base_order_size {
                 let best_offer_id = best_bo_ref.order_id();
-                let cur_side = side.opposite();
-                #[cfg(target_os = "solana")]
-                let order_id = best_offer_id;
-                #[cfg(not(target_os = "solana"))]
-                let order_id = [best_offer_id as u64, (best_offer_id >> 64) as u64];
-                let out_event = OutEvent {
-                    side: cur_side as u8,
-                    order_id,
-                    base_size: best_bo_ref.base_quantity,
-                    tag: EventTag::Out as u8,
-                    _padding: [0; 14],
-                };
-
-                let (_, out_event_callback_info) = self
-                    .get_tree(cur_side)
-                    .remove_by_key(best_offer_id)
-                    .unwrap();
-                event_queue
-                    .push_back(out_event, Some(out_event_callback_info), None)
-                    .map_err(|_| AoError::EventQueueFull)?;
+                self.remove_filled_order(
+                    event_queue,
+                    side.opposite(),
+                    best_offer_id,
+                    current_level,
+                    emit_level_cleared,
+                )?;
             }
 
             match_limit -= 1;
         }
 
-        let base_qty_to_post = std::cmp::min(
-            fp32_div(quote_qty_remaining, limit_price).unwrap_or(u64::MAX),
-            base_qty_remaining,
-        );
+        // The liquidity checks mirror the matching loop, so these only guard against the two diverging. Failing at
+        // this point still reverts the instruction's changes.
+        if let Some(fill_or_kill_base_qty) = fill_or_kill_base_qty {
+            if max_base_qty - base_qty_remaining < fill_or_kill_base_qty {
+                msg!("The fill or kill order can't be entirely filled");
+                return Err(AoError::FillOrKillUnfulfilled);
+            }
+        }
+        if max_base_qty - base_qty_remaining < min_fill_base_qty {
+            msg!("The order's minimum fill quantity can't be matched");
+            return Err(AoError::MinFillNotMet);
+        }
+
+        // Market orders have no price to be posted at
+        if is_market_order {
+            return Ok(OrderSummary {
+                posted_order_id: None,
+                total_base_qty: max_base_qty - base_qty_remaining,
+                total_quote_qty: max_quote_qty - quote_qty_remaining,
+                total_base_qty_posted: 0,
+                posted_price: None,
+                post_skipped_below_min: false,
+                post_skipped_book_full: false,
+                matched_order_count,
+            });
+        }
+
+        let base_qty_to_post = if post_full_base {
+            base_qty_remaining
+        } else {
+            // The quote budget is converted in a widened intermediate, so that large budgets such as u64::MAX are
+            // clamped instead of limiting the posted quantity
+            let quote_limited_base_qty = ((quote_qty_remaining as u128) << 32)
+                .checked_div(limit_price as u128)
+                .unwrap_or(u128::MAX)
+                .min(u64::MAX as u128) as u64;
+            std::cmp::min(quote_limited_base_qty, base_qty_remaining)
+        };
+
+        // Posting behind a skipped all-or-nothing order would cross the orderbook
+        let crossed = crossed || skipped_orders != 0;
 
         if crossed || !post_allowed || base_qty_to_post < min_base_order_size {
             return Ok(OrderSummary {
@@ -283,9 +972,59 @@ where
                 total_base_qty: max_base_qty - base_qty_remaining,
                 total_quote_qty: max_quote_qty - quote_qty_remaining,
                 total_base_qty_posted: 0,
+                posted_price: None,
+                post_skipped_below_min: !crossed && post_allowed && base_qty_to_post != 0,
+                post_skipped_book_full: false,
+                matched_order_count,
             });
         }
 
+        if require_top_of_book {
+            if let Some(best_h) = self.find_bbo(side) {
+                let best_price = self.get_tree(side).leaf_nodes[best_h as usize].price();
+                let improves = match side {
+                    Side::Bid => limit_price >= best_price,
+                    Side::Ask => limit_price <= best_price,
+                };
+                if !improves {
+                    msg!("The order would not post at the top of the book");
+                    return Err(AoError::WouldNotImprove);
+                }
+            }
+        }
+
+        let max_post_depth = market_state.max_post_depth;
+        if max_post_depth != 0
+            && self.levels_ahead(side, limit_price, max_post_depth) == max_post_depth
+        {
+            msg!("The order would post beyond the market's maximum depth");
+            return Err(AoError::TooDeep);
+        }
+
+        // The policy is consulted before generating an order id, so that skipped orders don't consume one
+        if self.get_tree(side).is_full() {
+            match on_slab_full(self, &params) {
+                SlabFullAction::Boot => {}
+                SlabFullAction::Reject => {
+                    msg!("Orderbook is full! rejecting the order");
+                    return Err(AoError::SlabOutOfSpace);
+                }
+                SlabFullAction::Skip => {
+                    msg!("Orderbook is full! skipping the order's post");
+                    return Ok(OrderSummary {
+                        posted_order_id: None,
+                        total_base_qty: max_base_qty - base_qty_remaining,
+                        total_quote_qty: max_quote_qty - quote_qty_remaining,
+                        total_base_qty_posted: 0,
+                        posted_price: None,
+                        post_skipped_below_min: false,
+                        post_skipped_book_full: true,
+                        matched_order_count,
+                    });
+                }
+            }
+        }
+
         let new_leaf_order_id = event_queue.gen_order_id(limit_price, side);
         let new_leaf = LeafNode {
             key: {
@@ -296,8 +1035,17 @@ where
                 k
             },
             base_quantity: base_qty_to_post,
+            max_ts,
+            display_qty,
+            session_id: market_state.current_session,
+            flags: if all_or_nothing {
+                LeafFlag::AllOrNothing as u8
+            } else {
+                0
+            },
+            _padding: [0; 3],
         };
-        let insert_result = self.get_tree(side).insert_leaf(&new_leaf);
+        let insert_result = self.get_tree(side).insert_leaf(&new_leaf, false);
         let k = if let Err(AoError::SlabOutOfSpace) = insert_result {
             // Boot out the least aggressive orders
             msg!("Orderbook is full! booting least aggressive orders...");
@@ -306,77 +1054,816 @@ where
                 Side::Bid => slab.find_min().unwrap(),
                 Side::Ask => slab.find_max().unwrap(),
             };
-            // let boot_candidate_key = slab.leaf_nodes[boot_candidate as usize].key;
+            // The out-of-space policy chose to boot the least aggressive order at this point
             let boot_candidate_key = slab.leaf_nodes[boot_candidate as usize].order_id();
-            let boot_candidate_price = LeafNode::price_from_key(boot_candidate_key);
-            let should_boot = match side {
-                Side::Bid => boot_candidate_price < limit_price,
-                Side::Ask => boot_candidate_price > limit_price,
-            };
-            if should_boot {
-                let (order, callback_info_booted) = slab.remove_by_key(boot_candidate_key).unwrap();
-                #[allow(clippy::let_and_return)]
-                let out = OutEvent {
-                    side: side as u8,
-                    order_id: {
-                        let o = order.order_id();
-                        #[cfg(not(target_os = "solana"))]
-                        let o = [o as u64, (o >> 64) as u64];
-                        o
-                    },
-                    base_size: order.base_quantity,
-                    tag: EventTag::Out as u8,
-                    _padding: [0; 14],
-                };
-                event_queue
-                    .push_back(out, Some(callback_info_booted), None)
-                    .map_err(|_| AoError::EventQueueFull)?;
-                slab.insert_leaf(&new_leaf).unwrap().0
-            } else {
-                return Ok(OrderSummary {
-                    posted_order_id: None,
-                    total_base_qty: max_base_qty - base_qty_remaining,
-                    total_quote_qty: max_quote_qty - quote_qty_remaining,
-                    total_base_qty_posted: 0,
-                });
+            // The booted order's out event must fit in the queue, otherwise the order would be lost
+            if event_queue.full() {
+                return Err(AoError::EventQueueFull);
             }
+            let (order, callback_info_booted) = slab.remove_by_key(boot_candidate_key).unwrap();
+            let out = OutEvent::new(
+                side,
+                order.order_id(),
+                order.base_quantity,
+                OutReason::Booted,
+            );
+            event_queue
+                .push_back(out, Some(&callback_info_booted), None)
+                .map_err(|_| AoError::EventQueueFull)?;
+            slab.insert_leaf(&new_leaf, false)?.0
         } else {
-            insert_result.unwrap().0
+            insert_result?.0
         };
         *self.get_tree(side).get_callback_info_mut(k) = callback_info;
         base_qty_remaining -= base_qty_to_post;
-        quote_qty_remaining -= match side {
+        let quote_qty_posted = match side {
             Side::Bid => fp32_mul_ceil(base_qty_to_post, limit_price),
             Side::Ask => fp32_mul_floor(base_qty_to_post, limit_price),
         }
         .ok_or(AoError::NumericalOverflow)?;
+        // The posted quote quantity only exceeds the remaining budget when post_full_base is set
+        let total_quote_qty = (max_quote_qty - quote_qty_remaining)
+            .checked_add(quote_qty_posted)
+            .ok_or(AoError::NumericalOverflow)?;
         Ok(OrderSummary {
             posted_order_id: Some(new_leaf_order_id),
             total_base_qty: max_base_qty - base_qty_remaining,
-            total_quote_qty: max_quote_qty - quote_qty_remaining,
+            total_quote_qty,
             total_base_qty_posted: base_qty_to_post,
+            posted_price: Some(limit_price),
+            post_skipped_below_min: false,
+            post_skipped_book_full: false,
+            matched_order_count,
         })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::state::event_queue::{EventRef, FillEventRef, OutEventRef};
+    /// Matches a taker order against a single maker order, leaving the rest of the orderbook untouched.
+    ///
+    /// The taker's limit price must cross the maker order's price, and self trading is rejected unless the market disables
+    /// self trade checks.
+    /// A fill event is pushed, followed by an out event if what remains of the maker order is below the market's minimum order size.
+    /// Fills which are empty, or whose quote quantity rounds down to zero, are rejected, and so are partial fills of
+    /// all-or-nothing orders.
+    ///
+    /// If the maker order has expired at the unix timestamp `current_ts`, it is removed with an out event instead of being filled.
+    pub fn take_order(
+        &mut self,
+        params: take_order::Params<C>,
+        event_queue: &mut EventQueue<'a, C>,
+        market_state: &MarketState,
+        current_ts: u64,
+    ) -> Result<OrderSummary, AoError> {
+        let take_order::Params {
+            order_id,
+            max_base_qty,
+            limit_price,
+            callback_info,
+        } = params;
+        let maker_side = get_side_from_order_id(order_id);
+        let taker_side = maker_side.opposite();
 
-    use super::*;
+        let slab = self.get_tree(maker_side);
+        let maker_h = slab.find_by_key(order_id).ok_or(AoError::OrderNotFound)?;
+        let maker_callback_info = slab.callback_infos[maker_h as usize];
+        let maker_ref = &mut slab.leaf_nodes[maker_h as usize];
 
-    type SlabTest<'a> = Slab<'a, [u8; 32]>;
-    type OrderBookStateTest<'a> = OrderBookState<'a, [u8; 32]>;
-    type EventQueueTest<'a> = EventQueue<'a, [u8; 32]>;
+        let trade_price = maker_ref.price();
+        let crossed = match taker_side {
+            Side::Bid => limit_price >= trade_price,
+            Side::Ask => limit_price <= trade_price,
+        };
+        if !crossed {
+            return Err(AoError::OrderDoesNotCross);
+        }
+        if maker_ref.is_expired(current_ts) {
+            let out = OutEvent::new(
+                maker_side,
+                order_id,
+                maker_ref.base_quantity,
+                OutReason::Expired,
+            );
+            event_queue
+                .push_back(out, Some(&maker_callback_info), None)
+                .map_err(|_| AoError::EventQueueFull)?;
+            slab.remove_by_key(order_id).unwrap();
+            return Ok(OrderSummary {
+                posted_order_id: None,
+                total_base_qty: 0,
+                total_quote_qty: 0,
+                total_base_qty_posted: 0,
+                posted_price: None,
+                post_skipped_below_min: false,
+                post_skipped_book_full: false,
+                matched_order_count: 0,
+            });
+        }
+        if market_state.self_trade_check_enabled()
+            && std::mem::size_of::<C::CallbackId>() != 0
+            && callback_info.as_callback_id() == maker_callback_info.as_callback_id()
+        {
+            return Err(AoError::WouldSelfTrade);
+        }
 
-    pub struct TestContext {
-        asks_buffer: Vec<u8>,
-        bids_buffer: Vec<u8>,
-        event_queue_buffer: Vec<u8>,
-    }
+        let base_trade_qty = maker_ref.base_quantity.min(max_base_qty);
+        if base_trade_qty == 0 {
+            msg!("The base quantity to take must be > 0");
+            return Err(AoError::InvalidBaseQuantity);
+        }
+        if base_trade_qty < maker_ref.base_quantity && maker_ref.has_flag(LeafFlag::AllOrNothing) {
+            msg!("The all-or-nothing maker order can't be partially filled");
+            return Err(AoError::PartialAllOrNothingFill);
+        }
+        let quote_maker_qty = match taker_side {
+            Side::Bid => fp32_mul_ceil(base_trade_qty, trade_price),
+            Side::Ask => fp32_mul_floor(base_trade_qty, trade_price),
+        }
+        .ok_or(AoError::NumericalOverflow)?;
+        if quote_maker_qty == 0 {
+            msg!("The fill's quote quantity rounds down to zero");
+            return Err(AoError::InvalidBaseQuantity);
+        }
 
-    impl TestContext {
-        pub fn new(order_capacity: usize, event_capacity: usize) -> Self {
+        #[allow(clippy::let_and_return)]
+        let maker_fill = FillEvent {
+            taker_side: taker_side as u8,
+            maker_order_id: {
+                #[cfg(not(target_os = "solana"))]
+                let order_id = [order_id as u64, (order_id >> 64) as u64];
+                order_id
+            },
+            quote_size: quote_maker_qty,
+            base_size: base_trade_qty,
+            price: trade_price,
+            tag: EventTag::Fill as u8,
+            _padding: [0; 6],
+        };
+        event_queue
+            .push_back(maker_fill, Some(&maker_callback_info), Some(&callback_info))
+            .map_err(|_| AoError::EventQueueFull)?;
+
+        maker_ref.base_quantity -= base_trade_qty;
+        if maker_ref.base_quantity < market_state.min_base_order_size {
+            self.remove_filled_order(
+                event_queue,
+                maker_side,
+                order_id,
+                (trade_price, base_trade_qty),
+                market_state.has_flag(MarketFlag::EmitLevelCleared),
+            )?;
+        }
+
+        Ok(OrderSummary {
+            posted_order_id: None,
+            total_base_qty: base_trade_qty,
+            total_quote_qty: quote_maker_qty,
+            total_base_qty_posted: 0,
+            posted_price: None,
+            post_skipped_below_min: false,
+            post_skipped_book_full: false,
+            matched_order_count: 1,
+        })
+    }
+
+    /// Decreases the base quantity of a resting order in place, which preserves its order id and queue priority.
+    ///
+    /// An out event which doesn't delete the order is pushed, with the reduction as its base size. Increases are rejected
+    /// since they would need the order to be posted again, and the new quantity can't be 0 or below the market's minimum
+    /// order size. The order's displayed quantity is clamped to its new quantity.
+    pub fn modify_order(
+        &mut self,
+        params: modify_order::Params,
+        event_queue: &mut EventQueue<'a, C>,
+        market_state: &MarketState,
+    ) -> Result<OrderSummary, AoError> {
+        let modify_order::Params {
+            order_id,
+            new_base_qty,
+        } = params;
+        let side = get_side_from_order_id(order_id);
+
+        let slab = self.get_tree(side);
+        let h = slab.find_by_key(order_id).ok_or(AoError::OrderNotFound)?;
+        let leaf = slab.leaf_nodes[h as usize];
+        if new_base_qty > leaf.base_quantity {
+            msg!("The order's quantity can't be increased in place");
+            return Err(AoError::OrderSizeIncrease);
+        }
+        if new_base_qty == 0 {
+            msg!("Orders can't be reduced to 0, they must be cancelled instead");
+            return Err(AoError::InvalidBaseQuantity);
+        }
+        if new_base_qty < market_state.min_base_order_size {
+            msg!("The new quantity is below the market's minimum order size");
+            return Err(AoError::InvalidBaseQuantity);
+        }
+
+        let base_reduction = leaf.base_quantity - new_base_qty;
+        let quote_reduction =
+            fp32_mul_floor(base_reduction, leaf.price()).ok_or(AoError::NumericalOverflow)?;
+
+        if base_reduction != 0 {
+            // The order is only reduced, so it isn't deleted
+            let out_event = OutEvent {
+                delete: 0,
+                ..OutEvent::new(side, order_id, base_reduction, OutReason::Cancel)
+            };
+            event_queue
+                .push_back(out_event, Some(&slab.callback_infos[h as usize]), None)
+                .map_err(|_| AoError::EventQueueFull)?;
+        }
+        // The order is only modified once its out event is pushed
+        let leaf = &mut slab.leaf_nodes[h as usize];
+        leaf.base_quantity = new_base_qty;
+        leaf.display_qty = leaf.display_qty.min(new_base_qty);
+
+        Ok(OrderSummary {
+            posted_order_id: None,
+            total_base_qty: base_reduction,
+            total_quote_qty: quote_reduction,
+            total_base_qty_posted: 0,
+            posted_price: None,
+            post_skipped_below_min: false,
+            post_skipped_book_full: false,
+            matched_order_count: 0,
+        })
+    }
+
+    /// Removes up to `max_orders` orders which weren't posted during the current session, pushing an out event for each of them.
+    ///
+    /// Bids are flushed before asks. The returned summary describes what was left of the removed orders.
+    pub fn cancel_session_orders(
+        &mut self,
+        current_session: u32,
+        max_orders: u64,
+        event_queue: &mut EventQueue<'a, C>,
+    ) -> Result<OrderSummary, AoError> {
+        let mut order_summary = OrderSummary::default();
+        let mut remaining_orders = max_orders as usize;
+
+        for side in [Side::Bid, Side::Ask] {
+            let slab = self.get_tree(side);
+            let stale_order_ids = slab
+                .leaf_handles(true)
+                .map(|h| &slab.leaf_nodes[h as usize])
+                .filter(|leaf| leaf.session_id != current_session)
+                .map(|leaf| leaf.order_id())
+                .take(remaining_orders)
+                .collect::<Vec<_>>();
+            remaining_orders -= stale_order_ids.len();
+            self.remove_orders(
+                side,
+                stale_order_ids,
+                Some(&mut *event_queue),
+                &mut order_summary,
+            )?;
+        }
+
+        Ok(order_summary)
+    }
+
+    /// Removes up to `max_orders` orders from the orderbook, starting with the least aggressive ones on each side.
+    ///
+    /// Bids are pruned before asks. When `emit_events` is unset, no out events are pushed to the event queue and the caller
+    /// is responsible for settling the removed orders. The returned summary describes what was left of the removed orders.
+    pub fn prune_orders(
+        &mut self,
+        max_orders: u64,
+        emit_events: bool,
+        event_queue: &mut EventQueue<'a, C>,
+    ) -> Result<OrderSummary, AoError> {
+        self.prune_orders_from(max_orders, emit_events, None, event_queue)
+            .map(|(order_summary, _)| order_summary)
+    }
+
+    /// Prunes orders like [`OrderBookState::prune_orders`], skipping the orders which come before `cursor` in the pruning order.
+    ///
+    /// Along with the summary, returns the id of the last pruned order, which should be passed as the cursor of the next call.
+    /// No cursor is returned once every order past the given cursor has been pruned. Orders posted behind the cursor since the
+    /// previous call are not pruned, which makes it possible to clear a large book over several transactions.
+    pub fn prune_orders_from(
+        &mut self,
+        max_orders: u64,
+        emit_events: bool,
+        cursor: Option<u128>,
+        event_queue: &mut EventQueue<'a, C>,
+    ) -> Result<(OrderSummary, Option<u128>), AoError> {
+        let mut order_summary = OrderSummary::default();
+        let mut remaining_orders = max_orders as usize;
+        let mut last_order_id = cursor;
+
+        for side in [Side::Bid, Side::Ask] {
+            // Bids are pruned in ascending key order, then asks in descending key order
+            let is_past_cursor = |order_id: u128| match cursor {
+                None => true,
+                Some(cursor) => match (get_side_from_order_id(cursor), side) {
+                    (Side::Bid, Side::Bid) => order_id > cursor,
+                    (Side::Bid, Side::Ask) => true,
+                    (Side::Ask, Side::Bid) => false,
+                    (Side::Ask, Side::Ask) => order_id < cursor,
+                },
+            };
+            let slab = self.get_tree(side);
+            let order_ids = slab
+                .leaf_handles(side == Side::Bid)
+                .map(|h| slab.leaf_nodes[h as usize].order_id())
+                .skip_while(|&order_id| !is_past_cursor(order_id))
+                .take(remaining_orders)
+                .collect::<Vec<_>>();
+            remaining_orders -= order_ids.len();
+            if let Some(&order_id) = order_ids.last() {
+                last_order_id = Some(order_id);
+            }
+            self.remove_orders(
+                side,
+                order_ids,
+                emit_events.then_some(&mut *event_queue),
+                &mut order_summary,
+            )?;
+        }
+
+        // Falling short of the limit means that there is nothing left to prune
+        let next_cursor = if remaining_orders == 0 {
+            last_order_id
+        } else {
+            None
+        };
+        Ok((order_summary, next_cursor))
+    }
+
+    /// Removes the given orders from the orderbook, pushing an out event for each of them.
+    ///
+    /// The returned summary describes what was left of the removed orders. The operation fails if any of the orders can't be found,
+    /// if their cumulated quantities overflow, or if the event queue can't hold all of their out events.
+    pub fn cancel_orders(
+        &mut self,
+        order_ids: &[u128],
+        event_queue: &mut EventQueue<'a, C>,
+    ) -> Result<OrderSummary, AoError> {
+        if event_queue.remaining_capacity() < order_ids.len() as u64 {
+            return Err(AoError::EventQueueFull);
+        }
+        let mut order_summary = OrderSummary::default();
+        let (bid_order_ids, ask_order_ids) = partition_by_side(order_ids);
+        self.remove_orders(
+            Side::Bid,
+            bid_order_ids,
+            Some(&mut *event_queue),
+            &mut order_summary,
+        )?;
+        self.remove_orders(
+            Side::Ask,
+            ask_order_ids,
+            Some(event_queue),
+            &mut order_summary,
+        )?;
+        Ok(order_summary)
+    }
+
+    /// Removes the given orders from the orderbook, pushing an out event for each of them and skipping the ones which
+    /// can't be found.
+    ///
+    /// Along with the summary, returns whether each order id was cancelled, in the order they were given.
+    pub fn cancel_orders_with_outcomes(
+        &mut self,
+        order_ids: &[u128],
+        event_queue: &mut EventQueue<'a, C>,
+    ) -> Result<(OrderSummary, Vec<(u128, bool)>), AoError> {
+        let mut order_summary = OrderSummary::default();
+        let mut outcomes = Vec::with_capacity(order_ids.len());
+        for &order_id in order_ids {
+            let side = get_side_from_order_id(order_id);
+            let found = self.get_tree(side).find_by_key(order_id).is_some();
+            if found {
+                self.remove_orders(
+                    side,
+                    vec![order_id],
+                    Some(&mut *event_queue),
+                    &mut order_summary,
+                )?;
+            }
+            outcomes.push((order_id, found));
+        }
+        Ok((order_summary, outcomes))
+    }
+
+    /// Removes the given orders from one side of the orderbook, optionally pushing an out event for each of them,
+    /// and adds their remaining quantities to the order summary.
+    fn remove_orders(
+        &mut self,
+        side: Side,
+        order_ids: Vec<u128>,
+        mut event_queue: Option<&mut EventQueue<'a, C>>,
+        order_summary: &mut OrderSummary,
+    ) -> Result<(), AoError> {
+        // Checking for space beforehand ensures that no order is removed without its out event
+        if let Some(event_queue) = &event_queue {
+            if event_queue.remaining_capacity() < order_ids.len() as u64 {
+                return Err(AoError::EventQueueFull);
+            }
+        }
+        let slab = self.get_tree(side);
+        for order_id in order_ids {
+            let (leaf_node, callback_info) =
+                slab.remove_by_key(order_id).ok_or(AoError::OrderNotFound)?;
+            order_summary.total_base_qty = order_summary
+                .total_base_qty
+                .checked_add(leaf_node.base_quantity)
+                .ok_or(AoError::NumericalOverflow)?;
+            order_summary.total_quote_qty =
+                fp32_mul_floor(leaf_node.base_quantity, leaf_node.price())
+                    .and_then(|n| n.checked_add(order_summary.total_quote_qty))
+                    .ok_or(AoError::NumericalOverflow)?;
+            if let Some(event_queue) = event_queue.as_deref_mut() {
+                let out = OutEvent::new(side, order_id, leaf_node.base_quantity, OutReason::Cancel);
+                event_queue
+                    .push_back(out, Some(&callback_info), None)
+                    .map_err(|_| AoError::EventQueueFull)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks the opposite side of the orderbook like the matching loop of [`OrderBookState::new_order`] would, without
+    /// modifying it.
+    ///
+    /// Expired orders, self trades, all-or-nothing orders, pro-rata allocations and the match limit are handled as in
+    /// the matching loop, so that orders which can't be filled are rejected before the orderbook is modified. Returns
+    /// the base and quote quantities which would be filled, along with whether matching would stop because the taker's
+    /// budget is exhausted rather than because the orderbook ran out of matchable orders.
+    fn simulate_matching(
+        &self,
+        params: &new_order::Params<C>,
+        inclusive_limit_price: u64,
+        market_state: &MarketState,
+        current_ts: u64,
+    ) -> Result<(u64, u64, bool), AoError> {
+        let side = params.side;
+        let best_effort = params.taker_mode == TakerMode::BestEffort;
+        let self_trade_behavior = params.self_trade_behavior;
+        let detects_self_trade = market_state.self_trade_check_enabled()
+            && self_trade_behavior != SelfTradeBehavior::DecrementTake
+            && std::mem::size_of::<C::CallbackId>() != 0;
+        let is_self_trade = |maker_callback_info: &C| {
+            detects_self_trade
+                && params.callback_info.as_callback_id() == maker_callback_info.as_callback_id()
+        };
+        let is_eligible = |leaf: &LeafNode| {
+            !leaf.is_expired(current_ts) && !leaf.has_flag(LeafFlag::AllOrNothing)
+        };
+        let opposite_slab = match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        };
+
+        let mut base_qty_remaining = params.max_base_qty;
+        let mut quote_qty_remaining = params.max_quote_qty;
+        let mut match_limit = params.match_limit;
+        let mut budget_exhausted = false;
+        let filled = |base_qty_remaining: u64, quote_qty_remaining: u64, budget_exhausted| {
+            Ok((
+                params.max_base_qty - base_qty_remaining,
+                params.max_quote_qty - quote_qty_remaining,
+                budget_exhausted || base_qty_remaining == 0 || quote_qty_remaining == 0,
+            ))
+        };
+
+        // Post only orders are never matched
+        if params.post_only && !best_effort {
+            return filled(base_qty_remaining, quote_qty_remaining, false);
+        }
+
+        // Orders are reached in the same order as in the matching loop. The maker order being matched stays current
+        // for as long as its base quantity, tracked separately, doesn't fall below the minimum order size.
+        let mut handles = opposite_slab.leaf_handles(side == Side::Bid).peekable();
+        let mut current_maker_qty = None;
+        while let Some(&h) = handles.peek() {
+            if match_limit == 0 {
+                break;
+            }
+            let leaf = &opposite_slab.leaf_nodes[h as usize];
+            let trade_price = leaf.price();
+            let crosses = match side {
+                Side::Bid => trade_price <= inclusive_limit_price,
+                Side::Ask => trade_price >= inclusive_limit_price,
+            };
+            if !crosses {
+                break;
+            }
+
+            if leaf.is_expired(current_ts) {
+                handles.next();
+                match_limit -= 1;
+                continue;
+            }
+
+            let offer_size = current_maker_qty.unwrap_or(leaf.base_quantity);
+            let quote_for = |base_qty: u64| match side {
+                Side::Bid => fp32_mul_ceil(base_qty, trade_price),
+                Side::Ask => fp32_mul_floor(base_qty, trade_price),
+            };
+
+            if market_state.matching_mode() == MatchingMode::ProRata {
+                let level_base_qty = base_qty_remaining
+                    .min(fp32_div(quote_qty_remaining, trade_price).unwrap_or(u64::MAX));
+                // The orders of the level which were already reached have been removed or skipped by the matching loop
+                let current_order_id = leaf.order_id();
+                let was_reached = |order_id: u128| match side {
+                    Side::Bid => order_id < current_order_id,
+                    Side::Ask => order_id > current_order_id,
+                };
+                let allocations = self
+                    .pro_rata_allocations(
+                        side.opposite(),
+                        trade_price,
+                        level_base_qty,
+                        match_limit,
+                        |maker, maker_callback_info| {
+                            if was_reached(maker.order_id())
+                                || !is_eligible(maker)
+                                || is_self_trade(maker_callback_info)
+                            {
+                                None
+                            } else if maker.order_id() == current_order_id {
+                                Some(offer_size)
+                            } else {
+                                Some(maker.base_quantity)
+                            }
+                        },
+                    )
+                    .filter(|allocations| {
+                        allocations
+                            .iter()
+                            .all(|&(_, base_qty)| base_qty == 0 || quote_for(base_qty) != Some(0))
+                    });
+                if let Some(allocations) = allocations {
+                    let has_self_trades = !self
+                        .level_orders(
+                            side.opposite(),
+                            trade_price,
+                            |maker, maker_callback_info| {
+                                !was_reached(maker.order_id())
+                                    && is_eligible(maker)
+                                    && is_self_trade(maker_callback_info)
+                            },
+                        )
+                        .is_empty();
+                    if has_self_trades {
+                        match self_trade_behavior {
+                            SelfTradeBehavior::AbortTransaction if !best_effort => {
+                                return Err(AoError::WouldSelfTrade)
+                            }
+                            SelfTradeBehavior::AbortTransaction | SelfTradeBehavior::CancelBoth => {
+                                break
+                            }
+                            _ => {}
+                        }
+                    }
+                    for (_, base_trade_qty) in allocations {
+                        if base_trade_qty == 0 {
+                            continue;
+                        }
+                        let quote_maker_qty = quote_for(base_trade_qty)
+                            .map(|q| q.min(quote_qty_remaining))
+                            .ok_or(AoError::NumericalOverflow)?;
+                        base_qty_remaining -= base_trade_qty;
+                        quote_qty_remaining -= quote_maker_qty;
+                    }
+                    // The taker's quantity was smaller than the level's, so it has been entirely matched
+                    return filled(base_qty_remaining, quote_qty_remaining, true);
+                }
+            }
+
+            let base_trade_qty = offer_size
+                .min(base_qty_remaining)
+                .min(fp32_div(quote_qty_remaining, trade_price).unwrap_or(u64::MAX));
+            if base_trade_qty == 0 {
+                budget_exhausted = offer_size != 0;
+                break;
+            }
+            if base_trade_qty < offer_size && leaf.has_flag(LeafFlag::AllOrNothing) {
+                handles.next();
+                match_limit -= 1;
+                continue;
+            }
+            let quote_maker_qty = quote_for(base_trade_qty)
+                .map(|q| q.min(quote_qty_remaining))
+                .ok_or(AoError::NumericalOverflow)?;
+            if quote_maker_qty == 0 {
+                break;
+            }
+
+            if is_self_trade(&opposite_slab.callback_infos[h as usize]) {
+                match self_trade_behavior {
+                    SelfTradeBehavior::AbortTransaction if !best_effort => {
+                        return Err(AoError::WouldSelfTrade)
+                    }
+                    SelfTradeBehavior::AbortTransaction | SelfTradeBehavior::CancelBoth => break,
+                    _ => {}
+                }
+                handles.next();
+                current_maker_qty = None;
+                match_limit -= 1;
+                continue;
+            }
+
+            base_qty_remaining -= base_trade_qty;
+            quote_qty_remaining -= quote_maker_qty;
+            if offer_size - base_trade_qty < market_state.min_base_order_size {
+                handles.next();
+                current_maker_qty = None;
+            } else {
+                current_maker_qty = Some(offer_size - base_trade_qty);
+            }
+            match_limit -= 1;
+        }
+        filled(base_qty_remaining, quote_qty_remaining, budget_exhausted)
+    }
+
+    /// Splits a taker's base quantity between the orders of a price level, proportionally to their sizes.
+    ///
+    /// `order_qty` gives the base quantity of each order taking part in the allocation, or `None` for orders which
+    /// don't. The rounding remainder goes to the largest
+    /// orders, in time priority on equal sizes. The allocations are returned along with their order ids, in time priority.
+    /// `None` is returned when price-time matching should be used instead, which is the case when the eligible orders
+    /// can all be entirely filled, or when there are less than two or more than `max_orders` of them.
+    fn pro_rata_allocations<P>(
+        &self,
+        side: Side,
+        price: u64,
+        base_qty: u64,
+        max_orders: u64,
+        order_qty: P,
+    ) -> Option<Vec<(u128, u64)>>
+    where
+        P: Fn(&LeafNode, &C) -> Option<u64>,
+    {
+        let slab = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let orders = slab
+            .subtree_leaf_handles(slab.find_price_subtree(price), side == Side::Ask)
+            .map(|h| {
+                (
+                    &slab.leaf_nodes[h as usize],
+                    &slab.callback_infos[h as usize],
+                )
+            })
+            .filter_map(|(leaf, callback_info)| {
+                order_qty(leaf, callback_info).map(|q| (leaf.order_id(), q))
+            })
+            .collect::<Vec<_>>();
+        let level_base_qty = orders.iter().map(|(_, q)| *q as u128).sum::<u128>();
+        if orders.len() < 2
+            || orders.len() as u64 > max_orders
+            || base_qty as u128 >= level_base_qty
+        {
+            return None;
+        }
+        let mut allocations = orders
+            .iter()
+            .map(|&(order_id, q)| {
+                let allocation = (base_qty as u128) * (q as u128) / level_base_qty;
+                (order_id, allocation as u64)
+            })
+            .collect::<Vec<_>>();
+        let mut remainder = base_qty - allocations.iter().map(|(_, a)| a).sum::<u64>();
+        // The sort is stable, which preserves time priority between orders of equal sizes
+        let mut by_size = (0..orders.len()).collect::<Vec<_>>();
+        by_size.sort_by_key(|&i| std::cmp::Reverse(orders[i].1));
+        for i in by_size {
+            if remainder == 0 {
+                break;
+            }
+            let extra = remainder.min(orders[i].1 - allocations[i].1);
+            allocations[i].1 += extra;
+            remainder -= extra;
+        }
+        Some(allocations)
+    }
+
+    /// Returns the ids of the orders of a price level satisfying `filter`, in time priority
+    fn level_orders<P>(&self, side: Side, price: u64, filter: P) -> Vec<u128>
+    where
+        P: Fn(&LeafNode, &C) -> bool,
+    {
+        let slab = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        slab.subtree_leaf_handles(slab.find_price_subtree(price), side == Side::Ask)
+            .filter(|&h| {
+                filter(
+                    &slab.leaf_nodes[h as usize],
+                    &slab.callback_infos[h as usize],
+                )
+            })
+            .map(|h| slab.leaf_nodes[h as usize].order_id())
+            .collect()
+    }
+
+    /// Removes a maker order whose remaining quantity fell below the market's minimum order size after being matched,
+    /// pushing the corresponding out event.
+    fn remove_filled_order(
+        &mut self,
+        event_queue: &mut EventQueue<'a, C>,
+        side: Side,
+        order_id: u128,
+        current_level: (u64, u64),
+        emit_level_cleared: bool,
+    ) -> Result<(), AoError> {
+        let (leaf, out_event_callback_info) = self.get_tree(side).remove_by_key(order_id).unwrap();
+        let out_event = OutEvent::new(side, order_id, leaf.base_quantity, OutReason::MinSizePurge);
+        event_queue
+            .push_back(out_event, Some(&out_event_callback_info), None)
+            .map_err(|_| AoError::EventQueueFull)?;
+        if emit_level_cleared {
+            self.push_level_cleared(event_queue, side, current_level)?;
+        }
+        Ok(())
+    }
+
+    /// Pushes a [`LevelClearedEvent`] if the given price level has just been emptied after being matched against.
+    fn push_level_cleared(
+        &self,
+        event_queue: &mut EventQueue<'a, C>,
+        side: Side,
+        (price, base_size): (u64, u64),
+    ) -> Result<(), AoError> {
+        if base_size == 0 || !self.level_cleared(side, price) {
+            return Ok(());
+        }
+        let level_cleared = LevelClearedEvent {
+            tag: EventTag::LevelCleared as u8,
+            side: side as u8,
+            _padding: [0; 6],
+            price,
+            base_size,
+            _padding_end: [0; 24],
+        };
+        event_queue
+            .push_back(level_cleared, None, None)
+            .map_err(|_| AoError::EventQueueFull)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::event_queue::{EventRef, FillEventRef, OutEventRef};
+    use bytemuck::Zeroable;
+
+    use super::*;
+
+    type SlabTest<'a> = Slab<'a, [u8; 32]>;
+    type OrderBookStateTest<'a> = OrderBookState<'a, [u8; 32]>;
+    type EventQueueTest<'a> = EventQueue<'a, [u8; 32]>;
+
+    fn test_market_state() -> MarketState {
+        MarketState {
+            min_base_order_size: 10,
+            tick_size: 1,
+            max_price: u64::MAX,
+            ..Zeroable::zeroed()
+        }
+    }
+
+    /// The parameters of a plain limit order, meant to be completed with struct update syntax
+    fn default_params<C: Zeroable>() -> new_order::Params<C> {
+        new_order::Params {
+            max_base_qty: 0,
+            max_quote_qty: u64::MAX,
+            limit_price: 0,
+            side: Side::Bid,
+            match_limit: 10,
+            callback_info: Zeroable::zeroed(),
+            post_only: false,
+            post_allowed: true,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            round_to_tick: false,
+            require_top_of_book: false,
+            post_full_base: false,
+            peg_offset: None,
+            cross_on_equal: true,
+            taker_mode: TakerMode::Standard,
+            immediate_or_cancel: false,
+            fill_or_kill: false,
+            order_type: OrderType::Limit,
+            max_ts: 0,
+            display_qty: 0,
+            post_only_slide: false,
+            all_or_nothing: false,
+            min_fill_base_qty: 0,
+        }
+    }
+
+    pub struct TestContext {
+        asks_buffer: Vec<u8>,
+        bids_buffer: Vec<u8>,
+        event_queue_buffer: Vec<u8>,
+    }
+
+    impl TestContext {
+        pub fn new(order_capacity: usize, event_capacity: usize) -> Self {
             let allocation_size = SlabTest::compute_allocation_size(order_capacity);
             let (mut asks_buffer, mut bids_buffer) =
                 (vec![0; allocation_size], vec![0; allocation_size]);
@@ -417,12 +1904,12 @@ mod tests {
                     side: Side::Ask,
                     match_limit: 0,
                     callback_info: [0; 32],
-                    post_only: false,
                     post_allowed: false,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(event_queue.header.count == 0);
@@ -434,6 +1921,8 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            posted_price,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -441,20 +1930,19 @@ mod tests {
                     max_quote_qty: 10_000_000,
                     limit_price: 10 << 32,
                     side: Side::Bid,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
         assert_eq!(total_base_qty, 1_000_000);
         assert_eq!(total_quote_qty, 10_000_000);
         assert_eq!(total_base_qty_posted, 1_000_000);
+        assert_eq!(posted_price, Some(10 << 32));
 
         // Bob posts an ask order for 3 BTC at 20 USD/BTC
         let OrderSummary {
@@ -462,6 +1950,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -469,14 +1958,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 20 << 32,
                     side: Side::Ask,
-                    match_limit: 10,
                     callback_info: bob,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -490,6 +1977,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -497,14 +1985,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 15 << 32,
                     side: Side::Bid,
-                    match_limit: 10,
                     callback_info: bob,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -519,6 +2005,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -526,14 +2013,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 14 << 32,
                     side: Side::Ask,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -560,7 +2045,8 @@ mod tests {
                             let o = [o as u64, (o >> 64) as u64];
                             o
                         },
-                        base_size: 500_000
+                        base_size: 500_000,
+                        price: 15 << 32
                     },
                     maker_callback_info: &bob,
                     taker_callback_info: &alice
@@ -573,7 +2059,12 @@ mod tests {
                     event: &OutEvent {
                         tag: EventTag::Out as u8,
                         side: Side::Bid as u8,
-                        _padding: [0; 14],
+                        self_trade: 0,
+                        expired: 0,
+                        delete: 1,
+                        reason: OutReason::MinSizePurge as u8,
+                        _padding: [0; 10],
+                        _padding_end: [0; 8],
                         base_size: 0,
                         order_id: {
                             let o = bob_order_id_0.unwrap();
@@ -582,7 +2073,8 @@ mod tests {
                             o
                         }
                     },
-                    callback_info: &bob
+                    callback_info: &bob,
+                    taker_callback_info: None,
                 })
             );
         }
@@ -600,14 +2092,13 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 15 << 32,
                     side: Side::Bid,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
                     self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap_err();
         assert!(matches!(r, AoError::WouldSelfTrade));
@@ -622,6 +2113,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -629,14 +2121,13 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 15 << 32,
                     side: Side::Bid,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
                     self_trade_behavior: SelfTradeBehavior::CancelProvide,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -654,7 +2145,12 @@ mod tests {
                     event: &OutEvent {
                         tag: EventTag::Out as u8,
                         side: Side::Ask as u8,
-                        _padding: [0; 14],
+                        self_trade: 1,
+                        expired: 0,
+                        delete: 1,
+                        reason: OutReason::SelfTrade as u8,
+                        _padding: [0; 10],
+                        _padding_end: [0; 8],
                         base_size: 250_000,
                         order_id: {
                             let o = alice_order_id_0.unwrap();
@@ -663,7 +2159,8 @@ mod tests {
                             o
                         }
                     },
-                    callback_info: &alice
+                    callback_info: &alice,
+                    taker_callback_info: Some(&alice),
                 })
             );
         }
@@ -677,14 +2174,13 @@ mod tests {
                     max_quote_qty: 10,
                     limit_price: 1,
                     side: Side::Ask,
-                    match_limit: 10,
                     callback_info: [0; 32],
-                    post_only: false,
                     post_allowed: false,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(event_queue.header.count == 0);
@@ -704,6 +2200,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -711,14 +2208,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 20 << 32,
                     side: Side::Ask,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -733,6 +2228,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -740,14 +2236,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 40 << 32,
                     side: Side::Ask,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -762,6 +2256,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -769,14 +2264,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 10 << 32,
                     side: Side::Ask,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -793,7 +2286,12 @@ mod tests {
                     event: &OutEvent {
                         tag: EventTag::Out as u8,
                         side: Side::Ask as u8,
-                        _padding: [0; 14],
+                        self_trade: 0,
+                        expired: 0,
+                        delete: 1,
+                        reason: OutReason::Booted as u8,
+                        _padding: [0; 10],
+                        _padding_end: [0; 8],
                         base_size: 6_000_000,
                         order_id: {
                             let o = order_id_to_be_booted.unwrap();
@@ -802,7 +2300,8 @@ mod tests {
                             o
                         }
                     },
-                    callback_info: &alice
+                    callback_info: &alice,
+                    taker_callback_info: None,
                 })
             );
         }
@@ -815,6 +2314,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -822,14 +2322,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 50 << 32,
                     side: Side::Ask,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_none());
@@ -852,6 +2350,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -859,14 +2358,12 @@ mod tests {
                     max_quote_qty: 10_000_000,
                     limit_price: 1 << 30,
                     side: Side::Bid,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -884,14 +2381,13 @@ mod tests {
                     max_quote_qty: 1_000_000,
                     limit_price: 1 << 28,
                     side: Side::Ask,
-                    match_limit: 10,
                     callback_info: [0; 32],
-                    post_only: false,
                     post_allowed: false,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(event_queue.header.count == 0);
@@ -909,6 +2405,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -916,14 +2413,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 20 << 32,
                     side: Side::Bid,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -938,6 +2433,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -945,14 +2441,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 10 << 32,
                     side: Side::Bid,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -967,6 +2461,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -974,14 +2469,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 40 << 32,
                     side: Side::Bid,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_some());
@@ -998,7 +2491,12 @@ mod tests {
                     event: &OutEvent {
                         tag: EventTag::Out as u8,
                         side: Side::Bid as u8,
-                        _padding: [0; 14],
+                        self_trade: 0,
+                        expired: 0,
+                        delete: 1,
+                        reason: OutReason::Booted as u8,
+                        _padding: [0; 10],
+                        _padding_end: [0; 8],
                         base_size: 6_000_000,
                         order_id: {
                             let o = order_id_to_be_booted.unwrap();
@@ -1007,7 +2505,8 @@ mod tests {
                             o
                         }
                     },
-                    callback_info: &alice
+                    callback_info: &alice,
+                    taker_callback_info: None,
                 })
             );
         }
@@ -1020,6 +2519,7 @@ mod tests {
             total_base_qty,
             total_quote_qty,
             total_base_qty_posted,
+            ..
         } = orderbook
             .new_order(
                 new_order::Params {
@@ -1027,14 +2527,12 @@ mod tests {
                     max_quote_qty: 1_000_000_000,
                     limit_price: 5 << 32,
                     side: Side::Bid,
-                    match_limit: 10,
                     callback_info: alice,
-                    post_only: false,
-                    post_allowed: true,
-                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                    ..default_params()
                 },
                 &mut event_queue,
-                10,
+                &test_market_state(),
+                0,
             )
             .unwrap();
         assert!(posted_order_id.is_none());
@@ -1043,4 +2541,2983 @@ mod tests {
         assert_eq!(total_base_qty_posted, 0);
         assert_eq!(event_queue.header.count, 0);
     }
+
+    #[test]
+    fn test_ob_empty_callback_id() {
+        #[derive(Clone, Copy, Zeroable, Pod, Debug, PartialEq)]
+        #[repr(C)]
+        struct NoIdCallbackInfo {
+            data: [u8; 8],
+        }
+
+        impl CallbackInfo for NoIdCallbackInfo {
+            type CallbackId = ();
+
+            fn as_callback_id(&self) -> &Self::CallbackId {
+                &()
+            }
+        }
+
+        let allocation_size = Slab::<NoIdCallbackInfo>::compute_allocation_size(10);
+        let (mut asks_buffer, mut bids_buffer) =
+            (vec![0; allocation_size], vec![0; allocation_size]);
+        Slab::<NoIdCallbackInfo>::initialize(&mut asks_buffer, &mut bids_buffer).unwrap();
+        let mut event_queue_buffer =
+            vec![0; EventQueue::<NoIdCallbackInfo>::compute_allocation_size(10)];
+        let mut orderbook =
+            OrderBookState::<NoIdCallbackInfo>::new_safe(&mut bids_buffer, &mut asks_buffer)
+                .unwrap();
+        let mut event_queue =
+            EventQueue::from_buffer(&mut event_queue_buffer, AccountTag::Uninitialized).unwrap();
+        let alice = NoIdCallbackInfo { data: [1; 8] };
+        let bob = NoIdCallbackInfo { data: [2; 8] };
+
+        // Alice posts an ask order for 1 BTC at 10 USD/BTC
+        orderbook
+            .new_order(
+                new_order::Params {
+                    max_base_qty: 1_000_000,
+                    max_quote_qty: 1_000_000_000,
+                    limit_price: 10 << 32,
+                    side: Side::Ask,
+                    callback_info: alice,
+                    self_trade_behavior: SelfTradeBehavior::CancelProvide,
+                    ..default_params()
+                },
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+
+        // Bob's bid is matched instead of cancelling Alice's order, since the empty callback ids can't identify a self trade
+        let OrderSummary {
+            posted_order_id,
+            total_base_qty,
+            total_quote_qty,
+            ..
+        } = orderbook
+            .new_order(
+                new_order::Params {
+                    max_base_qty: 1_000_000,
+                    max_quote_qty: 1_000_000_000,
+                    limit_price: 10 << 32,
+                    side: Side::Bid,
+                    callback_info: bob,
+                    self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+                    ..default_params()
+                },
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert!(posted_order_id.is_none());
+        assert_eq!(total_base_qty, 1_000_000);
+        assert_eq!(total_quote_qty, 10_000_000);
+        assert_eq!(event_queue.header.count, 2);
+        match event_queue.iter().next().unwrap() {
+            EventRef::Fill(FillEventRef {
+                maker_callback_info,
+                taker_callback_info,
+                ..
+            }) => {
+                assert_eq!(maker_callback_info, &alice);
+                assert_eq!(taker_callback_info, &bob);
+            }
+            _ => panic!("Expected a fill event"),
+        }
+    }
+
+    #[test]
+    fn test_ob_level_cleared() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let market_state = MarketState {
+            flags: MarketFlag::EmitLevelCleared as u64,
+            ..test_market_state()
+        };
+        let alice = [1; 32];
+        let bob = [2; 32];
+
+        // Alice posts two asks at 10 USD/BTC and one at 11 USD/BTC
+        for limit_price in [10 << 32, 10 << 32, 11 << 32] {
+            orderbook
+                .new_order(
+                    new_order::Params {
+                        max_base_qty: 1_000,
+                        limit_price,
+                        side: Side::Ask,
+                        callback_info: alice,
+                        ..default_params()
+                    },
+                    &mut event_queue,
+                    &market_state,
+                    0,
+                )
+                .unwrap();
+        }
+
+        // Bob sweeps the 10 USD/BTC level and partially fills the 11 USD/BTC order
+        orderbook
+            .new_order(
+                new_order::Params {
+                    max_base_qty: 2_500,
+                    limit_price: 11 << 32,
+                    side: Side::Bid,
+                    callback_info: bob,
+                    post_allowed: false,
+                    ..default_params()
+                },
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+
+        // Fill, Out, Fill, Out, LevelCleared, Fill
+        assert_eq!(event_queue.header.count, 6);
+        let level_cleared_events = event_queue
+            .iter()
+            .filter_map(|e| match e {
+                EventRef::LevelCleared(l) => Some(*l.event),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            level_cleared_events,
+            vec![LevelClearedEvent {
+                tag: EventTag::LevelCleared as u8,
+                side: Side::Ask as u8,
+                _padding: [0; 6],
+                price: 10 << 32,
+                base_size: 2_000,
+                _padding_end: [0; 24],
+            }]
+        );
+        assert!(matches!(
+            event_queue.peek_at(4),
+            Some(EventRef::LevelCleared(_))
+        ));
+
+        // Without the flag, no level cleared event is emitted
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        for side in [Side::Ask, Side::Bid] {
+            orderbook
+                .new_order(
+                    new_order::Params {
+                        max_base_qty: 1_000,
+                        limit_price: 10 << 32,
+                        side,
+                        callback_info: alice,
+                        ..default_params()
+                    },
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        assert_eq!(event_queue.header.count, 2);
+        assert!(event_queue
+            .iter()
+            .all(|e| !matches!(e, EventRef::LevelCleared(_))));
+    }
+
+    #[test]
+    fn test_ob_max_price() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let market_state = MarketState {
+            max_price: 100 << 32,
+            ..test_market_state()
+        };
+        let params = |limit_price| new_order::Params {
+            max_base_qty: 1_000,
+            limit_price,
+            side: Side::Ask,
+            callback_info: [1; 32],
+            ..default_params()
+        };
+
+        let OrderSummary {
+            posted_order_id, ..
+        } = orderbook
+            .new_order(params(100 << 32), &mut event_queue, &market_state, 0)
+            .unwrap();
+        assert!(posted_order_id.is_some());
+
+        assert!(matches!(
+            orderbook.new_order(params((100 << 32) + 1), &mut event_queue, &market_state, 0),
+            Err(AoError::LimitPriceTooHigh)
+        ));
+        assert_eq!(orderbook.asks.header.leaf_count, 1);
+    }
+
+    #[test]
+    fn test_ob_session_rollover() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let alice = [1; 32];
+        let mut market_state = test_market_state();
+        let params = |limit_price, side| new_order::Params {
+            max_base_qty: 1_000,
+            limit_price,
+            side,
+            callback_info: alice,
+            ..default_params()
+        };
+
+        // Session 0 orders
+        for (limit_price, side) in [
+            (9 << 32, Side::Bid),
+            (8 << 32, Side::Bid),
+            (11 << 32, Side::Ask),
+        ] {
+            orderbook
+                .new_order(
+                    params(limit_price, side),
+                    &mut event_queue,
+                    &market_state,
+                    0,
+                )
+                .unwrap();
+        }
+
+        // Session 1 order
+        market_state.current_session = 1;
+        let OrderSummary {
+            posted_order_id, ..
+        } = orderbook
+            .new_order(
+                params(10 << 32, Side::Bid),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        let session_1_order_id = posted_order_id.unwrap();
+
+        // The flush is bounded by the maximum number of orders
+        let OrderSummary {
+            total_base_qty,
+            total_quote_qty,
+            ..
+        } = orderbook
+            .cancel_session_orders(1, 2, &mut event_queue)
+            .unwrap();
+        assert_eq!(total_base_qty, 2_000);
+        assert_eq!(total_quote_qty, 17_000);
+        assert_eq!(event_queue.header.count, 2);
+        assert_eq!(orderbook.bids.order_ids(true), vec![session_1_order_id]);
+        assert_eq!(orderbook.asks.header.leaf_count, 1);
+
+        let OrderSummary { total_base_qty, .. } = orderbook
+            .cancel_session_orders(1, 2, &mut event_queue)
+            .unwrap();
+        assert_eq!(total_base_qty, 1_000);
+        assert_eq!(event_queue.header.count, 3);
+        assert!(orderbook.asks.order_ids(true).is_empty());
+        for event in event_queue.iter() {
+            match event {
+                EventRef::Out(OutEventRef { callback_info, .. }) => {
+                    assert_eq!(callback_info, &alice)
+                }
+                _ => panic!("Expected an out event"),
+            }
+        }
+
+        // Nothing is left to flush
+        let OrderSummary { total_base_qty, .. } = orderbook
+            .cancel_session_orders(1, 2, &mut event_queue)
+            .unwrap();
+        assert_eq!(total_base_qty, 0);
+        assert_eq!(orderbook.bids.header.leaf_count, 1);
+    }
+
+    #[test]
+    fn test_ob_boot_full_event_queue() {
+        let mut test_context = TestContext::new(2, 5);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |limit_price| new_order::Params {
+            max_base_qty: 1_000,
+            limit_price,
+            side: Side::Ask,
+            callback_info: [1; 32],
+            ..default_params()
+        };
+        for limit_price in [10 << 32, 11 << 32] {
+            orderbook
+                .new_order(
+                    params(limit_price),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        let order_ids = orderbook.asks.order_ids(true);
+
+        while !event_queue.full() {
+            event_queue
+                .push_back(OutEvent::zeroed(), Some(&[0; 32]), None)
+                .unwrap();
+        }
+
+        // Posting a more aggressive ask would boot the 11 USD/BTC order, but its out event can't be pushed
+        assert!(matches!(
+            orderbook.new_order(params(9 << 32), &mut event_queue, &test_market_state(), 0),
+            Err(AoError::EventQueueFull)
+        ));
+        assert_eq!(orderbook.asks.order_ids(true), order_ids);
+    }
+
+    #[test]
+    fn test_ob_best_level() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        assert_eq!(orderbook.best_level(Side::Bid), None);
+        assert_eq!(orderbook.best_level(Side::Ask), None);
+
+        let orders = [
+            (Side::Bid, 9 << 32, 100),
+            (Side::Bid, 9 << 32, 200),
+            (Side::Bid, (9 << 32) - 1, 400),
+            (Side::Bid, 8 << 32, 800),
+            (Side::Ask, 11 << 32, 50),
+            (Side::Ask, (11 << 32) + 1, 60),
+        ];
+        for (side, limit_price, max_base_qty) in orders {
+            orderbook
+                .new_order(
+                    new_order::Params {
+                        max_base_qty,
+                        limit_price,
+                        side,
+                        callback_info: [1; 32],
+                        ..default_params()
+                    },
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        assert_eq!(orderbook.best_level(Side::Bid), Some((9 << 32, 300)));
+        assert_eq!(orderbook.best_level(Side::Ask), Some((11 << 32, 50)));
+    }
+
+    #[test]
+    fn test_ob_prune_orders() {
+        let mut test_context = TestContext::new(1000, 5);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        for (limit_price, side) in [
+            (9 << 32, Side::Bid),
+            (8 << 32, Side::Bid),
+            (11 << 32, Side::Ask),
+            (12 << 32, Side::Ask),
+        ] {
+            orderbook
+                .new_order(
+                    new_order::Params {
+                        max_base_qty: 1_000,
+                        limit_price,
+                        side,
+                        callback_info: [1; 32],
+                        ..default_params()
+                    },
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        while !event_queue.full() {
+            event_queue
+                .push_back(OutEvent::zeroed(), Some(&[0; 32]), None)
+                .unwrap();
+        }
+
+        // Pruning with events can't proceed with a full event queue
+        assert!(matches!(
+            orderbook.prune_orders(1, true, &mut event_queue),
+            Err(AoError::EventQueueFull)
+        ));
+        assert_eq!(orderbook.bids.header.leaf_count, 2);
+
+        // The least aggressive orders are pruned first
+        let OrderSummary {
+            total_base_qty,
+            total_quote_qty,
+            ..
+        } = orderbook.prune_orders(3, false, &mut event_queue).unwrap();
+        assert_eq!(total_base_qty, 3_000);
+        assert_eq!(total_quote_qty, 8_000 + 9_000 + 12_000);
+        assert_eq!(orderbook.best_level(Side::Ask), Some((11 << 32, 1_000)));
+        assert_eq!(orderbook.bids.header.leaf_count, 0);
+
+        event_queue.pop_n(5);
+        let OrderSummary { total_base_qty, .. } =
+            orderbook.prune_orders(10, true, &mut event_queue).unwrap();
+        assert_eq!(total_base_qty, 1_000);
+        assert!(orderbook.is_empty());
+        assert_eq!(event_queue.len(), 1);
+    }
+
+    #[test]
+    fn test_ob_post_skipped_below_min() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |max_base_qty, side| new_order::Params {
+            max_base_qty,
+            limit_price: 10 << 32,
+            side,
+            callback_info: [1; 32],
+            ..default_params()
+        };
+
+        // At the minimum order size, the order is posted
+        let summary = orderbook
+            .new_order(
+                params(10, Side::Ask),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert!(summary.posted_order_id.is_some());
+        assert!(!summary.post_skipped_below_min);
+
+        // The remainder of 9 is below the minimum order size
+        let summary = orderbook
+            .new_order(
+                params(19, Side::Bid),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 10);
+        assert!(summary.posted_order_id.is_none());
+        assert!(summary.post_skipped_below_min);
+
+        // Nothing is left to post
+        orderbook
+            .new_order(
+                params(10, Side::Ask),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        let summary = orderbook
+            .new_order(
+                params(10, Side::Bid),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 10);
+        assert!(summary.posted_order_id.is_none());
+        assert!(!summary.post_skipped_below_min);
+    }
+
+    #[test]
+    fn test_ob_require_top_of_book() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |limit_price: u64, side, require_top_of_book| new_order::Params {
+            max_base_qty: 10,
+            limit_price: limit_price << 32,
+            side,
+            callback_info: [1; 32],
+            require_top_of_book,
+            ..default_params()
+        };
+
+        // An empty side is always improved upon
+        for (price, side) in [(10, Side::Bid), (20, Side::Ask)] {
+            orderbook
+                .new_order(
+                    params(price, side, true),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+
+        // Joining the best price is allowed
+        let summary = orderbook
+            .new_order(
+                params(10, Side::Bid, true),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert!(summary.posted_order_id.is_some());
+
+        // Posting behind the best price is rejected
+        for (price, side) in [(9, Side::Bid), (21, Side::Ask)] {
+            let r = orderbook
+                .new_order(
+                    params(price, side, true),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap_err();
+            assert!(matches!(r, AoError::WouldNotImprove));
+            orderbook
+                .new_order(
+                    params(price, side, false),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+
+        // Improving on the best price is allowed
+        let summary = orderbook
+            .new_order(
+                params(19, Side::Ask, true),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert!(summary.posted_order_id.is_some());
+
+        // A fully matched order is unaffected
+        let summary = orderbook
+            .new_order(
+                params(10, Side::Ask, true),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 10);
+        assert!(summary.posted_order_id.is_none());
+    }
+
+    #[test]
+    fn test_ob_cancel_orders_overflow() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |side| new_order::Params {
+            max_base_qty: u64::MAX,
+            limit_price: u64::MAX,
+            side,
+            callback_info: [1; 32],
+            post_only: true,
+            ..default_params()
+        };
+
+        let order_ids = (0..10)
+            .map(|_| {
+                orderbook
+                    .new_order(params(Side::Bid), &mut event_queue, &test_market_state(), 0)
+                    .unwrap()
+                    .posted_order_id
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // A single max-value order is fine
+        let summary = orderbook
+            .cancel_orders(&order_ids[..1], &mut event_queue)
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 1 << 32);
+
+        // The cumulated quote quantity doesn't fit in a u64
+        let r = orderbook
+            .cancel_orders(&order_ids[1..], &mut event_queue)
+            .unwrap_err();
+        assert!(matches!(r, AoError::NumericalOverflow));
+
+        // Unknown orders are reported
+        let r = orderbook
+            .cancel_orders(&order_ids[..1], &mut event_queue)
+            .unwrap_err();
+        assert!(matches!(r, AoError::OrderNotFound));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_order_summary_serde() {
+        let summary = OrderSummary {
+            posted_order_id: Some(u64::MAX as u128 + 1),
+            total_base_qty: 10,
+            total_quote_qty: 100,
+            total_base_qty_posted: 5,
+            posted_price: None,
+            post_skipped_below_min: false,
+            post_skipped_book_full: false,
+            matched_order_count: 0,
+        };
+        let json = serde_json::to_string(&summary).unwrap();
+        assert!(json.contains("\"posted_order_id\":18446744073709551616"));
+        assert!(json.contains("\"total_base_qty\":10"));
+        assert!(json.contains("\"posted_price\":null"));
+        let decoded: OrderSummary = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.posted_order_id, summary.posted_order_id);
+        assert_eq!(decoded.total_quote_qty, 100);
+    }
+
+    #[test]
+    fn test_ob_max_post_depth() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let market_state = MarketState {
+            max_post_depth: 2,
+            ..test_market_state()
+        };
+        let params = |limit_price: u64, side| new_order::Params {
+            max_base_qty: 10,
+            limit_price: limit_price << 32,
+            side,
+            callback_info: [1; 32],
+            ..default_params()
+        };
+
+        // Two bid levels, the best one holding two orders
+        for price in [10, 10, 9] {
+            orderbook
+                .new_order(params(price, Side::Bid), &mut event_queue, &market_state, 0)
+                .unwrap();
+        }
+
+        // Joining the second level is allowed, posting behind it isn't
+        orderbook
+            .new_order(params(9, Side::Bid), &mut event_queue, &market_state, 0)
+            .unwrap();
+        let r = orderbook
+            .new_order(params(8, Side::Bid), &mut event_queue, &market_state, 0)
+            .unwrap_err();
+        assert!(matches!(r, AoError::TooDeep));
+
+        // The depth is unlimited by default
+        orderbook
+            .new_order(
+                params(8, Side::Bid),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+
+        // Each side is bounded independently
+        for price in [20, 21] {
+            orderbook
+                .new_order(params(price, Side::Ask), &mut event_queue, &market_state, 0)
+                .unwrap();
+        }
+        let r = orderbook
+            .new_order(params(22, Side::Ask), &mut event_queue, &market_state, 0)
+            .unwrap_err();
+        assert!(matches!(r, AoError::TooDeep));
+
+        // A fully matched order is unaffected
+        let summary = orderbook
+            .new_order(params(7, Side::Ask), &mut event_queue, &market_state, 0)
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 10);
+        assert!(summary.posted_order_id.is_none());
+    }
+
+    #[test]
+    fn test_ob_post_full_base() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |max_base_qty, side, post_full_base| new_order::Params {
+            max_base_qty,
+            max_quote_qty: 100,
+            limit_price: 10 << 32,
+            side,
+            callback_info: [1; 32],
+            post_full_base,
+            ..default_params()
+        };
+
+        // The posted size is clamped by the quote budget by default
+        let summary = orderbook
+            .new_order(
+                params(50, Side::Bid, false),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty_posted, 10);
+        assert_eq!(summary.total_quote_qty, 100);
+
+        let summary = orderbook
+            .new_order(
+                params(50, Side::Bid, true),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty_posted, 50);
+        assert_eq!(summary.total_base_qty, 50);
+        assert_eq!(summary.total_quote_qty, 500);
+
+        // Matching still respects the quote budget, the remainder is posted in full
+        let summary = orderbook
+            .new_order(
+                params(30, Side::Ask, true),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 10);
+        assert_eq!(summary.total_quote_qty, 100);
+        assert!(summary.posted_order_id.is_none());
+
+        let summary = orderbook
+            .new_order(
+                params(30, Side::Bid, true),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty_posted, 30);
+    }
+
+    #[test]
+    fn test_ob_take_order() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let (alice, bob) = ([1; 32], [2; 32]);
+        let post = |limit_price: u64, callback_info| new_order::Params {
+            max_base_qty: 100,
+            limit_price: limit_price << 32,
+            side: Side::Ask,
+            callback_info,
+            post_only: true,
+            ..default_params()
+        };
+        let take = |order_id, max_base_qty, limit_price: u64| take_order::Params {
+            order_id,
+            max_base_qty,
+            limit_price: limit_price << 32,
+            callback_info: bob,
+        };
+
+        let best_ask = orderbook
+            .new_order(post(10, alice), &mut event_queue, &test_market_state(), 0)
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        let quoted_ask = orderbook
+            .new_order(post(12, alice), &mut event_queue, &test_market_state(), 0)
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+
+        // The taker's price has to cross the maker order
+        let r = orderbook
+            .take_order(
+                take(quoted_ask, 50, 11),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap_err();
+        assert!(matches!(r, AoError::OrderDoesNotCross));
+
+        // Partial lift, the best ask is left untouched
+        let summary = orderbook
+            .take_order(
+                take(quoted_ask, 60, 12),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 60);
+        assert_eq!(summary.total_quote_qty, 720);
+        assert!(summary.posted_order_id.is_none());
+        assert_eq!(event_queue.header.count, 1);
+        assert_eq!(orderbook.asks.header.leaf_count, 2);
+        let h = orderbook.asks.find_by_key(quoted_ask).unwrap();
+        assert_eq!(orderbook.asks.leaf_nodes[h as usize].base_quantity, 40);
+        let h = orderbook.asks.find_by_key(best_ask).unwrap();
+        assert_eq!(orderbook.asks.leaf_nodes[h as usize].base_quantity, 100);
+
+        // Full lift, the maker order is removed
+        let summary = orderbook
+            .take_order(
+                take(quoted_ask, 100, 13),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 40);
+        assert_eq!(event_queue.header.count, 3);
+        assert!(matches!(
+            event_queue.peek_at(2),
+            Some(EventRef::Out(OutEventRef { event, .. }))
+                if event.base_size == 0 && event.reason == OutReason::MinSizePurge as u8
+        ));
+        assert!(orderbook.asks.find_by_key(quoted_ask).is_none());
+
+        let r = orderbook
+            .take_order(
+                take(quoted_ask, 100, 13),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap_err();
+        assert!(matches!(r, AoError::OrderNotFound));
+
+        // Self trading is rejected
+        let r = orderbook
+            .take_order(
+                take_order::Params {
+                    callback_info: alice,
+                    ..take(best_ask, 100, 10)
+                },
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap_err();
+        assert!(matches!(r, AoError::WouldSelfTrade));
+
+        // Unless the market disables self trade checks
+        let summary = orderbook
+            .take_order(
+                take_order::Params {
+                    callback_info: alice,
+                    ..take(best_ask, 100, 10)
+                },
+                &mut event_queue,
+                &MarketState {
+                    flags: MarketFlag::DisableSelfTradeCheck as u64,
+                    ..test_market_state()
+                },
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 100);
+    }
+
+    #[test]
+    fn test_ob_cancel_provide_interleaved() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let (alice, bob, carol) = ([1; 32], [2; 32], [3; 32]);
+        let params = |max_base_qty, side, callback_info| new_order::Params {
+            max_base_qty,
+            limit_price: 10 << 32,
+            side,
+            callback_info,
+            self_trade_behavior: SelfTradeBehavior::CancelProvide,
+            ..default_params()
+        };
+
+        // A single price level, with self orders interleaved between non-self ones
+        for owner in [alice, bob, alice, alice, carol] {
+            orderbook
+                .new_order(
+                    params(10, Side::Ask, owner),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        assert_eq!(event_queue.header.count, 0);
+
+        let summary = orderbook
+            .new_order(
+                params(30, Side::Bid, alice),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 30);
+        assert_eq!(summary.total_base_qty_posted, 10);
+        assert!(orderbook.asks.order_ids(true).is_empty());
+
+        // Every self order is cancelled, and the non-self ones are filled in time priority
+        let events = event_queue
+            .iter()
+            .map(|e| match e {
+                EventRef::Fill(FillEventRef {
+                    event,
+                    maker_callback_info,
+                    ..
+                }) => (EventTag::Fill, *maker_callback_info, event.base_size),
+                EventRef::Out(OutEventRef {
+                    event,
+                    callback_info,
+                    ..
+                }) => (EventTag::Out, *callback_info, event.base_size),
+                EventRef::LevelCleared(_) => panic!("Unexpected level cleared event"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                (EventTag::Out, alice, 10),
+                (EventTag::Fill, bob, 10),
+                (EventTag::Out, bob, 0),
+                (EventTag::Out, alice, 10),
+                (EventTag::Out, alice, 10),
+                (EventTag::Fill, carol, 10),
+                (EventTag::Out, carol, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ob_snapshot() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |limit_price: u64, side, callback_info| new_order::Params {
+            max_base_qty: 10 + limit_price,
+            limit_price: limit_price << 32,
+            side,
+            callback_info,
+            post_only: true,
+            ..default_params()
+        };
+
+        let (bids, asks) = decode_snapshot::<[u8; 32]>(&orderbook.snapshot()).unwrap();
+        assert!(bids.is_empty() && asks.is_empty());
+
+        let mut order = |limit_price, side, callback_info| OwnedOrder {
+            order_id: orderbook
+                .new_order(
+                    params(limit_price, side, callback_info),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap()
+                .posted_order_id
+                .unwrap(),
+            base_quantity: 10 + limit_price,
+            callback_info,
+        };
+        let bid_9 = order(9, Side::Bid, [1; 32]);
+        let bid_10 = order(10, Side::Bid, [2; 32]);
+        let ask_12 = order(12, Side::Ask, [3; 32]);
+        let ask_11 = order(11, Side::Ask, [4; 32]);
+
+        let snapshot = orderbook.snapshot();
+        let (bids, asks) = decode_snapshot::<[u8; 32]>(&snapshot).unwrap();
+        assert_eq!(bids, vec![bid_10, bid_9]);
+        assert_eq!(asks, vec![ask_11, ask_12]);
+
+        // Malformed blobs are rejected
+        assert!(decode_snapshot::<[u8; 32]>(&snapshot[..snapshot.len() - 1]).is_err());
+        assert!(decode_snapshot::<[u8; 16]>(&snapshot).is_err());
+        let mut wrong_version = snapshot;
+        wrong_version[0] += 1;
+        assert!(decode_snapshot::<[u8; 32]>(&wrong_version).is_err());
+    }
+
+    #[test]
+    fn test_ob_full_book_marginal_order() {
+        let mut test_context = TestContext::new(2, 10);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |limit_price| new_order::Params {
+            max_base_qty: 1_000,
+            limit_price,
+            side: Side::Bid,
+            callback_info: [1; 32],
+            ..default_params()
+        };
+        for limit_price in [10 << 32, 11 << 32] {
+            orderbook
+                .new_order(
+                    params(limit_price),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        assert!(orderbook.bids.is_full());
+        let order_ids = orderbook.bids.order_ids(true);
+
+        // A bid at the worst price isn't more aggressive than the order it would boot
+        let summary = orderbook
+            .new_order(params(10 << 32), &mut event_queue, &test_market_state(), 0)
+            .unwrap();
+        assert_eq!(summary.posted_order_id, None);
+        assert!(summary.post_skipped_book_full);
+        assert!(!summary.post_skipped_below_min);
+        assert_eq!(orderbook.bids.order_ids(true), order_ids);
+        assert_eq!(event_queue.len(), 0);
+
+        // A more aggressive bid still boots the worst one
+        let summary = orderbook
+            .new_order(params(12 << 32), &mut event_queue, &test_market_state(), 0)
+            .unwrap();
+        assert!(summary.posted_order_id.is_some());
+        assert!(!summary.post_skipped_book_full);
+        assert_eq!(event_queue.len(), 1);
+        assert!(!orderbook.bids.order_ids(true).contains(&order_ids[0]));
+    }
+
+    #[test]
+    fn test_ob_cross_on_equal() {
+        let params = |side, cross_on_equal| new_order::Params {
+            max_base_qty: 100,
+            limit_price: 10 << 32,
+            side,
+            callback_info: [side as u8; 32],
+            cross_on_equal,
+            ..default_params()
+        };
+        for cross_on_equal in [true, false] {
+            let mut test_context = TestContext::new(1000, 1000);
+            let (mut orderbook, mut event_queue) = test_context.get();
+            let ask_id = orderbook
+                .new_order(
+                    params(Side::Ask, true),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap()
+                .posted_order_id
+                .unwrap();
+
+            let summary = orderbook
+                .new_order(
+                    params(Side::Bid, cross_on_equal),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+            if cross_on_equal {
+                // The bid takes the ask at the same price
+                assert_eq!(summary.total_base_qty, 100);
+                assert_eq!(summary.posted_order_id, None);
+                assert_eq!(orderbook.asks.header.leaf_count, 0);
+                // A fill, then the maker's out event
+                assert_eq!(event_queue.len(), 2);
+            } else {
+                // The bid joins the book at the ask's price without matching
+                assert_eq!(summary.total_base_qty, 100);
+                assert_eq!(summary.total_base_qty_posted, 100);
+                assert!(summary.posted_order_id.is_some());
+                assert_eq!(orderbook.asks.order_ids(true), vec![ask_id]);
+                assert_eq!(orderbook.bids.header.leaf_count, 1);
+                assert!(event_queue.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_ob_is_crossed() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, _) = test_context.get();
+        let make_leaf = |price: u64, side: Side| {
+            let key = ((price as u128) << 64)
+                | match side {
+                    Side::Bid => !0u64,
+                    Side::Ask => 0,
+                } as u128;
+            LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity: 100,
+                ..Zeroable::zeroed()
+            }
+        };
+        assert!(!orderbook.is_crossed());
+
+        orderbook
+            .bids
+            .insert_leaf(&make_leaf(10 << 32, Side::Bid), false)
+            .unwrap();
+        assert!(!orderbook.is_crossed());
+
+        // A locked book isn't reported
+        orderbook
+            .asks
+            .insert_leaf(&make_leaf(10 << 32, Side::Ask), false)
+            .unwrap();
+        assert!(!orderbook.is_crossed());
+
+        // Matching would never leave a bid above the best ask behind
+        orderbook
+            .bids
+            .insert_leaf(&make_leaf(11 << 32, Side::Bid), false)
+            .unwrap();
+        assert!(orderbook.is_crossed());
+    }
+
+    #[test]
+    fn test_ob_slab_full_policy() {
+        let params = |limit_price: u64| new_order::Params {
+            max_base_qty: 1_000,
+            limit_price: limit_price << 32,
+            side: Side::Ask,
+            callback_info: [1; 32],
+            ..default_params()
+        };
+        for action in [
+            SlabFullAction::Boot,
+            SlabFullAction::Reject,
+            SlabFullAction::Skip,
+        ] {
+            let mut test_context = TestContext::new(2, 10);
+            let (mut orderbook, mut event_queue) = test_context.get();
+            for limit_price in [10, 11] {
+                orderbook
+                    .new_order(
+                        params(limit_price),
+                        &mut event_queue,
+                        &test_market_state(),
+                        0,
+                    )
+                    .unwrap();
+            }
+            let order_ids = orderbook.asks.order_ids(true);
+
+            // The policy is only consulted when the book side is full, with the new order's parameters
+            let mut calls = 0;
+            let result = orderbook.new_order_with_policy(
+                params(12),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+                |_, p| {
+                    calls += 1;
+                    assert_eq!(p.limit_price, 12 << 32);
+                    action
+                },
+            );
+            assert_eq!(calls, 1);
+            match action {
+                SlabFullAction::Boot => {
+                    // Even the least aggressive order can be posted if the policy allows it
+                    let summary = result.unwrap();
+                    assert!(summary.posted_order_id.is_some());
+                    assert_eq!(
+                        orderbook.asks.order_ids(true),
+                        vec![order_ids[0], summary.posted_order_id.unwrap()]
+                    );
+                    assert_eq!(event_queue.len(), 1);
+                }
+                SlabFullAction::Reject => {
+                    assert!(matches!(result, Err(AoError::SlabOutOfSpace)));
+                    assert_eq!(orderbook.asks.order_ids(true), order_ids);
+                }
+                SlabFullAction::Skip => {
+                    let summary = result.unwrap();
+                    assert_eq!(summary.posted_order_id, None);
+                    assert!(summary.post_skipped_book_full);
+                    assert_eq!(orderbook.asks.order_ids(true), order_ids);
+                    assert!(event_queue.is_empty());
+                }
+            }
+        }
+
+        // The default policy boots for a more aggressive order, and skips otherwise
+        let mut test_context = TestContext::new(2, 10);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        for limit_price in [10, 11] {
+            orderbook
+                .new_order(
+                    params(limit_price),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            orderbook.default_slab_full_action(&params(9)),
+            SlabFullAction::Boot
+        );
+        assert_eq!(
+            orderbook.default_slab_full_action(&params(11)),
+            SlabFullAction::Skip
+        );
+    }
+
+    #[test]
+    fn test_ob_cancel_orders_with_outcomes() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |limit_price: u64, side| new_order::Params {
+            max_base_qty: 10,
+            limit_price: limit_price << 32,
+            side,
+            callback_info: [1; 32],
+            post_only: true,
+            ..default_params()
+        };
+        let order_ids = [(9, Side::Bid), (10, Side::Bid), (11, Side::Ask)]
+            .iter()
+            .map(|&(limit_price, side)| {
+                orderbook
+                    .new_order(
+                        params(limit_price, side),
+                        &mut event_queue,
+                        &test_market_state(),
+                        0,
+                    )
+                    .unwrap()
+                    .posted_order_id
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        orderbook
+            .cancel_orders(&order_ids[1..2], &mut event_queue)
+            .unwrap();
+
+        // The already cancelled bid and the duplicate ask are reported as not cancelled
+        let ids = [order_ids[1], order_ids[2], order_ids[0], order_ids[2]];
+        let (summary, outcomes) = orderbook
+            .cancel_orders_with_outcomes(&ids, &mut event_queue)
+            .unwrap();
+        assert_eq!(
+            outcomes,
+            vec![
+                (order_ids[1], false),
+                (order_ids[2], true),
+                (order_ids[0], true),
+                (order_ids[2], false),
+            ]
+        );
+        assert_eq!(summary.total_base_qty, 20);
+        assert_eq!(summary.total_quote_qty, 200);
+        assert!(orderbook.is_empty());
+    }
+
+    #[test]
+    fn test_ob_best_effort() {
+        let params = |limit_price: u64, side, callback_info, taker_mode| new_order::Params {
+            max_base_qty: 200,
+            limit_price,
+            side,
+            callback_info,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            taker_mode,
+            ..default_params()
+        };
+        let alice = [1; 32];
+        let bob = [2; 32];
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        orderbook
+            .new_order(
+                params(10 << 32, Side::Ask, alice, TakerMode::Standard),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+
+        // Nothing crosses: the order neither fails nor posts, even when it is post only
+        let mut no_fill = params(9 << 32, Side::Bid, bob, TakerMode::BestEffort);
+        no_fill.post_only = true;
+        let summary = orderbook
+            .new_order(no_fill, &mut event_queue, &test_market_state(), 0)
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 0);
+        assert_eq!(summary.total_quote_qty, 0);
+        assert_eq!(summary.posted_order_id, None);
+        assert!(!summary.post_skipped_below_min);
+        assert_eq!(orderbook.bids.header.leaf_count, 0);
+        assert!(event_queue.is_empty());
+
+        // A self trade stops the matching instead of aborting
+        let summary = orderbook
+            .new_order(
+                params(10 << 32, Side::Bid, alice, TakerMode::BestEffort),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 0);
+        assert_eq!(summary.posted_order_id, None);
+        assert_eq!(orderbook.asks.header.leaf_count, 1);
+        assert!(event_queue.is_empty());
+        assert!(matches!(
+            orderbook.new_order(
+                params(10 << 32, Side::Bid, alice, TakerMode::Standard),
+                &mut event_queue,
+                &test_market_state(),
+                0
+            ),
+            Err(AoError::WouldSelfTrade)
+        ));
+
+        // The available liquidity is taken and the remainder is dropped
+        let summary = orderbook
+            .new_order(
+                params(11 << 32, Side::Bid, bob, TakerMode::BestEffort),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 200);
+        assert_eq!(summary.total_quote_qty, 2000);
+        assert_eq!(summary.total_base_qty_posted, 0);
+        assert_eq!(summary.posted_order_id, None);
+        assert_eq!(orderbook.asks.header.leaf_count, 0);
+        assert_eq!(orderbook.bids.header.leaf_count, 0);
+    }
+
+    #[test]
+    fn test_ob_prune_orders_cursor() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |limit_price: u64, side| new_order::Params {
+            max_base_qty: 1_000,
+            limit_price,
+            side,
+            callback_info: [1; 32],
+            ..default_params()
+        };
+        for i in 0..100 {
+            for (limit_price, side) in [
+                ((100 - i % 50) << 32, Side::Bid),
+                ((101 + i % 50) << 32, Side::Ask),
+            ] {
+                orderbook
+                    .new_order(
+                        params(limit_price, side),
+                        &mut event_queue,
+                        &test_market_state(),
+                        0,
+                    )
+                    .unwrap();
+            }
+        }
+
+        let mut cursor = None;
+        let mut pruned = 0;
+        let mut calls = 0;
+        let mut late_order_id = None;
+        loop {
+            let (OrderSummary { total_base_qty, .. }, next_cursor) = orderbook
+                .prune_orders_from(7, false, cursor, &mut event_queue)
+                .unwrap();
+            pruned += total_base_qty / 1_000;
+            calls += 1;
+            if calls == 3 {
+                // This bid is posted behind the cursor, so it isn't reprocessed
+                late_order_id = orderbook
+                    .new_order(
+                        params(1 << 32, Side::Bid),
+                        &mut event_queue,
+                        &test_market_state(),
+                        0,
+                    )
+                    .unwrap()
+                    .posted_order_id;
+            }
+            match next_cursor {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+        assert_eq!(pruned, 200);
+        assert_eq!(calls, 29);
+        assert_eq!(orderbook.asks.header.leaf_count, 0);
+        assert_eq!(orderbook.bids.order_ids(true), vec![late_order_id.unwrap()]);
+        assert!(event_queue.is_empty());
+
+        // A limit of 0 leaves the cursor unchanged
+        assert_eq!(
+            orderbook
+                .prune_orders_from(0, false, cursor, &mut event_queue)
+                .unwrap()
+                .1,
+            cursor
+        );
+    }
+
+    #[test]
+    fn test_ob_top_of_book() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |max_base_qty, limit_price: u64, side| new_order::Params {
+            max_base_qty,
+            limit_price,
+            side,
+            callback_info: [1; 32],
+            post_only: true,
+            ..default_params()
+        };
+        assert_eq!(orderbook.top_of_book(), TopOfBook::default());
+
+        for (max_base_qty, limit_price, side) in [
+            (100, 9 << 32, Side::Bid),
+            (200, 9 << 32, Side::Bid),
+            (400, 8 << 32, Side::Bid),
+        ] {
+            orderbook
+                .new_order(
+                    params(max_base_qty, limit_price, side),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        // A one-sided book
+        assert_eq!(
+            orderbook.top_of_book(),
+            TopOfBook {
+                bid_price: Some(9 << 32),
+                bid_qty: 300,
+                ask_price: None,
+                ask_qty: 0,
+            }
+        );
+
+        for (max_base_qty, limit_price, side) in
+            [(50, 12 << 32, Side::Ask), (70, 11 << 32, Side::Ask)]
+        {
+            orderbook
+                .new_order(
+                    params(max_base_qty, limit_price, side),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            orderbook.top_of_book(),
+            TopOfBook {
+                bid_price: Some(9 << 32),
+                bid_qty: 300,
+                ask_price: Some(11 << 32),
+                ask_qty: 70,
+            }
+        );
+        assert_eq!(
+            orderbook.get_spread(),
+            (
+                orderbook.top_of_book().bid_price,
+                orderbook.top_of_book().ask_price
+            )
+        );
+    }
+
+    #[test]
+    fn test_ob_self_trade_check_disabled() {
+        let params = |side| new_order::Params {
+            max_base_qty: 100,
+            limit_price: 10 << 32,
+            side,
+            callback_info: [1; 32],
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            ..default_params()
+        };
+        let market_state = MarketState {
+            flags: MarketFlag::DisableSelfTradeCheck as u64,
+            ..test_market_state()
+        };
+        assert!(test_market_state().self_trade_check_enabled());
+        assert!(!market_state.self_trade_check_enabled());
+
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        orderbook
+            .new_order(params(Side::Ask), &mut event_queue, &market_state, 0)
+            .unwrap();
+        assert!(matches!(
+            orderbook.new_order(params(Side::Bid), &mut event_queue, &test_market_state(), 0),
+            Err(AoError::WouldSelfTrade)
+        ));
+
+        // The same user's orders are matched together
+        let summary = orderbook
+            .new_order(params(Side::Bid), &mut event_queue, &market_state, 0)
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 100);
+        assert_eq!(summary.posted_order_id, None);
+        assert!(orderbook.is_empty());
+        match event_queue.iter().next() {
+            Some(EventRef::Fill(FillEventRef {
+                maker_callback_info,
+                taker_callback_info,
+                ..
+            })) => assert_eq!(maker_callback_info, taker_callback_info),
+            _ => panic!("Expected a fill event"),
+        }
+    }
+
+    #[test]
+    fn test_ob_immediate_or_cancel() {
+        let params =
+            |max_base_qty, limit_price: u64, side, immediate_or_cancel| new_order::Params {
+                max_base_qty,
+                limit_price,
+                side,
+                callback_info: [side as u8; 32],
+                immediate_or_cancel,
+                ..default_params()
+            };
+        for match_limit in [1, 10] {
+            let mut test_context = TestContext::new(1000, 1000);
+            let (mut orderbook, mut event_queue) = test_context.get();
+            for limit_price in [10 << 32, 11 << 32] {
+                orderbook
+                    .new_order(
+                        params(100, limit_price, Side::Ask, false),
+                        &mut event_queue,
+                        &test_market_state(),
+                        0,
+                    )
+                    .unwrap();
+            }
+
+            // The bid crosses both levels, and would post its remainder without the flag
+            let summary = orderbook
+                .new_order(
+                    new_order::Params {
+                        match_limit,
+                        ..params(500, 12 << 32, Side::Bid, true)
+                    },
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+            let matched_orders = match_limit.min(2);
+            assert_eq!(summary.total_base_qty, 100 * matched_orders);
+            assert_eq!(summary.total_base_qty_posted, 0);
+            assert_eq!(summary.posted_order_id, None);
+            assert_eq!(summary.posted_price, None);
+            assert!(!summary.post_skipped_below_min);
+            assert_eq!(orderbook.bids.header.leaf_count, 0);
+            assert_eq!(orderbook.asks.header.leaf_count, 2 - matched_orders as u32);
+        }
+    }
+
+    #[test]
+    fn test_ob_fill_or_kill() {
+        let params = |max_base_qty, limit_price: u64, side, fill_or_kill| new_order::Params {
+            max_base_qty,
+            limit_price,
+            side,
+            callback_info: [side as u8; 32],
+            fill_or_kill,
+            ..default_params()
+        };
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        for limit_price in [10 << 32, 11 << 32] {
+            orderbook
+                .new_order(
+                    params(100, limit_price, Side::Ask, false),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            orderbook
+                .available_fill(Side::Bid, 11 << 32, 300, u64::MAX)
+                .unwrap(),
+            (200, 2100)
+        );
+        assert_eq!(
+            orderbook
+                .available_fill(Side::Bid, 10 << 32, 300, u64::MAX)
+                .unwrap(),
+            (100, 1000)
+        );
+        assert_eq!(
+            orderbook
+                .available_fill(Side::Bid, 11 << 32, 300, 1500)
+                .unwrap(),
+            (145, 1495)
+        );
+        assert_eq!(
+            orderbook
+                .available_fill(Side::Ask, 0, 300, u64::MAX)
+                .unwrap(),
+            (0, 0)
+        );
+
+        // The order would only partially fill, so nothing happens
+        assert!(matches!(
+            orderbook.new_order(
+                params(300, 12 << 32, Side::Bid, true),
+                &mut event_queue,
+                &test_market_state(),
+                0
+            ),
+            Err(AoError::FillOrKillUnfulfilled)
+        ));
+        assert!(matches!(
+            orderbook.new_order(
+                params(200, 10 << 32, Side::Bid, true),
+                &mut event_queue,
+                &test_market_state(),
+                0
+            ),
+            Err(AoError::FillOrKillUnfulfilled)
+        ));
+        assert_eq!(orderbook.asks.header.leaf_count, 2);
+        assert!(event_queue.is_empty());
+
+        // The quote budget runs out before the crossing liquidity does
+        let summary = orderbook
+            .new_order(
+                new_order::Params {
+                    max_quote_qty: 1500,
+                    ..params(300, 11 << 32, Side::Bid, true)
+                },
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 145);
+        assert_eq!(summary.total_quote_qty, 1495);
+        assert_eq!(summary.posted_order_id, None);
+
+        let summary = orderbook
+            .new_order(
+                params(55, 11 << 32, Side::Bid, true),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 55);
+        assert_eq!(summary.posted_order_id, None);
+        assert_eq!(orderbook.asks.header.leaf_count, 0);
+    }
+
+    #[test]
+    fn test_ob_fill_or_kill_unmatchable_makers() {
+        let maker = |user: u8, max_ts| new_order::Params {
+            max_base_qty: 50,
+            limit_price: 10 << 32,
+            side: Side::Ask,
+            callback_info: [user; 32],
+            max_ts,
+            ..default_params()
+        };
+        let taker = |max_base_qty| new_order::Params {
+            max_base_qty,
+            limit_price: 10 << 32,
+            side: Side::Bid,
+            callback_info: [1; 32],
+            fill_or_kill: true,
+            self_trade_behavior: SelfTradeBehavior::CancelProvide,
+            ..default_params()
+        };
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        // A self trading order, an order expiring at 5, then two regular orders
+        for params in [maker(1, 0), maker(2, 5), maker(2, 0), maker(2, 0)] {
+            orderbook
+                .new_order(params, &mut event_queue, &test_market_state(), 0)
+                .unwrap();
+        }
+        let events_len = event_queue.len();
+
+        // Neither the self trading order nor the expired one can be filled, so nothing happens
+        for (params, current_ts) in [
+            (taker(150), 10),
+            (taker(151), 0),
+            (
+                new_order::Params {
+                    match_limit: 3,
+                    ..taker(100)
+                },
+                10,
+            ),
+        ] {
+            assert!(matches!(
+                orderbook.new_order(params, &mut event_queue, &test_market_state(), current_ts),
+                Err(AoError::FillOrKillUnfulfilled)
+            ));
+            assert_eq!(orderbook.asks.header.leaf_count, 4);
+            assert_eq!(event_queue.len(), events_len);
+        }
+
+        // Aborting on self trades is reported as such
+        assert!(matches!(
+            orderbook.new_order(
+                new_order::Params {
+                    self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+                    ..taker(50)
+                },
+                &mut event_queue,
+                &test_market_state(),
+                0
+            ),
+            Err(AoError::WouldSelfTrade)
+        ));
+
+        let summary = orderbook
+            .new_order(taker(100), &mut event_queue, &test_market_state(), 10)
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 100);
+        assert_eq!(orderbook.asks.header.leaf_count, 0);
+    }
+
+    #[test]
+    fn test_ob_market_order() {
+        let params = |max_base_qty, limit_price: u64, side, order_type| new_order::Params {
+            max_base_qty,
+            limit_price,
+            side,
+            callback_info: [side as u8; 32],
+            order_type,
+            ..default_params()
+        };
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+
+        // There is nothing to match against
+        let summary = orderbook
+            .new_order(
+                params(u64::MAX, 0, Side::Bid, OrderType::Market),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 0);
+        assert_eq!(summary.posted_order_id, None);
+        assert!(orderbook.is_empty());
+
+        for limit_price in [10 << 32, 11 << 32] {
+            orderbook
+                .new_order(
+                    params(100, limit_price, Side::Ask, OrderType::Limit),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap();
+        }
+
+        // The market buy sweeps the first level and runs out of quote in the middle of the second one
+        let summary = orderbook
+            .new_order(
+                new_order::Params {
+                    max_quote_qty: 1500,
+                    ..params(u64::MAX, 0, Side::Bid, OrderType::Market)
+                },
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 145);
+        assert_eq!(summary.total_quote_qty, 1495);
+        assert_eq!(summary.total_base_qty_posted, 0);
+        assert_eq!(summary.posted_order_id, None);
+        assert_eq!(summary.posted_price, None);
+        assert_eq!(orderbook.best_level(Side::Ask), Some((11 << 32, 55)));
+        assert_eq!(orderbook.bids.header.leaf_count, 0);
+        // Two fills and the first maker's out event
+        assert_eq!(event_queue.len(), 3);
+
+        // No order id was generated for the market orders, so the next ask gets the third sequence number
+        let posted_order_id = orderbook
+            .new_order(
+                params(100, 20 << 32, Side::Ask, OrderType::Limit),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        assert_eq!(posted_order_id as u64, 2);
+    }
+
+    #[test]
+    fn test_ob_expired_maker() {
+        let params = |limit_price: u64, side, max_ts| new_order::Params {
+            max_base_qty: 100,
+            limit_price,
+            side,
+            callback_info: [limit_price as u8; 32],
+            max_ts,
+            ..default_params()
+        };
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let expiring_ask = orderbook
+            .new_order(
+                params(10 << 32, Side::Ask, 100),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        orderbook
+            .new_order(
+                params(11 << 32, Side::Ask, 0),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(
+            orderbook.asks.leaf_nodes[orderbook.find_bbo(Side::Ask).unwrap() as usize].max_ts,
+            100
+        );
+
+        // The ask expires after its max_ts, so it is still matched at that timestamp
+        let summary = orderbook
+            .new_order(
+                params(11 << 32, Side::Bid, 0),
+                &mut event_queue,
+                &test_market_state(),
+                100,
+            )
+            .unwrap();
+        assert_eq!(summary.total_quote_qty, 1000);
+        assert!(orderbook.asks.find_by_key(expiring_ask).is_none());
+        event_queue.pop_n(event_queue.len());
+
+        let expired_ask = orderbook
+            .new_order(
+                params(10 << 32, Side::Ask, 100),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        let summary = orderbook
+            .new_order(
+                params(11 << 32, Side::Bid, 0),
+                &mut event_queue,
+                &test_market_state(),
+                101,
+            )
+            .unwrap();
+        // The expired ask is skipped, and the bid is matched against the next one
+        assert_eq!(summary.total_base_qty, 100);
+        assert_eq!(summary.total_quote_qty, 1100);
+        assert!(orderbook.is_empty());
+        match event_queue.iter().next() {
+            Some(EventRef::Out(OutEventRef {
+                event,
+                callback_info,
+                taker_callback_info: None,
+            })) => {
+                assert_eq!(event.expired, 1);
+                assert_eq!(event.base_size, 100);
+                assert_eq!(event.order_id, {
+                    #[cfg(not(target_os = "solana"))]
+                    let o = [expired_ask as u64, (expired_ask >> 64) as u64];
+                    #[cfg(target_os = "solana")]
+                    let o = expired_ask;
+                    o
+                });
+                assert_eq!(callback_info, &[0; 32]);
+            }
+            _ => panic!("Expected an out event"),
+        }
+        assert!(matches!(event_queue.iter().nth(1), Some(EventRef::Fill(_))));
+    }
+
+    #[test]
+    fn test_ob_iceberg_order() {
+        let params = |max_base_qty, limit_price: u64, side, display_qty| new_order::Params {
+            max_base_qty,
+            limit_price,
+            side,
+            callback_info: [side as u8; 32],
+            display_qty,
+            ..default_params()
+        };
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let iceberg_id = orderbook
+            .new_order(
+                params(1000, 10 << 32, Side::Ask, 100),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        orderbook
+            .new_order(
+                params(50, 11 << 32, Side::Ask, 0),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(
+            orderbook.asks.cumulative_depth(true).collect::<Vec<_>>(),
+            vec![(10 << 32, 100, 100), (11 << 32, 50, 150)]
+        );
+
+        // The taker fills well beyond the displayed quantity
+        let summary = orderbook
+            .new_order(
+                params(500, 10 << 32, Side::Bid, 0),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 500);
+        assert_eq!(summary.posted_order_id, None);
+        // The iceberg keeps its order id, and still displays a full slice
+        assert_eq!(orderbook.asks.order_ids(true)[0], iceberg_id);
+        assert_eq!(
+            orderbook.asks.cumulative_depth(true).next(),
+            Some((10 << 32, 100, 100))
+        );
+
+        orderbook
+            .new_order(
+                params(450, 10 << 32, Side::Bid, 0),
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap();
+        assert_eq!(
+            orderbook.asks.cumulative_depth(true).next(),
+            Some((10 << 32, 50, 50))
+        );
+        assert_eq!(orderbook.best_level(Side::Ask), Some((10 << 32, 50)));
+    }
+
+    #[test]
+    fn test_ob_post_only_slide() {
+        let params = |limit_price: u64, side, post_only, post_only_slide| new_order::Params {
+            max_base_qty: 100,
+            limit_price,
+            side,
+            callback_info: [side as u8; 32],
+            post_only,
+            post_only_slide,
+            ..default_params()
+        };
+        let market_state = MarketState {
+            tick_size: 1 << 30,
+            ..test_market_state()
+        };
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        orderbook
+            .new_order(
+                params(10 << 32, Side::Ask, false, false),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+
+        // Without sliding, a crossing post only order isn't posted
+        let summary = orderbook
+            .new_order(
+                params(10 << 32, Side::Bid, true, false),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.posted_order_id, None);
+        assert_eq!(orderbook.bids.header.leaf_count, 0);
+
+        // The bid at the ask price slides one tick down
+        let slid_price = (10 << 32) - (1 << 30);
+        let summary = orderbook
+            .new_order(
+                params(10 << 32, Side::Bid, true, true),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 100);
+        assert_eq!(summary.total_base_qty_posted, 100);
+        assert_eq!(summary.posted_price, Some(slid_price));
+        assert_eq!(
+            LeafNode::price_from_key(summary.posted_order_id.unwrap()),
+            slid_price
+        );
+        assert_eq!(orderbook.best_level(Side::Bid), Some((slid_price, 100)));
+        assert_eq!(orderbook.asks.header.leaf_count, 1);
+        assert!(event_queue.is_empty());
+
+        // An ask can't slide above the market's maximum price
+        let capped_market_state = MarketState {
+            max_price: slid_price,
+            ..market_state
+        };
+        let summary = orderbook
+            .new_order(
+                params(slid_price - (1 << 30), Side::Ask, true, true),
+                &mut event_queue,
+                &capped_market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.posted_order_id, None);
+        assert_eq!(orderbook.asks.header.leaf_count, 1);
+    }
+
+    #[test]
+    fn test_ob_pro_rata_matching() {
+        let params = |max_base_qty, side| new_order::Params {
+            max_base_qty,
+            limit_price: 10 << 32,
+            side,
+            callback_info: [side as u8; 32],
+            ..default_params()
+        };
+        let market_state = MarketState {
+            flags: MarketFlag::ProRataMatching as u64,
+            ..test_market_state()
+        };
+        assert_eq!(test_market_state().matching_mode(), MatchingMode::PriceTime);
+        assert_eq!(market_state.matching_mode(), MatchingMode::ProRata);
+
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let maker_ids = [30, 50, 20]
+            .iter()
+            .map(|&qty| {
+                orderbook
+                    .new_order(params(qty, Side::Ask), &mut event_queue, &market_state, 0)
+                    .unwrap()
+                    .posted_order_id
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let summary = orderbook
+            .new_order(params(51, Side::Bid), &mut event_queue, &market_state, 0)
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 51);
+        assert_eq!(summary.posted_order_id, None);
+
+        // 51 is split as 15, 25 and 10, and the remaining unit goes to the largest order
+        let fills = event_queue
+            .iter()
+            .filter_map(|e| match e {
+                EventRef::Fill(FillEventRef { event, .. }) => {
+                    let [lo, hi] = event.maker_order_id;
+                    Some((((hi as u128) << 64) | lo as u128, event.base_size))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fills,
+            vec![(maker_ids[0], 15), (maker_ids[1], 26), (maker_ids[2], 10)]
+        );
+        let remaining = orderbook
+            .asks
+            .leaf_handles(true)
+            .map(|h| orderbook.asks.leaf_nodes[h as usize].base_quantity)
+            .collect::<Vec<_>>();
+        assert_eq!(remaining, vec![15, 24, 10]);
+
+        // A level which can be entirely filled is matched in price-time order
+        let summary = orderbook
+            .new_order(params(49, Side::Bid), &mut event_queue, &market_state, 0)
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 49);
+        assert!(orderbook.asks.root().is_none());
+    }
+
+    #[test]
+    fn test_ob_pro_rata_self_trade() {
+        let market_state = MarketState {
+            flags: MarketFlag::ProRataMatching as u64,
+            ..test_market_state()
+        };
+        let taker = |self_trade_behavior| new_order::Params {
+            max_base_qty: 40,
+            limit_price: 10 << 32,
+            side: Side::Bid,
+            callback_info: [3; 32],
+            self_trade_behavior,
+            ..default_params()
+        };
+        for self_trade_behavior in [
+            SelfTradeBehavior::AbortTransaction,
+            SelfTradeBehavior::CancelProvide,
+            SelfTradeBehavior::CancelBoth,
+        ] {
+            let mut test_context = TestContext::new(1000, 1000);
+            let (mut orderbook, mut event_queue) = test_context.get();
+            let maker_ids = [(30, 1), (50, 2), (20, 3)]
+                .iter()
+                .map(|&(max_base_qty, c)| {
+                    let params = new_order::Params {
+                        max_base_qty,
+                        limit_price: 10 << 32,
+                        side: Side::Ask,
+                        callback_info: [c; 32],
+                        ..default_params()
+                    };
+                    orderbook
+                        .new_order(params, &mut event_queue, &market_state, 0)
+                        .unwrap()
+                        .posted_order_id
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+
+            let r = orderbook.new_order(
+                taker(self_trade_behavior),
+                &mut event_queue,
+                &market_state,
+                0,
+            );
+            if self_trade_behavior == SelfTradeBehavior::AbortTransaction {
+                assert!(matches!(r, Err(AoError::WouldSelfTrade)));
+                continue;
+            }
+            let summary = r.unwrap();
+            let events = event_queue
+                .iter()
+                .filter_map(|e| match e {
+                    EventRef::Fill(FillEventRef { event, .. }) => {
+                        let [lo, hi] = event.maker_order_id;
+                        Some((((hi as u128) << 64) | lo as u128, event.base_size, None))
+                    }
+                    EventRef::Out(OutEventRef { event, .. }) => {
+                        let [lo, hi] = event.order_id;
+                        let order_id = ((hi as u128) << 64) | lo as u128;
+                        Some((order_id, event.base_size, Some(event.reason)))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            // The self trading order is cancelled before the level is shared between the others
+            let self_trade_out = (maker_ids[2], 20, Some(OutReason::SelfTrade as u8));
+            if self_trade_behavior == SelfTradeBehavior::CancelBoth {
+                assert_eq!(summary.total_base_qty, 0);
+                assert_eq!(summary.posted_order_id, None);
+                assert_eq!(events, vec![self_trade_out]);
+            } else {
+                assert_eq!(summary.total_base_qty, 40);
+                assert_eq!(
+                    events,
+                    vec![
+                        self_trade_out,
+                        (maker_ids[0], 15, None),
+                        (maker_ids[1], 25, None)
+                    ]
+                );
+            }
+            assert!(orderbook.asks.find_by_key(maker_ids[2]).is_none());
+        }
+    }
+
+    #[test]
+    fn test_ob_pro_rata_zero_quote_allocation() {
+        let market_state = MarketState {
+            flags: MarketFlag::ProRataMatching as u64,
+            min_base_order_size: 1,
+            ..test_market_state()
+        };
+        let params = |max_base_qty, side| new_order::Params {
+            max_base_qty,
+            limit_price: 1 << 31,
+            side,
+            callback_info: [side as u8; 32],
+            ..default_params()
+        };
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let maker_ids = [100, 100, 2]
+            .iter()
+            .map(|&qty| {
+                orderbook
+                    .new_order(params(qty, Side::Bid), &mut event_queue, &market_state, 0)
+                    .unwrap()
+                    .posted_order_id
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // The smallest order's share of 1 would be worth no quote at all, so the level is matched in price-time order
+        let summary = orderbook
+            .new_order(params(103, Side::Ask), &mut event_queue, &market_state, 0)
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 103);
+        let fills = event_queue
+            .iter()
+            .filter_map(|e| match e {
+                EventRef::Fill(FillEventRef { event, .. }) => {
+                    let [lo, hi] = event.maker_order_id;
+                    Some((((hi as u128) << 64) | lo as u128, event.base_size))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(fills, vec![(maker_ids[0], 100), (maker_ids[1], 3)]);
+    }
+
+    #[test]
+    fn test_ob_modify_order() {
+        let params = |max_base_qty, side| new_order::Params {
+            max_base_qty,
+            limit_price: 10 << 32,
+            side,
+            callback_info: [side as u8; 32],
+            ..default_params()
+        };
+        let market_state = test_market_state();
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let order_ids = (0..2)
+            .map(|_| {
+                orderbook
+                    .new_order(params(100, Side::Ask), &mut event_queue, &market_state, 0)
+                    .unwrap()
+                    .posted_order_id
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let modify = |new_base_qty| modify_order::Params {
+            order_id: order_ids[0],
+            new_base_qty,
+        };
+        assert!(matches!(
+            orderbook.modify_order(modify(101), &mut event_queue, &market_state),
+            Err(AoError::OrderSizeIncrease)
+        ));
+        assert!(matches!(
+            orderbook.modify_order(modify(9), &mut event_queue, &market_state),
+            Err(AoError::InvalidBaseQuantity)
+        ));
+        // Orders can't be reduced to nothing, even without a minimum order size
+        let no_min_size = MarketState {
+            min_base_order_size: 0,
+            ..market_state
+        };
+        assert!(matches!(
+            orderbook.modify_order(modify(0), &mut event_queue, &no_min_size),
+            Err(AoError::InvalidBaseQuantity)
+        ));
+        assert!(event_queue.is_empty());
+
+        // The displayed quantity is clamped to the new quantity
+        let h = orderbook.asks.find_by_key(order_ids[0]).unwrap();
+        orderbook.asks.leaf_nodes[h as usize].display_qty = 50;
+        let summary = orderbook
+            .modify_order(modify(40), &mut event_queue, &market_state)
+            .unwrap();
+        assert_eq!(orderbook.asks.leaf_nodes[h as usize].display_qty, 40);
+        assert_eq!(summary.total_base_qty, 60);
+        assert_eq!(summary.total_quote_qty, 600);
+        match event_queue.iter().next() {
+            Some(EventRef::Out(OutEventRef { event, .. })) => {
+                assert_eq!(event.delete, 0);
+                assert_eq!(event.base_size, 60);
+            }
+            _ => panic!("Expected an out event"),
+        }
+
+        // The order keeps its id and its place in the queue
+        assert_eq!(orderbook.asks.order_ids(true), order_ids);
+        let h = orderbook.asks.find_by_key(order_ids[0]).unwrap();
+        assert_eq!(orderbook.asks.leaf_nodes[h as usize].base_quantity, 40);
+        orderbook
+            .new_order(params(40, Side::Bid), &mut event_queue, &market_state, 0)
+            .unwrap();
+        assert_eq!(orderbook.asks.order_ids(true), vec![order_ids[1]]);
+
+        // The order is left untouched when its out event can't be pushed
+        let mut test_context = TestContext::new(10, 1);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let order_id = orderbook
+            .new_order(params(100, Side::Ask), &mut event_queue, &market_state, 0)
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        event_queue
+            .push_back(OutEvent::zeroed(), Some(&[0; 32]), None)
+            .unwrap();
+        let modify = modify_order::Params {
+            order_id,
+            new_base_qty: 40,
+        };
+        assert!(matches!(
+            orderbook.modify_order(modify, &mut event_queue, &market_state),
+            Err(AoError::EventQueueFull)
+        ));
+        let h = orderbook.asks.find_by_key(order_id).unwrap();
+        assert_eq!(orderbook.asks.leaf_nodes[h as usize].base_quantity, 100);
+    }
+
+    #[test]
+    fn test_ob_all_or_nothing() {
+        let params = |max_base_qty, limit_price: u64, side, all_or_nothing| new_order::Params {
+            max_base_qty,
+            limit_price,
+            side,
+            callback_info: [side as u8; 32],
+            all_or_nothing,
+            ..default_params()
+        };
+        let market_state = test_market_state();
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let aon_order_id = orderbook
+            .new_order(
+                params(100, 10 << 32, Side::Ask, true),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        let order_id = orderbook
+            .new_order(
+                params(30, 11 << 32, Side::Ask, false),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        let h = orderbook.asks.find_by_key(aon_order_id).unwrap();
+        assert!(orderbook.asks.leaf_nodes[h as usize].has_flag(LeafFlag::AllOrNothing));
+        assert_eq!(
+            orderbook
+                .available_fill(Side::Bid, 12 << 32, 50, u64::MAX)
+                .unwrap(),
+            (30, 330)
+        );
+
+        // The small taker skips the all-or-nothing order and fills the order behind it, without posting its remainder
+        let summary = orderbook
+            .new_order(
+                params(50, 12 << 32, Side::Bid, false),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 30);
+        assert_eq!(summary.total_quote_qty, 330);
+        assert_eq!(summary.posted_order_id, None);
+        assert!(orderbook.asks.find_by_key(order_id).is_none());
+        match event_queue.iter().next() {
+            Some(EventRef::Fill(FillEventRef { event, .. })) => {
+                assert_eq!(
+                    event.maker_order_id,
+                    [order_id as u64, (order_id >> 64) as u64]
+                );
+            }
+            _ => panic!("Expected a fill event"),
+        }
+        let h = orderbook.asks.find_by_key(aon_order_id).unwrap();
+        assert_eq!(orderbook.asks.leaf_nodes[h as usize].base_quantity, 100);
+
+        // A taker large enough fills it entirely
+        let summary = orderbook
+            .new_order(
+                params(100, 10 << 32, Side::Bid, false),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 100);
+        assert!(orderbook.asks.root().is_none());
+    }
+
+    #[test]
+    fn test_ob_min_fill_base_qty() {
+        let params = |max_base_qty, side, min_fill_base_qty| new_order::Params {
+            max_base_qty,
+            limit_price: 10 << 32,
+            side,
+            callback_info: [side as u8; 32],
+            min_fill_base_qty,
+            ..default_params()
+        };
+        let market_state = test_market_state();
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        orderbook
+            .new_order(params(40, Side::Ask, 0), &mut event_queue, &market_state, 0)
+            .unwrap();
+        let events_len = event_queue.len();
+
+        // Not enough liquidity crosses the order, so nothing happens
+        assert!(matches!(
+            orderbook.new_order(
+                params(100, Side::Bid, 50),
+                &mut event_queue,
+                &market_state,
+                0
+            ),
+            Err(AoError::MinFillNotMet)
+        ));
+        assert_eq!(event_queue.len(), events_len);
+        assert_eq!(orderbook.best_level(Side::Ask), Some((10 << 32, 40)));
+
+        // The minimum is met, and the remainder is posted
+        let summary = orderbook
+            .new_order(
+                params(100, Side::Bid, 40),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 100);
+        assert_eq!(summary.total_base_qty_posted, 60);
+
+        // Orders which would self trade don't count towards the minimum
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        for (max_base_qty, callback_info) in [(50, [Side::Bid as u8; 32]), (20, [2; 32])] {
+            let params = new_order::Params {
+                callback_info,
+                ..params(max_base_qty, Side::Ask, 0)
+            };
+            orderbook
+                .new_order(params, &mut event_queue, &market_state, 0)
+                .unwrap();
+        }
+        let taker = |min_fill_base_qty| new_order::Params {
+            self_trade_behavior: SelfTradeBehavior::CancelProvide,
+            ..params(100, Side::Bid, min_fill_base_qty)
+        };
+        assert!(matches!(
+            orderbook.new_order(taker(30), &mut event_queue, &market_state, 0),
+            Err(AoError::MinFillNotMet)
+        ));
+        assert!(event_queue.is_empty());
+        let summary = orderbook
+            .new_order(taker(20), &mut event_queue, &market_state, 0)
+            .unwrap();
+        assert_eq!(summary.total_base_qty - summary.total_base_qty_posted, 20);
+    }
+
+    #[test]
+    fn test_ob_self_trade_cancel_both() {
+        let params = |side, user: u8, self_trade_behavior| new_order::Params {
+            max_base_qty: 50,
+            limit_price: 10 << 32,
+            side,
+            callback_info: [user; 32],
+            self_trade_behavior,
+            ..default_params()
+        };
+        let market_state = test_market_state();
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let own_order_id = orderbook
+            .new_order(
+                params(Side::Ask, 1, SelfTradeBehavior::DecrementTake),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        let other_order_id = orderbook
+            .new_order(
+                params(Side::Ask, 2, SelfTradeBehavior::DecrementTake),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+
+        // Both the resting order and the taker's quantity are cancelled, so the other user's order isn't matched
+        let summary = orderbook
+            .new_order(
+                params(Side::Bid, 1, SelfTradeBehavior::CancelBoth),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 0);
+        assert_eq!(summary.posted_order_id, None);
+        assert_eq!(orderbook.asks.order_ids(true), vec![other_order_id]);
+        assert!(orderbook.bids.root().is_none());
+        assert_eq!(event_queue.len(), 1);
+        match event_queue.iter().next() {
+            Some(EventRef::Out(OutEventRef { event, .. })) => {
+                assert_eq!(
+                    event.order_id,
+                    [own_order_id as u64, (own_order_id >> 64) as u64]
+                );
+                assert_eq!(event.self_trade, 1);
+                assert_eq!(event.delete, 1);
+                assert_eq!(event.base_size, 50);
+            }
+            _ => panic!("Expected an out event"),
+        }
+    }
+
+    #[test]
+    fn test_ob_matched_order_count() {
+        let params = |max_base_qty, limit_price: u64, side| new_order::Params {
+            max_base_qty,
+            limit_price,
+            side,
+            callback_info: [side as u8; 32],
+            ..default_params()
+        };
+        let market_state = test_market_state();
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        for price in [10, 10, 11].iter() {
+            let summary = orderbook
+                .new_order(
+                    params(20, price << 32, Side::Ask),
+                    &mut event_queue,
+                    &market_state,
+                    0,
+                )
+                .unwrap();
+            assert_eq!(summary.matched_order_count, 0);
+        }
+
+        let summary = orderbook
+            .new_order(
+                params(50, 11 << 32, Side::Bid),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 50);
+        assert_eq!(summary.matched_order_count, 3);
+        assert_eq!(
+            summary.matched_order_count as usize,
+            event_queue
+                .iter()
+                .filter(|e| matches!(e, EventRef::Fill(_)))
+                .count()
+        );
+        // The serialized size is the largest when all options are set
+        let summary = OrderSummary {
+            posted_order_id: Some(0),
+            posted_price: Some(0),
+            ..summary
+        };
+        assert_eq!(
+            summary.try_to_vec().unwrap().len(),
+            ORDER_SUMMARY_SIZE as usize
+        );
+    }
+
+    #[test]
+    fn test_ob_average_fill_price() {
+        let params = |max_base_qty, limit_price: u64, side| new_order::Params {
+            max_base_qty,
+            limit_price,
+            side,
+            callback_info: [side as u8; 32],
+            ..default_params()
+        };
+        let market_state = test_market_state();
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+
+        // Nothing is matched when posting to an empty orderbook
+        let summary = orderbook
+            .new_order(
+                params(10, 10 << 32, Side::Ask),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty_posted, 10);
+        assert_eq!(summary.average_fill_price(), None);
+        assert_eq!(OrderSummary::default().average_fill_price(), None);
+
+        orderbook
+            .new_order(
+                params(30, 12 << 32, Side::Ask),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+
+        // 10 at 10 and 30 at 12, then 20 are posted at 13
+        let summary = orderbook
+            .new_order(
+                params(60, 13 << 32, Side::Bid),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 60);
+        assert_eq!(summary.total_base_qty_posted, 20);
+        assert_eq!(summary.total_quote_qty, 100 + 360 + 260);
+        assert_eq!(summary.average_fill_price(), Some(23 << 31));
+    }
+
+    #[test]
+    fn test_ob_post_with_max_quote_qty() {
+        let market_state = test_market_state();
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+
+        // Selling 100M tokens (with 6 decimals) at a price of 0.2 posts the whole order
+        let summary = orderbook
+            .new_order(
+                new_order::Params {
+                    max_base_qty: 100_000_000_000_000,
+                    limit_price: 858993400,
+                    side: Side::Ask,
+                    callback_info: [0; 32],
+                    ..default_params()
+                },
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty_posted, 100_000_000_000_000);
+        assert_eq!(
+            orderbook.best_level(Side::Ask),
+            Some((858993400, 100_000_000_000_000))
+        );
+    }
+
+    #[test]
+    fn test_ob_cancel_orders_out_events() {
+        let params = |limit_price: u64, side| new_order::Params {
+            max_base_qty: 10,
+            limit_price: limit_price << 32,
+            side,
+            callback_info: [limit_price as u8; 32],
+            post_only: true,
+            ..default_params()
+        };
+        let mut test_context = TestContext::new(1000, 2);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let order_ids = [(9, Side::Bid), (10, Side::Bid), (11, Side::Ask)]
+            .iter()
+            .map(|&(limit_price, side)| {
+                orderbook
+                    .new_order(
+                        params(limit_price, side),
+                        &mut event_queue,
+                        &test_market_state(),
+                        0,
+                    )
+                    .unwrap()
+                    .posted_order_id
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // The queue can't hold all of the out events, so no order is removed
+        assert!(matches!(
+            orderbook.cancel_orders(&order_ids, &mut event_queue),
+            Err(AoError::EventQueueFull)
+        ));
+        assert!(event_queue.is_empty());
+        assert_eq!(orderbook.bids.order_ids(true).len(), 2);
+
+        orderbook
+            .cancel_orders(&order_ids[1..], &mut event_queue)
+            .unwrap();
+        let out_events = event_queue
+            .iter()
+            .map(|e| match e {
+                EventRef::Out(OutEventRef {
+                    event,
+                    callback_info,
+                    ..
+                }) => {
+                    assert_eq!(event.delete, 1);
+                    assert_eq!(event.reason, OutReason::Cancel as u8);
+                    assert_eq!(event.base_size, 10);
+                    let [lo, hi] = event.order_id;
+                    (
+                        ((hi as u128) << 64) | lo as u128,
+                        event.side,
+                        callback_info[0],
+                    )
+                }
+                _ => panic!("Expected an out event"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            out_events,
+            vec![
+                (order_ids[1], Side::Bid as u8, 10),
+                (order_ids[2], Side::Ask as u8, 11)
+            ]
+        );
+        assert_eq!(orderbook.bids.order_ids(true), vec![order_ids[0]]);
+        assert!(orderbook.asks.root().is_none());
+    }
+
+    #[test]
+    fn test_ob_booted_out_reason() {
+        let mut test_context = TestContext::new(2, 10);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let params = |limit_price| new_order::Params {
+            max_base_qty: 1_000,
+            limit_price,
+            side: Side::Bid,
+            callback_info: [1; 32],
+            ..default_params()
+        };
+        let mut post = |limit_price| {
+            orderbook
+                .new_order(
+                    params(limit_price),
+                    &mut event_queue,
+                    &test_market_state(),
+                    0,
+                )
+                .unwrap()
+                .posted_order_id
+                .unwrap()
+        };
+        let worst_bid = post(9 << 32);
+        post(10 << 32);
+        // The book is full, so a better priced bid boots the worst one out
+        post(11 << 32);
+
+        assert_eq!(event_queue.len(), 1);
+        match event_queue.peek_at(0).unwrap() {
+            EventRef::Out(OutEventRef { event, .. }) => {
+                assert_eq!(event.reason, OutReason::Booted as u8);
+                assert_eq!(event.delete, 1);
+                assert_eq!(event.base_size, 1_000);
+                let [lo, hi] = event.order_id;
+                assert_eq!(((hi as u128) << 64) | lo as u128, worst_bid);
+            }
+            _ => panic!("Expected an out event"),
+        }
+    }
+
+    #[test]
+    fn test_ob_take_order_sizes_and_level_cleared() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let market_state = MarketState {
+            flags: MarketFlag::EmitLevelCleared as u64,
+            ..test_market_state()
+        };
+        let (alice, bob) = ([1; 32], [2; 32]);
+        let mut post = |limit_price, side| {
+            orderbook
+                .new_order(
+                    new_order::Params {
+                        max_base_qty: 100,
+                        limit_price,
+                        side,
+                        callback_info: alice,
+                        post_only: true,
+                        ..default_params()
+                    },
+                    &mut event_queue,
+                    &market_state,
+                    0,
+                )
+                .unwrap()
+                .posted_order_id
+                .unwrap()
+        };
+        let tiny_bid = post(1, Side::Bid);
+        let asks = [
+            post(10 << 32, Side::Ask),
+            post(12 << 32, Side::Ask),
+            post(12 << 32, Side::Ask),
+        ];
+        let take = |order_id, max_base_qty, limit_price| take_order::Params {
+            order_id,
+            max_base_qty,
+            limit_price,
+            callback_info: bob,
+        };
+
+        // Empty fills are rejected before any event is pushed
+        let r = orderbook.take_order(
+            take(asks[1], 0, 12 << 32),
+            &mut event_queue,
+            &market_state,
+            0,
+        );
+        assert!(matches!(r, Err(AoError::InvalidBaseQuantity)));
+        let r = orderbook.take_order(take(tiny_bid, 10, 0), &mut event_queue, &market_state, 0);
+        assert!(matches!(r, Err(AoError::InvalidBaseQuantity)));
+        assert!(event_queue.is_empty());
+
+        // Another order is left at the price, so the level isn't cleared
+        orderbook
+            .take_order(
+                take(asks[2], 100, 12 << 32),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(event_queue.len(), 2);
+
+        // The last order at the price is taken, even though it isn't the best ask
+        orderbook
+            .take_order(
+                take(asks[1], 100, 12 << 32),
+                &mut event_queue,
+                &market_state,
+                0,
+            )
+            .unwrap();
+        assert_eq!(event_queue.len(), 5);
+        match event_queue.peek_at(4).unwrap() {
+            EventRef::LevelCleared(e) => {
+                assert_eq!(e.event.side, Side::Ask as u8);
+                assert_eq!(e.event.price, 12 << 32);
+                assert_eq!(e.event.base_size, 100);
+            }
+            _ => panic!("Expected a level cleared event"),
+        }
+        assert_eq!(orderbook.asks.order_ids(true), vec![asks[0]]);
+    }
+
+    #[test]
+    fn test_ob_take_order_expired_maker() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let (alice, bob) = ([1; 32], [2; 32]);
+        let order_id = orderbook
+            .new_order(
+                new_order::Params {
+                    max_base_qty: 100,
+                    limit_price: 10 << 32,
+                    side: Side::Ask,
+                    callback_info: alice,
+                    max_ts: 5,
+                    ..default_params()
+                },
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+
+        let summary = orderbook
+            .take_order(
+                take_order::Params {
+                    order_id,
+                    max_base_qty: 100,
+                    limit_price: 10 << 32,
+                    callback_info: bob,
+                },
+                &mut event_queue,
+                &test_market_state(),
+                6,
+            )
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 0);
+        assert_eq!(summary.matched_order_count, 0);
+        assert_eq!(event_queue.len(), 1);
+        match event_queue.peek_at(0).unwrap() {
+            EventRef::Out(OutEventRef {
+                event,
+                callback_info,
+                ..
+            }) => {
+                assert_eq!(event.reason, OutReason::Expired as u8);
+                assert_eq!(event.expired, 1);
+                assert_eq!(event.base_size, 100);
+                assert_eq!(callback_info, &alice);
+            }
+            _ => panic!("Expected an out event"),
+        }
+        assert!(orderbook.asks.root().is_none());
+    }
+
+    #[test]
+    fn test_ob_take_order_all_or_nothing() {
+        let mut test_context = TestContext::new(1000, 1000);
+        let (mut orderbook, mut event_queue) = test_context.get();
+        let (alice, bob) = ([1; 32], [2; 32]);
+        let order_id = orderbook
+            .new_order(
+                new_order::Params {
+                    max_base_qty: 100,
+                    limit_price: 10 << 32,
+                    side: Side::Ask,
+                    callback_info: alice,
+                    all_or_nothing: true,
+                    ..default_params()
+                },
+                &mut event_queue,
+                &test_market_state(),
+                0,
+            )
+            .unwrap()
+            .posted_order_id
+            .unwrap();
+        let take = |max_base_qty| take_order::Params {
+            order_id,
+            max_base_qty,
+            limit_price: 10 << 32,
+            callback_info: bob,
+        };
+
+        let r = orderbook.take_order(take(99), &mut event_queue, &test_market_state(), 0);
+        assert!(matches!(r, Err(AoError::PartialAllOrNothingFill)));
+        assert!(event_queue.is_empty());
+        let h = orderbook.asks.find_by_key(order_id).unwrap();
+        assert_eq!(orderbook.asks.leaf_nodes[h as usize].base_quantity, 100);
+
+        // The order can still be taken entirely
+        let summary = orderbook
+            .take_order(take(150), &mut event_queue, &test_market_state(), 0)
+            .unwrap();
+        assert_eq!(summary.total_base_qty, 100);
+        assert!(orderbook.asks.root().is_none());
+    }
 }
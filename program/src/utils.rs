@@ -1,5 +1,15 @@
 use crate::{error::AoError, state::Side};
 
+#[cfg(not(feature = "entrypoint"))]
+use crate::state::{
+    critbit::{NodeHandle, Slab},
+    event_queue::EventQueue,
+    market_state::MarketState,
+};
+#[cfg(not(feature = "entrypoint"))]
+use bonfida_utils::fp_math::fp32_mul_ceil;
+#[cfg(not(feature = "entrypoint"))]
+use solana_program::rent::Rent;
 use solana_program::{account_info::AccountInfo, pubkey::Pubkey};
 
 use crate::state::orderbook::{CallbackInfo, OrderBookState};
@@ -51,7 +61,12 @@ where
 }
 
 /// Rounds a given price the nearest tick size according to the rules of the AOB
+///
+/// A tick size of 0 means that prices aren't constrained, the price is then returned as is.
 pub fn round_price(tick_size: u64, limit_price: u64, side: Side) -> u64 {
+    if tick_size == 0 {
+        return limit_price;
+    }
     match side {
         // Round down
         Side::Bid => tick_size * (limit_price / tick_size),
@@ -60,6 +75,188 @@ pub fn round_price(tick_size: u64, limit_price: u64, side: Side) -> u64 {
     }
 }
 
+/// The account sizes in bytes required to create a market with a given configuration
+#[cfg(not(feature = "entrypoint"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RentBreakdown {
+    #[allow(missing_docs)]
+    pub market: usize,
+    #[allow(missing_docs)]
+    pub bids: usize,
+    #[allow(missing_docs)]
+    pub asks: usize,
+    #[allow(missing_docs)]
+    pub event_queue: usize,
+    /// The sum of all account sizes
+    pub total_bytes: usize,
+}
+
+#[cfg(not(feature = "entrypoint"))]
+impl RentBreakdown {
+    /// Returns the total amount of lamports required to make all of the market's accounts rent exempt
+    pub fn minimum_balance(&self, rent: &Rent) -> u64 {
+        [self.market, self.bids, self.asks, self.event_queue]
+            .iter()
+            .map(|&size| rent.minimum_balance(size))
+            .sum()
+    }
+}
+
+/// This util is used to compute the sizes of all the accounts required by a market,
+/// given the desired orderbook and event queue capacities and the byte size of the callback information object.
+#[cfg(not(feature = "entrypoint"))]
+pub fn market_rent_breakdown(
+    order_capacity: usize,
+    event_capacity: usize,
+    callback_info_size: usize,
+) -> RentBreakdown {
+    let market = 8 + MarketState::LEN;
+    // Each slab stores one callback info per order, and the event queue two per event
+    let slab =
+        Slab::<()>::compute_allocation_size(order_capacity) + order_capacity * callback_info_size;
+    let event_queue = EventQueue::<()>::compute_allocation_size(event_capacity)
+        + 2 * event_capacity * callback_info_size;
+    RentBreakdown {
+        market,
+        bids: slab,
+        asks: slab,
+        event_queue,
+        total_bytes: market + 2 * slab + event_queue,
+    }
+}
+
+/// This util is used to compute how much could be traded between the bids of an orderbook and the asks of another one.
+///
+/// Returns the matchable `(base_qty, quote_qty)`, where the quote quantity is valued at the ask prices, rounded up.
+/// Both books are left untouched.
+#[cfg(not(feature = "entrypoint"))]
+pub fn crossing_volume<C>(bids: &OrderBookState<C>, asks: &OrderBookState<C>) -> (u64, u64) {
+    let leaf = |slab: &'_ Slab<C>, h: NodeHandle| {
+        let leaf = &slab.leaf_nodes[h as usize];
+        (leaf.price(), leaf.base_quantity)
+    };
+    let mut bid_levels = bids.bids.leaf_handles(false).map(|h| leaf(&bids.bids, h));
+    let mut ask_levels = asks.asks.leaf_handles(true).map(|h| leaf(&asks.asks, h));
+
+    let mut base_qty = 0u64;
+    let mut quote_qty = 0u64;
+    let mut current_bid = bid_levels.next();
+    let mut current_ask = ask_levels.next();
+    while let (Some((bid_price, bid_qty)), Some((ask_price, ask_qty))) = (current_bid, current_ask)
+    {
+        if bid_price < ask_price {
+            break;
+        }
+        let trade_qty = bid_qty.min(ask_qty);
+        base_qty = base_qty.saturating_add(trade_qty);
+        quote_qty =
+            quote_qty.saturating_add(fp32_mul_ceil(trade_qty, ask_price).unwrap_or(u64::MAX));
+        current_bid = if bid_qty == trade_qty {
+            bid_levels.next()
+        } else {
+            Some((bid_price, bid_qty - trade_qty))
+        };
+        current_ask = if ask_qty == trade_qty {
+            ask_levels.next()
+        } else {
+            Some((ask_price, ask_qty - trade_qty))
+        };
+    }
+    (base_qty, quote_qty)
+}
+
+/// Describes a change to a single order between two orderbook snapshots
+#[cfg(not(feature = "entrypoint"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderDiff {
+    /// The order only exists in the current snapshot
+    Added {
+        #[allow(missing_docs)]
+        order_id: u128,
+        #[allow(missing_docs)]
+        base_quantity: u64,
+    },
+    /// The order only exists in the previous snapshot, it was either matched or cancelled
+    Removed {
+        #[allow(missing_docs)]
+        order_id: u128,
+        #[allow(missing_docs)]
+        base_quantity: u64,
+    },
+    /// The order exists in both snapshots with different base quantities, it was partially matched
+    Changed {
+        #[allow(missing_docs)]
+        order_id: u128,
+        #[allow(missing_docs)]
+        previous_base_quantity: u64,
+        #[allow(missing_docs)]
+        base_quantity: u64,
+    },
+}
+
+/// This util is used to compute the differences between two orderbook snapshots, given as `(order_id, base_quantity)` pairs.
+///
+/// The snapshots don't need to be sorted. The diffs are yielded in ascending order id order, orders which are
+/// unchanged between the two snapshots are skipped.
+#[cfg(not(feature = "entrypoint"))]
+pub fn diff_snapshots(
+    previous: &[(u128, u64)],
+    current: &[(u128, u64)],
+) -> impl Iterator<Item = OrderDiff> {
+    let mut previous = previous.to_vec();
+    let mut current = current.to_vec();
+    previous.sort_unstable();
+    current.sort_unstable();
+    let mut previous = previous.into_iter().peekable();
+    let mut current = current.into_iter().peekable();
+    std::iter::from_fn(move || loop {
+        let diff = match (previous.peek().copied(), current.peek().copied()) {
+            (None, None) => return None,
+            (Some((order_id, base_quantity)), None) => {
+                previous.next();
+                OrderDiff::Removed {
+                    order_id,
+                    base_quantity,
+                }
+            }
+            (None, Some((order_id, base_quantity))) => {
+                current.next();
+                OrderDiff::Added {
+                    order_id,
+                    base_quantity,
+                }
+            }
+            (Some((previous_id, previous_base_quantity)), Some((current_id, base_quantity))) => {
+                if previous_id < current_id {
+                    previous.next();
+                    OrderDiff::Removed {
+                        order_id: previous_id,
+                        base_quantity: previous_base_quantity,
+                    }
+                } else if current_id < previous_id {
+                    current.next();
+                    OrderDiff::Added {
+                        order_id: current_id,
+                        base_quantity,
+                    }
+                } else {
+                    previous.next();
+                    current.next();
+                    if previous_base_quantity == base_quantity {
+                        continue;
+                    }
+                    OrderDiff::Changed {
+                        order_id: current_id,
+                        previous_base_quantity,
+                        base_quantity,
+                    }
+                }
+            }
+        };
+        return Some(diff);
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,5 +270,115 @@ mod tests {
 
         let rounded_price_ask = round_price(tick_size, price, Side::Ask);
         assert_eq!(rounded_price_ask, (5.5 * 2.0f64.powi(32)) as u64);
+
+        assert_eq!(round_price(0, price, Side::Bid), price);
+        assert_eq!(round_price(0, price, Side::Ask), price);
+    }
+
+    #[test]
+    #[cfg(not(feature = "entrypoint"))]
+    fn test_market_rent_breakdown() {
+        let breakdown = market_rent_breakdown(100, 50, 32);
+        assert_eq!(breakdown.market, 8 + MarketState::LEN);
+        assert_eq!(
+            breakdown.bids,
+            Slab::<[u8; 32]>::compute_allocation_size(100)
+        );
+        assert_eq!(breakdown.asks, breakdown.bids);
+        assert_eq!(
+            breakdown.event_queue,
+            EventQueue::<[u8; 32]>::compute_allocation_size(50)
+        );
+        assert_eq!(
+            breakdown.total_bytes,
+            breakdown.market + breakdown.bids + breakdown.asks + breakdown.event_queue
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "entrypoint"))]
+    fn test_diff_snapshots() {
+        let previous = [(3, 10), (1, 10), (2, 10), (5, 10)];
+        let current = [(1, 10), (2, 4), (4, 7), (5, 10), (6, 1)];
+        let diffs = diff_snapshots(&previous, &current).collect::<Vec<_>>();
+        assert_eq!(
+            diffs,
+            vec![
+                OrderDiff::Changed {
+                    order_id: 2,
+                    previous_base_quantity: 10,
+                    base_quantity: 4
+                },
+                OrderDiff::Removed {
+                    order_id: 3,
+                    base_quantity: 10
+                },
+                OrderDiff::Added {
+                    order_id: 4,
+                    base_quantity: 7
+                },
+                OrderDiff::Added {
+                    order_id: 6,
+                    base_quantity: 1
+                },
+            ]
+        );
+        assert_eq!(diff_snapshots(&current, &current).count(), 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "entrypoint"))]
+    fn test_crossing_volume() {
+        use crate::state::{critbit::LeafNode, AccountTag};
+        use bytemuck::Zeroable;
+
+        let size = Slab::<[u8; 32]>::compute_allocation_size(10);
+        let mut buffers = vec![vec![0u8; size]; 4];
+        let mut slabs = buffers
+            .iter_mut()
+            .zip([
+                AccountTag::Bids,
+                AccountTag::Asks,
+                AccountTag::Bids,
+                AccountTag::Asks,
+            ])
+            .map(|(b, tag)| Slab::<[u8; 32]>::from_buffer_zeroed(b, tag).unwrap())
+            .collect::<Vec<_>>();
+        let insert = |slab: &mut Slab<[u8; 32]>, seq: u64, price: u64, base_quantity: u64| {
+            let key = ((price as u128) << 64) | seq as u128;
+            let leaf = LeafNode {
+                #[cfg(target_os = "solana")]
+                key,
+                #[cfg(not(target_os = "solana"))]
+                key: [key as u64, (key >> 64) as u64],
+                base_quantity,
+                ..Zeroable::zeroed()
+            };
+            slab.insert_leaf(&leaf, false).unwrap();
+        };
+        // This book's bids
+        insert(&mut slabs[0], 0, 12 << 32, 10);
+        insert(&mut slabs[0], 1, 11 << 32, 10);
+        insert(&mut slabs[0], 2, 9 << 32, 10);
+        // The other book's asks
+        insert(&mut slabs[3], 3, 10 << 32, 15);
+        insert(&mut slabs[3], 4, 11 << 32, 20);
+        insert(&mut slabs[3], 5, 13 << 32, 20);
+
+        let other_bids_asks = slabs.split_off(2);
+        let mut other_bids_asks = other_bids_asks.into_iter();
+        let mut slabs = slabs.into_iter();
+        let book = OrderBookState {
+            bids: slabs.next().unwrap(),
+            asks: slabs.next().unwrap(),
+        };
+        let other_book = OrderBookState {
+            bids: other_bids_asks.next().unwrap(),
+            asks: other_bids_asks.next().unwrap(),
+        };
+
+        // 15 at 10, then 5 at 11
+        assert_eq!(crossing_volume(&book, &other_book), (20, 15 * 10 + 5 * 11));
+        assert_eq!(crossing_volume(&other_book, &book), (0, 0));
     }
 }
@@ -2,10 +2,12 @@ use asset_agnostic_orderbook::instruction::create_market;
 use asset_agnostic_orderbook::state::critbit::Slab;
 use asset_agnostic_orderbook::state::event_queue::EventQueue;
 use asset_agnostic_orderbook::state::market_state::MarketState;
+use asset_agnostic_orderbook::state::AccountTag;
 use solana_program::instruction::Instruction;
 use solana_program::pubkey::Pubkey;
 use solana_program::system_instruction::create_account;
-use solana_program_test::{BanksClientError, ProgramTestContext};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::account::Account;
 use solana_sdk::signature::Signer;
 use solana_sdk::{signature::Keypair, transaction::Transaction};
 
@@ -15,6 +17,95 @@ pub async fn create_market_and_accounts(
     prg_test_ctx: &mut ProgramTestContext,
     register_account: Pubkey,
     agnostic_orderbook_program_id: Pubkey,
+) -> Pubkey {
+    create_market_and_accounts_with_params(
+        prg_test_ctx,
+        register_account,
+        agnostic_orderbook_program_id,
+        default_market_params(),
+    )
+    .await
+}
+
+/// The configuration of the markets created by [`create_market_and_accounts`]
+pub fn default_market_params() -> create_market::Params {
+    create_market::Params {
+        min_base_order_size: 10,
+        tick_size: 1,
+        flags: 0,
+        max_price: None,
+        max_post_depth: 0,
+    }
+}
+
+/// A market created in a fresh program test environment by [`setup_market`]
+pub struct TestMarket {
+    pub prg_test_ctx: ProgramTestContext,
+    pub market_account: Pubkey,
+    pub market_state: MarketState,
+    /// The register accounts, the first of which the market was created with
+    pub register_accounts: Vec<Pubkey>,
+}
+
+/// Starts a program test environment holding register accounts of the given sizes, and creates a market with a custom
+/// configuration in it.
+pub async fn setup_market(register_sizes: &[usize], params: create_market::Params) -> TestMarket {
+    let mut program_test = ProgramTest::new(
+        "agnostic_orderbook",
+        asset_agnostic_orderbook::ID,
+        processor!(asset_agnostic_orderbook::entrypoint::process_instruction),
+    );
+
+    let register_accounts = register_sizes
+        .iter()
+        .map(|&size| {
+            let register_account = Pubkey::new_unique();
+            program_test.add_account(
+                register_account,
+                Account {
+                    lamports: 1_000_000,
+                    data: vec![0; size],
+                    owner: asset_agnostic_orderbook::ID,
+                    ..Account::default()
+                },
+            );
+            register_account
+        })
+        .collect::<Vec<_>>();
+
+    let mut prg_test_ctx = program_test.start_with_context().await;
+    let market_account = create_market_and_accounts_with_params(
+        &mut prg_test_ctx,
+        register_accounts[0],
+        asset_agnostic_orderbook::ID,
+        params,
+    )
+    .await;
+
+    let mut market_state_data = prg_test_ctx
+        .banks_client
+        .get_account(market_account)
+        .await
+        .unwrap()
+        .unwrap();
+    let market_state =
+        *MarketState::from_buffer(&mut market_state_data.data, AccountTag::Market).unwrap();
+
+    TestMarket {
+        prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    }
+}
+
+/// Creates the accounts needed for the AAOB market testing with a custom market
+/// configuration and returns the address of the market.
+pub async fn create_market_and_accounts_with_params(
+    prg_test_ctx: &mut ProgramTestContext,
+    register_account: Pubkey,
+    agnostic_orderbook_program_id: Pubkey,
+    params: create_market::Params,
 ) -> Pubkey {
     let rent = prg_test_ctx.banks_client.get_rent().await.unwrap();
 
@@ -97,10 +188,7 @@ pub async fn create_market_and_accounts(
             asks: &asks_account.pubkey(),
         },
         register_account,
-        create_market::Params {
-            min_base_order_size: 10,
-            tick_size: 1,
-        },
+        params,
     );
     sign_send_instructions(prg_test_ctx, vec![create_market_instruction], vec![])
         .await
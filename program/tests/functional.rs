@@ -1,21 +1,34 @@
+use asset_agnostic_orderbook::error::AoError;
 use asset_agnostic_orderbook::instruction::{
-    cancel_order, close_market, consume_events, new_order,
+    cancel_order, close_market, consume_events, consume_events::ConsumeEventsSummary,
+    consume_then_new_order, consume_then_new_order::ConsumeThenNewOrderSummary, create_market,
+    mass_cancel_orders, migrate_market, new_order, new_order_batch, replace_order,
+    set_reference_price,
 };
-use asset_agnostic_orderbook::state::{market_state::MarketState, OrderSummary};
-use asset_agnostic_orderbook::state::{AccountTag, SelfTradeBehavior, Side};
+use asset_agnostic_orderbook::state::{
+    event_queue::{EventQueue, EventTag},
+    market_state::{LegacyMarketState, MarketState},
+    OrderSummary, ORDER_SUMMARY_SIZE,
+};
+use asset_agnostic_orderbook::state::{AccountTag, OrderType, SelfTradeBehavior, Side, TakerMode};
 use bonfida_utils::BorshSize;
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::instruction::InstructionError;
 use solana_program::program_option::COption;
 use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
 use solana_program::system_instruction::{create_account, transfer};
 use solana_program::system_program;
-use solana_program_test::{processor, ProgramTest};
-use solana_sdk::account::Account;
+use solana_program_test::{processor, BanksClientError, ProgramTest};
+use solana_sdk::account::{Account, AccountSharedData};
 use solana_sdk::signature::Keypair;
 use solana_sdk::signature::Signer;
+use solana_sdk::transaction::TransactionError;
 pub mod common;
-use crate::common::utils::{create_market_and_accounts, sign_send_instructions};
+use crate::common::utils::{
+    create_market_and_accounts, default_market_params, setup_market, sign_send_instructions,
+    TestMarket,
+};
 
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct C([u8; 32]);
@@ -26,6 +39,35 @@ impl BorshSize for C {
     }
 }
 
+/// The parameters of a plain limit order, meant to be completed with struct update syntax
+fn default_new_order_params() -> new_order::Params<C> {
+    new_order::Params {
+        max_base_qty: 0,
+        max_quote_qty: u64::MAX,
+        limit_price: 0,
+        side: Side::Bid,
+        match_limit: 10,
+        callback_info: C([0; 32]),
+        post_only: false,
+        post_allowed: true,
+        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        round_to_tick: false,
+        require_top_of_book: false,
+        post_full_base: false,
+        peg_offset: None,
+        cross_on_equal: true,
+        taker_mode: TakerMode::Standard,
+        immediate_or_cancel: false,
+        fill_or_kill: false,
+        order_type: OrderType::Limit,
+        max_ts: 0,
+        display_qty: 0,
+        post_only_slide: false,
+        all_or_nothing: false,
+        min_fill_base_qty: 0,
+    }
+}
+
 #[tokio::test]
 async fn test_agnostic_orderbook() {
     // Create program and test environment
@@ -56,7 +98,7 @@ async fn test_agnostic_orderbook() {
         register_account,
         Account {
             lamports: 1_000_000,
-            data: vec![0; 42],
+            data: vec![0; ORDER_SUMMARY_SIZE as usize + 1],
             owner: asset_agnostic_orderbook::ID,
             ..Account::default()
         },
@@ -129,10 +171,9 @@ async fn test_agnostic_orderbook() {
             limit_price: 1000 << 32,
             side: Side::Bid,
             callback_info: C(Pubkey::new_unique().to_bytes()),
-            post_only: false,
-            post_allowed: true,
             self_trade_behavior: SelfTradeBehavior::CancelProvide,
             match_limit: 3,
+            ..default_new_order_params()
         },
     );
 
@@ -169,10 +210,9 @@ async fn test_agnostic_orderbook() {
             limit_price: 1000 << 32,
             side: Side::Ask,
             callback_info: C(Pubkey::new_unique().to_bytes()),
-            post_only: false,
-            post_allowed: true,
             self_trade_behavior: SelfTradeBehavior::CancelProvide,
             match_limit: 3,
+            ..default_new_order_params()
         },
     );
     sign_send_instructions(&mut prg_test_ctx, vec![new_order_instruction], vec![])
@@ -197,6 +237,8 @@ async fn test_agnostic_orderbook() {
         .data as &[u8];
     let order_summary: Option<OrderSummary> = Option::deserialize(&mut register_acc).unwrap();
     println!("Parsed order summary {:#?}", order_summary);
+    // The ask was matched against the resting bid before being posted
+    assert_eq!(order_summary.as_ref().unwrap().matched_order_count, 1);
 
     // Cancel order
     let cancel_order_instruction = cancel_order(
@@ -209,6 +251,7 @@ async fn test_agnostic_orderbook() {
         register_account,
         cancel_order::Params {
             order_id: order_summary.unwrap().posted_order_id.unwrap(),
+            expected_base_qty: None,
         },
     );
     sign_send_instructions(&mut prg_test_ctx, vec![cancel_order_instruction], vec![])
@@ -241,12 +284,31 @@ async fn test_agnostic_orderbook() {
         register_account,
         consume_events::Params {
             number_of_entries_to_consume: 10,
+            callback_id_filter: None,
         },
     );
     sign_send_instructions(&mut prg_test_ctx, vec![consume_events_instruction], vec![])
         .await
         .unwrap();
 
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let consume_events_summary = ConsumeEventsSummary::deserialize(&mut register_acc).unwrap();
+    assert_eq!(
+        consume_events_summary,
+        ConsumeEventsSummary {
+            number_of_fills_consumed: 1,
+            number_of_outs_consumed: 1,
+            total_base_qty: 100,
+            total_quote_qty: 100_000,
+        }
+    );
+
     // Close Market
     let close_market_instruction = close_market(
         close_market::Accounts {
@@ -263,3 +325,915 @@ async fn test_agnostic_orderbook() {
         .await
         .unwrap();
 }
+
+#[tokio::test]
+async fn test_new_order_batch() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(
+        &[new_order_batch::BATCH_SUMMARY_SIZE],
+        default_market_params(),
+    )
+    .await;
+    let register_account = register_accounts[0];
+
+    let order = |limit_price: u64, side: Side| new_order::Params {
+        max_base_qty: 1000,
+        limit_price: limit_price << 32,
+        side,
+        callback_info: C(Pubkey::new_unique().to_bytes()),
+        self_trade_behavior: SelfTradeBehavior::CancelProvide,
+        match_limit: 3,
+        ..default_new_order_params()
+    };
+    let accounts = || new_order_batch::Accounts {
+        market: &market_account,
+        event_queue: &market_state.event_queue,
+        bids: &market_state.bids,
+        asks: &market_state.asks,
+    };
+
+    // A two-sided ladder, the last ask crossing the first bid
+    let new_order_batch_instruction = new_order_batch(
+        accounts(),
+        register_account,
+        new_order_batch::Params {
+            orders: vec![
+                order(100, Side::Bid),
+                order(99, Side::Bid),
+                order(101, Side::Ask),
+                order(100, Side::Ask),
+            ],
+        },
+    );
+    sign_send_instructions(&mut prg_test_ctx, vec![new_order_batch_instruction], vec![])
+        .await
+        .unwrap();
+
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let order_summaries = Vec::<OrderSummary>::deserialize(&mut register_acc).unwrap();
+    assert_eq!(order_summaries.len(), 4);
+    assert!(order_summaries[..3]
+        .iter()
+        .all(|o| o.posted_order_id.is_some()));
+    assert!(order_summaries[3].posted_order_id.is_none());
+    assert_eq!(order_summaries[3].total_base_qty, 1000);
+
+    // Batches larger than the maximum size are rejected
+    let new_order_batch_instruction = new_order_batch(
+        accounts(),
+        register_account,
+        new_order_batch::Params {
+            orders: (0..new_order_batch::MAX_BATCH_SIZE as u64 + 1)
+                .map(|i| order(50 - i, Side::Bid))
+                .collect(),
+        },
+    );
+    assert!(
+        sign_send_instructions(&mut prg_test_ctx, vec![new_order_batch_instruction], vec![])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_zero_tick_size() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(
+        &[ORDER_SUMMARY_SIZE as usize + 1],
+        create_market::Params {
+            min_base_order_size: 10,
+            tick_size: 0,
+            flags: 0,
+            max_price: None,
+            max_post_depth: 0,
+        },
+    )
+    .await;
+    let register_account = register_accounts[0];
+
+    // Any price can be posted when the tick size is 0
+    let limit_price = (1000 << 32) + 12345;
+    let new_order_instruction = new_order(
+        new_order::Accounts {
+            market: &market_account,
+            event_queue: &market_state.event_queue,
+            bids: &market_state.bids,
+            asks: &market_state.asks,
+        },
+        register_account,
+        new_order::Params {
+            max_base_qty: 100000,
+            limit_price,
+            side: Side::Bid,
+            callback_info: C(Pubkey::new_unique().to_bytes()),
+            self_trade_behavior: SelfTradeBehavior::CancelProvide,
+            match_limit: 3,
+            ..default_new_order_params()
+        },
+    );
+    sign_send_instructions(&mut prg_test_ctx, vec![new_order_instruction], vec![])
+        .await
+        .unwrap();
+
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let order_summary = Option::<OrderSummary>::deserialize(&mut register_acc)
+        .unwrap()
+        .unwrap();
+    assert!(order_summary.posted_order_id.is_some());
+    assert_eq!(order_summary.posted_price, Some(limit_price));
+}
+
+#[tokio::test]
+async fn test_consume_then_new_order() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(
+        &[new_order_batch::BATCH_SUMMARY_SIZE
+            .max(consume_then_new_order::CONSUME_THEN_NEW_ORDER_SUMMARY_SIZE)],
+        default_market_params(),
+    )
+    .await;
+    let register_account = register_accounts[0];
+
+    let order = |limit_price: u64, side: Side| new_order::Params {
+        max_base_qty: 1000,
+        limit_price: limit_price << 32,
+        side,
+        callback_info: C(Pubkey::new_unique().to_bytes()),
+        self_trade_behavior: SelfTradeBehavior::CancelProvide,
+        match_limit: 3,
+        ..default_new_order_params()
+    };
+
+    // A full match leaves a fill and an out event in the queue
+    let new_order_batch_instruction = new_order_batch(
+        new_order_batch::Accounts {
+            market: &market_account,
+            event_queue: &market_state.event_queue,
+            bids: &market_state.bids,
+            asks: &market_state.asks,
+        },
+        register_account,
+        new_order_batch::Params {
+            orders: vec![order(100, Side::Bid), order(100, Side::Ask)],
+        },
+    );
+    sign_send_instructions(&mut prg_test_ctx, vec![new_order_batch_instruction], vec![])
+        .await
+        .unwrap();
+
+    let consume_then_new_order_instruction = consume_then_new_order(
+        consume_then_new_order::Accounts {
+            market: &market_account,
+            event_queue: &market_state.event_queue,
+            bids: &market_state.bids,
+            asks: &market_state.asks,
+        },
+        register_account,
+        consume_then_new_order::Params {
+            number_of_entries_to_consume: 10,
+            order: order(99, Side::Bid),
+        },
+    );
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![consume_then_new_order_instruction],
+        vec![],
+    )
+    .await
+    .unwrap();
+
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let summary = ConsumeThenNewOrderSummary::deserialize(&mut register_acc).unwrap();
+    assert_eq!(summary.consume_events_summary.number_of_fills_consumed, 1);
+    assert_eq!(summary.consume_events_summary.number_of_outs_consumed, 1);
+    assert_eq!(summary.consume_events_summary.total_base_qty, 1000);
+    assert!(summary.order_summary.posted_order_id.is_some());
+    assert_eq!(summary.order_summary.total_base_qty_posted, 1000);
+}
+
+#[tokio::test]
+async fn test_register_too_small() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(
+        &[
+            ORDER_SUMMARY_SIZE as usize + 1,
+            ORDER_SUMMARY_SIZE as usize - 1,
+        ],
+        default_market_params(),
+    )
+    .await;
+    let (register_account, small_register_account) = (register_accounts[0], register_accounts[1]);
+
+    let new_order_instruction = |register_account| {
+        new_order(
+            new_order::Accounts {
+                market: &market_account,
+                event_queue: &market_state.event_queue,
+                bids: &market_state.bids,
+                asks: &market_state.asks,
+            },
+            register_account,
+            new_order::Params {
+                max_base_qty: 1000,
+                limit_price: 1000 << 32,
+                side: Side::Bid,
+                callback_info: C(Pubkey::new_unique().to_bytes()),
+                self_trade_behavior: SelfTradeBehavior::CancelProvide,
+                match_limit: 3,
+                ..default_new_order_params()
+            },
+        )
+    };
+
+    // A posted order's summary doesn't fit in the small register
+    let err = sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![new_order_instruction(small_register_account)],
+        vec![],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(code)
+        )) if code == AoError::RegisterTooSmall as u32
+    ));
+
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![new_order_instruction(register_account)],
+        vec![],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_cancel_order_expected_base_qty() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(&[ORDER_SUMMARY_SIZE as usize + 1], default_market_params()).await;
+    let register_account = register_accounts[0];
+
+    let new_order_instruction = |max_base_qty, side| {
+        new_order(
+            new_order::Accounts {
+                market: &market_account,
+                event_queue: &market_state.event_queue,
+                bids: &market_state.bids,
+                asks: &market_state.asks,
+            },
+            register_account,
+            new_order::Params {
+                max_base_qty,
+                limit_price: 1000 << 32,
+                side,
+                callback_info: C(Pubkey::new_unique().to_bytes()),
+                self_trade_behavior: SelfTradeBehavior::CancelProvide,
+                match_limit: 3,
+                ..default_new_order_params()
+            },
+        )
+    };
+    let cancel_order_instruction = |order_id, expected_base_qty| {
+        cancel_order(
+            cancel_order::Accounts {
+                market: &market_account,
+                event_queue: &market_state.event_queue,
+                bids: &market_state.bids,
+                asks: &market_state.asks,
+            },
+            register_account,
+            cancel_order::Params {
+                order_id,
+                expected_base_qty,
+            },
+        )
+    };
+
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![new_order_instruction(1000, Side::Bid)],
+        vec![],
+    )
+    .await
+    .unwrap();
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let order_id = Option::<OrderSummary>::deserialize(&mut register_acc)
+        .unwrap()
+        .unwrap()
+        .posted_order_id
+        .unwrap();
+
+    // An intervening fill changes the resting order's quantity
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![new_order_instruction(400, Side::Ask)],
+        vec![],
+    )
+    .await
+    .unwrap();
+
+    let err = sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![cancel_order_instruction(order_id, Some(1000))],
+        vec![],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(code)
+        )) if code == AoError::OrderChanged as u32
+    ));
+
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![cancel_order_instruction(order_id, Some(600))],
+        vec![],
+    )
+    .await
+    .unwrap();
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let order_summary = Option::<OrderSummary>::deserialize(&mut register_acc)
+        .unwrap()
+        .unwrap();
+    assert_eq!(order_summary.total_base_qty, 600);
+}
+
+#[tokio::test]
+async fn test_pegged_order() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(&[ORDER_SUMMARY_SIZE as usize + 1], default_market_params()).await;
+    let register_account = register_accounts[0];
+
+    let pegged_order_instruction = || {
+        new_order(
+            new_order::Accounts {
+                market: &market_account,
+                event_queue: &market_state.event_queue,
+                bids: &market_state.bids,
+                asks: &market_state.asks,
+            },
+            register_account,
+            new_order::Params {
+                max_base_qty: 1000,
+                limit_price: 0,
+                side: Side::Bid,
+                callback_info: C(Pubkey::new_unique().to_bytes()),
+                self_trade_behavior: SelfTradeBehavior::CancelProvide,
+                peg_offset: Some(-(1 << 32)),
+                match_limit: 3,
+                ..default_new_order_params()
+            },
+        )
+    };
+
+    // The reference price was never set
+    let err = sign_send_instructions(&mut prg_test_ctx, vec![pegged_order_instruction()], vec![])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(code)
+        )) if code == AoError::StaleReferencePrice as u32
+    ));
+
+    let set_reference_price_instruction = set_reference_price(
+        set_reference_price::Accounts {
+            market: &market_account,
+        },
+        register_account,
+        set_reference_price::Params {
+            ref_price: 1000 << 32,
+            max_age: 3600,
+        },
+    );
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![set_reference_price_instruction],
+        vec![],
+    )
+    .await
+    .unwrap();
+
+    sign_send_instructions(&mut prg_test_ctx, vec![pegged_order_instruction()], vec![])
+        .await
+        .unwrap();
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let order_summary = Option::<OrderSummary>::deserialize(&mut register_acc)
+        .unwrap()
+        .unwrap();
+    assert!(order_summary.posted_order_id.is_some());
+    assert_eq!(order_summary.posted_price, Some(999 << 32));
+}
+
+#[tokio::test]
+async fn test_replace_order() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(&[ORDER_SUMMARY_SIZE as usize + 1], default_market_params()).await;
+    let register_account = register_accounts[0];
+
+    let order_params = |max_base_qty, limit_price| new_order::Params {
+        max_base_qty,
+        limit_price,
+        side: Side::Bid,
+        callback_info: C(Pubkey::new_unique().to_bytes()),
+        self_trade_behavior: SelfTradeBehavior::CancelProvide,
+        match_limit: 3,
+        ..default_new_order_params()
+    };
+    let replace_order_instruction = |order_id, order| {
+        replace_order(
+            replace_order::Accounts {
+                market: &market_account,
+                event_queue: &market_state.event_queue,
+                bids: &market_state.bids,
+                asks: &market_state.asks,
+            },
+            register_account,
+            replace_order::Params { order_id, order },
+        )
+    };
+    let new_order_instruction = new_order(
+        new_order::Accounts {
+            market: &market_account,
+            event_queue: &market_state.event_queue,
+            bids: &market_state.bids,
+            asks: &market_state.asks,
+        },
+        register_account,
+        order_params(1000, 1000 << 32),
+    );
+
+    sign_send_instructions(&mut prg_test_ctx, vec![new_order_instruction], vec![])
+        .await
+        .unwrap();
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let order_id = Option::<OrderSummary>::deserialize(&mut register_acc)
+        .unwrap()
+        .unwrap()
+        .posted_order_id
+        .unwrap();
+
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![replace_order_instruction(
+            order_id,
+            order_params(500, 900 << 32),
+        )],
+        vec![],
+    )
+    .await
+    .unwrap();
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let order_summary = Option::<OrderSummary>::deserialize(&mut register_acc)
+        .unwrap()
+        .unwrap();
+    assert_eq!(order_summary.total_base_qty_posted, 500);
+    assert_eq!(order_summary.posted_price, Some(900 << 32));
+    let new_order_id = order_summary.posted_order_id.unwrap();
+    assert_ne!(new_order_id, order_id);
+
+    // The original order is gone, so replacing it again fails without posting anything
+    let err = sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![replace_order_instruction(
+            order_id,
+            order_params(200, 800 << 32),
+        )],
+        vec![],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(code)
+        )) if code == AoError::OrderNotFound as u32
+    ));
+
+    let cancel_order_instruction = cancel_order(
+        cancel_order::Accounts {
+            market: &market_account,
+            event_queue: &market_state.event_queue,
+            bids: &market_state.bids,
+            asks: &market_state.asks,
+        },
+        register_account,
+        cancel_order::Params {
+            order_id: new_order_id,
+            expected_base_qty: Some(500),
+        },
+    );
+    sign_send_instructions(&mut prg_test_ctx, vec![cancel_order_instruction], vec![])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_mass_cancel_orders() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(&[ORDER_SUMMARY_SIZE as usize + 1], default_market_params()).await;
+    let register_account = register_accounts[0];
+
+    let new_order_instruction = |limit_price| {
+        new_order(
+            new_order::Accounts {
+                market: &market_account,
+                event_queue: &market_state.event_queue,
+                bids: &market_state.bids,
+                asks: &market_state.asks,
+            },
+            register_account,
+            new_order::Params {
+                max_base_qty: 1000,
+                limit_price,
+                side: Side::Bid,
+                callback_info: C(Pubkey::new_unique().to_bytes()),
+                self_trade_behavior: SelfTradeBehavior::CancelProvide,
+                match_limit: 3,
+                ..default_new_order_params()
+            },
+        )
+    };
+
+    let mut order_ids = vec![];
+    for limit_price in [1000 << 32, 999 << 32, 998 << 32].iter() {
+        sign_send_instructions(
+            &mut prg_test_ctx,
+            vec![new_order_instruction(*limit_price)],
+            vec![],
+        )
+        .await
+        .unwrap();
+        let mut register_acc = &prg_test_ctx
+            .banks_client
+            .get_account(register_account)
+            .await
+            .unwrap()
+            .unwrap()
+            .data as &[u8];
+        order_ids.push(
+            Option::<OrderSummary>::deserialize(&mut register_acc)
+                .unwrap()
+                .unwrap()
+                .posted_order_id
+                .unwrap(),
+        );
+    }
+
+    let mass_cancel_orders_instruction = mass_cancel_orders(
+        mass_cancel_orders::Accounts {
+            market: &market_account,
+            event_queue: &market_state.event_queue,
+            bids: &market_state.bids,
+            asks: &market_state.asks,
+        },
+        register_account,
+        mass_cancel_orders::Params {
+            order_ids: order_ids.clone(),
+            report_outcomes: false,
+        },
+    );
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![mass_cancel_orders_instruction],
+        vec![],
+    )
+    .await
+    .unwrap();
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    let order_summary = Option::<OrderSummary>::deserialize(&mut register_acc)
+        .unwrap()
+        .unwrap();
+    assert_eq!(order_summary.total_base_qty, 3000);
+
+    // The orders were removed from the book
+    for order_id in order_ids {
+        let cancel_order_instruction = cancel_order(
+            cancel_order::Accounts {
+                market: &market_account,
+                event_queue: &market_state.event_queue,
+                bids: &market_state.bids,
+                asks: &market_state.asks,
+            },
+            register_account,
+            cancel_order::Params {
+                order_id,
+                expected_base_qty: None,
+            },
+        );
+        let err = sign_send_instructions(&mut prg_test_ctx, vec![cancel_order_instruction], vec![])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BanksClientError::TransactionError(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(code)
+            )) if code == AoError::OrderNotFound as u32
+        ));
+    }
+
+    // The market is still open
+    let market_data = prg_test_ctx
+        .banks_client
+        .get_account(market_account)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        market_data.data[0..8],
+        (AccountTag::Market as u64).to_le_bytes()
+    );
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![new_order_instruction(1000 << 32)],
+        vec![],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_migrate_market() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(&[ORDER_SUMMARY_SIZE as usize + 1], default_market_params()).await;
+    let register_account = register_accounts[0];
+
+    // Shrink the market account back to the legacy layout
+    let mut account = prg_test_ctx
+        .banks_client
+        .get_account(market_account)
+        .await
+        .unwrap()
+        .unwrap();
+    account.data.truncate(8 + LegacyMarketState::LEN);
+    prg_test_ctx.set_account(&market_account, &AccountSharedData::from(account));
+
+    let new_order_instruction = || {
+        new_order(
+            new_order::Accounts {
+                market: &market_account,
+                event_queue: &market_state.event_queue,
+                bids: &market_state.bids,
+                asks: &market_state.asks,
+            },
+            register_account,
+            new_order::Params {
+                max_base_qty: 1000,
+                limit_price: 1000 << 32,
+                ..default_new_order_params()
+            },
+        )
+    };
+    let err = sign_send_instructions(&mut prg_test_ctx, vec![new_order_instruction()], vec![])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(code)
+        )) if code == AoError::MarketStateVersionMismatch as u32
+    ));
+
+    // The account already holds enough lamports for its new size
+    let migrate_market_instruction = || {
+        migrate_market(
+            migrate_market::Accounts {
+                market: &market_account,
+            },
+            register_account,
+            migrate_market::Params {},
+        )
+    };
+    sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![migrate_market_instruction()],
+        vec![],
+    )
+    .await
+    .unwrap();
+    let mut account = prg_test_ctx
+        .banks_client
+        .get_account(market_account)
+        .await
+        .unwrap()
+        .unwrap();
+    let migrated = MarketState::from_buffer(&mut account.data, AccountTag::Market).unwrap();
+    assert_eq!(migrated.event_queue, market_state.event_queue);
+    assert_eq!(migrated.tick_size, market_state.tick_size);
+    assert_eq!(migrated.max_price, u64::MAX);
+    assert_eq!(migrated.flags, 0);
+
+    // The same transaction was rejected before the migration
+    prg_test_ctx.get_new_latest_blockhash().await.unwrap();
+    sign_send_instructions(&mut prg_test_ctx, vec![new_order_instruction()], vec![])
+        .await
+        .unwrap();
+
+    // Markets can only be migrated once
+    prg_test_ctx.get_new_latest_blockhash().await.unwrap();
+    let err = sign_send_instructions(
+        &mut prg_test_ctx,
+        vec![migrate_market_instruction()],
+        vec![],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            0,
+            InstructionError::InvalidAccountData
+        ))
+    ));
+}
+
+#[tokio::test]
+async fn test_consume_legacy_event_queue() {
+    let TestMarket {
+        mut prg_test_ctx,
+        market_account,
+        market_state,
+        register_accounts,
+    } = setup_market(&[ORDER_SUMMARY_SIZE as usize + 1], default_market_params()).await;
+    let register_account = register_accounts[0];
+
+    // Lay out the market account and the event queue with their legacy layouts
+    let mut account = prg_test_ctx
+        .banks_client
+        .get_account(market_account)
+        .await
+        .unwrap()
+        .unwrap();
+    account.data.truncate(8 + LegacyMarketState::LEN);
+    prg_test_ctx.set_account(&market_account, &AccountSharedData::from(account));
+
+    let mut account = prg_test_ctx
+        .banks_client
+        .get_account(market_state.event_queue)
+        .await
+        .unwrap()
+        .unwrap();
+    account.data[8..].fill(0);
+    // The legacy header holds the head, count and sequence number, followed by 40-byte events
+    for (i, n) in [0u64, 2, 2].iter().enumerate() {
+        account.data[8 + 8 * i..16 + 8 * i].copy_from_slice(&n.to_le_bytes());
+    }
+    for (slot, (tag, quote_size, base_size)) in [
+        (EventTag::Fill as u8, 5000u64, 10u64),
+        (EventTag::Out as u8, 0, 20),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let event = &mut account.data[32 + slot * 40..72 + slot * 40];
+        event[0] = *tag;
+        event[8..16].copy_from_slice(&quote_size.to_le_bytes());
+        event[32..40].copy_from_slice(&base_size.to_le_bytes());
+    }
+    prg_test_ctx.set_account(&market_state.event_queue, &AccountSharedData::from(account));
+
+    // The legacy event queue is drained before the market account is migrated
+    let consume_events_instruction = consume_events(
+        consume_events::Accounts {
+            market: &market_account,
+            event_queue: &market_state.event_queue,
+        },
+        register_account,
+        consume_events::Params {
+            number_of_entries_to_consume: 10,
+            callback_id_filter: None,
+        },
+    );
+    sign_send_instructions(&mut prg_test_ctx, vec![consume_events_instruction], vec![])
+        .await
+        .unwrap();
+
+    let mut register_acc = &prg_test_ctx
+        .banks_client
+        .get_account(register_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data as &[u8];
+    assert_eq!(
+        ConsumeEventsSummary::deserialize(&mut register_acc).unwrap(),
+        ConsumeEventsSummary {
+            number_of_fills_consumed: 1,
+            number_of_outs_consumed: 1,
+            total_base_qty: 10,
+            total_quote_qty: 5000,
+        }
+    );
+
+    // The emptied queue was laid out again with the current version
+    let mut account = prg_test_ctx
+        .banks_client
+        .get_account(market_state.event_queue)
+        .await
+        .unwrap()
+        .unwrap();
+    let event_queue =
+        EventQueue::<[u8; 32]>::from_buffer(&mut account.data, AccountTag::EventQueue).unwrap();
+    assert!(event_queue.is_empty());
+}